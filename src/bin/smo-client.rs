@@ -0,0 +1,16 @@
+use dotenv::dotenv;
+use smo::client::Client;
+
+#[tokio::main]
+async fn main() {
+    dotenv().ok();
+    env_logger::builder()
+        .filter_level(log::LevelFilter::Debug)
+        .init();
+
+    let mut client = Client::from_env()
+        .await
+        .expect("Failed initialize client.");
+
+    client.run().await;
+}
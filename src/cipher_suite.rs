@@ -0,0 +1,74 @@
+use std::str::FromStr;
+use ring::aead::{Algorithm, LessSafeKey, UnboundKey, AES_256_GCM, CHACHA20_POLY1305};
+use crate::protocol::Capabilities;
+
+/// AEAD protecting a session's control and data packets.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum CipherSuite {
+    #[default]
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl CipherSuite {
+    /// ChaCha20-Poly1305 when the CPU lacks AES instructions, AES-256-GCM otherwise.
+    pub fn preferred() -> Self {
+        #[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+        let aes = std::arch::is_x86_feature_detected!("aes");
+
+        #[cfg(target_arch = "aarch64")]
+        let aes = std::arch::is_aarch64_feature_detected!("aes");
+
+        #[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+        let aes = false;
+
+        if aes {
+            Self::Aes256Gcm
+        } else {
+            Self::ChaCha20Poly1305
+        }
+    }
+
+    /// Suite selected by the negotiated capabilities.
+    pub fn negotiated(capabilities: Capabilities) -> Self {
+        if capabilities.contains(Capabilities::CHACHA20_POLY1305) {
+            Self::ChaCha20Poly1305
+        } else {
+            Self::Aes256Gcm
+        }
+    }
+
+    /// Capability a client offers in `Sign` to ask for this suite.
+    pub fn capability(self) -> Capabilities {
+        match self {
+            Self::Aes256Gcm => Capabilities::empty(),
+            Self::ChaCha20Poly1305 => Capabilities::CHACHA20_POLY1305,
+        }
+    }
+
+    pub fn algorithm(self) -> &'static Algorithm {
+        match self {
+            Self::Aes256Gcm => &AES_256_GCM,
+            Self::ChaCha20Poly1305 => &CHACHA20_POLY1305,
+        }
+    }
+
+    /// Session key from 32 bytes of key material.
+    pub fn less_safe_key(self, key_material: &[u8]) -> Option<LessSafeKey> {
+        UnboundKey::new(self.algorithm(), key_material)
+            .map(LessSafeKey::new)
+            .ok()
+    }
+}
+
+impl FromStr for CipherSuite {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "aes-256-gcm" => Ok(Self::Aes256Gcm),
+            "chacha20-poly1305" => Ok(Self::ChaCha20Poly1305),
+            _ => Err(()),
+        }
+    }
+}
@@ -0,0 +1,669 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+use ring::aead::LessSafeKey;
+use ring::agreement;
+use ring::agreement::{EphemeralPrivateKey, UnparsedPublicKey};
+use ring::rand::SystemRandom;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::Interval;
+use crate::cipher_suite::CipherSuite;
+use crate::data_frame::{frame, next_frame};
+use crate::data_header::{DataHeader, DATAGRAM_BUFFER_LEN, DATA_HEADER_LEN};
+use crate::message_type::MessageType;
+use crate::obfuscation::Obfuscation;
+use crate::packet_decoder::PacketDecoder;
+use crate::packet_encoder::PacketEncoder;
+use crate::padding::Padding;
+use crate::protocol::{Capabilities, Negotiation, PROTOCOL_VERSION_LEGACY, PROTOCOL_VERSION_MAX};
+use crate::replay_window::ReplayWindow;
+use crate::session_ticket::{resumption_key, resumption_proof};
+use crate::tunnel::Tunnel;
+use crate::tunnel_config::TunnelConfig;
+use crate::tunnel_device::TunnelDevice;
+use crate::websocket::WebSocketConnector;
+
+const CONTROL_TIMEOUT: Duration = Duration::from_secs(10);
+const TRACE_INTERVAL: Duration = Duration::from_secs(5);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Control connection of a session, plain TCP or a WebSocket over TLS.
+pub trait ControlStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ControlStream for T {}
+
+/// Resumption ticket of the last approved session with its secret.
+struct ResumptionTicket {
+    ticket: Vec<u8>,
+    secret: Vec<u8>,
+    protocol_version: u16,
+    capabilities: Capabilities,
+}
+
+/// Established session, as announced by `SignApprove` / `ResumeApprove`.
+pub struct ClientSession {
+    pub session_id: u32,
+    pub config: TunnelConfig,
+    pub protocol_version: u16,
+    pub capabilities: Capabilities,
+    pub cipher: CipherSuite,
+    less_safe_key: LessSafeKey,
+    control: Box<dyn ControlStream>,
+    received: Vec<u8>,
+}
+
+/// Creates the local tunnel device for a pushed config.
+pub type TunnelFactory = Box<dyn Fn(&TunnelConfig) -> Arc<dyn TunnelDevice> + Send + Sync>;
+
+/// Reference client: handshake, udp port announcement, local tunnel device
+/// with the pushed config and reconnects with exponential backoff.
+pub struct Client {
+    server: String,
+    access_token: String,
+    udp_socket: UdpSocket,
+    cipher: CipherSuite,
+    tcp_data: bool,
+    websocket: Option<WebSocketConnector>,
+    obfuscation: Option<Obfuscation>,
+    padding: Option<Padding>,
+    cover_traffic: Option<Duration>,
+    ticket: Option<ResumptionTicket>,
+    tunnel: Option<(TunnelConfig, Arc<dyn TunnelDevice>)>,
+    tunnel_factory: TunnelFactory,
+}
+
+impl Client {
+    pub fn new(server: &str, access_token: &str, udp_socket: UdpSocket) -> Self {
+        Self {
+            server: String::from(server),
+            access_token: String::from(access_token),
+            udp_socket,
+            cipher: CipherSuite::preferred(),
+            tcp_data: false,
+            websocket: None,
+            obfuscation: None,
+            padding: None,
+            cover_traffic: None,
+            ticket: None,
+            tunnel: None,
+            tunnel_factory: Box::new(|config| Arc::new(
+                Tunnel::create(config.address, config.netmask, config.mtu as i32)
+            )),
+        }
+    }
+
+    /// Replaces the TUN device, e.g. with a `MemoryTunnel` in tests.
+    pub fn with_tunnel_factory(mut self, tunnel_factory: TunnelFactory) -> Self {
+        self.tunnel_factory = tunnel_factory;
+        self
+    }
+
+    /// Cipher suite asked for in `Sign`, the server falls back to AES-256-GCM
+    /// when it does not support it.
+    pub fn with_cipher(mut self, cipher: CipherSuite) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Asks for data packets on the control connection, for networks blocking UDP.
+    pub fn with_tcp_data(mut self, tcp_data: bool) -> Self {
+        self.tcp_data = tcp_data;
+        self
+    }
+
+    /// Connects through the server's TLS listener instead of `server`, with data
+    /// packets framed on the WebSocket.
+    pub fn with_websocket(mut self, websocket: Option<WebSocketConnector>) -> Self {
+        self.websocket = websocket;
+        self
+    }
+
+    /// Obfuscates the control stream and datagrams with the server's shared key.
+    pub fn with_obfuscation(mut self, obfuscation: Option<Obfuscation>) -> Self {
+        self.obfuscation = obfuscation;
+        self
+    }
+
+    /// Pads sealed messages when the server negotiates `Capabilities::PADDING`.
+    pub fn with_padding(mut self, padding: Option<Padding>) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Sends a cover packet per `interval` while the tunnel is idle, padding required.
+    pub fn with_cover_traffic(mut self, interval: Option<Duration>) -> Self {
+        self.cover_traffic = interval;
+        self
+    }
+
+    /// Reads `SMO_SERVER` (host:port of the control and data sockets),
+    /// `SMO_ACCESS_TOKEN`, optional `SMO_UDP_PORT` (default: any free port),
+    /// `SMO_CIPHER` (`aes-256-gcm` or `chacha20-poly1305`, default: by CPU support)
+    /// `SMO_TCP_DATA` (default false), `SMO_OBFUSCATION_KEY` (default: none),
+    /// `SMO_PADDING` (as `PADDING` on the server), `SMO_COVER_TRAFFIC_INTERVAL`
+    /// (milliseconds, default: none) and the `WebSocketConnector` variables.
+    pub async fn from_env() -> io::Result<Self> {
+        let server = std::env::var("SMO_SERVER")
+            .expect("Failed import SMO_SERVER.");
+
+        let access_token = std::env::var("SMO_ACCESS_TOKEN")
+            .expect("Failed import SMO_ACCESS_TOKEN.");
+
+        let udp_port = std::env::var("SMO_UDP_PORT")
+            .map(|udp_port| udp_port.parse::<u16>().expect("Failed parse SMO_UDP_PORT."))
+            .unwrap_or(0);
+
+        let cipher = std::env::var("SMO_CIPHER")
+            .map(|cipher| cipher.parse::<CipherSuite>().expect("Failed parse SMO_CIPHER."))
+            .unwrap_or_else(|_| CipherSuite::preferred());
+
+        let tcp_data = std::env::var("SMO_TCP_DATA")
+            .map(|tcp_data| tcp_data.parse::<bool>().expect("Failed parse SMO_TCP_DATA."))
+            .unwrap_or(false);
+
+        let obfuscation = std::env::var("SMO_OBFUSCATION_KEY")
+            .ok()
+            .filter(|key| !key.is_empty())
+            .map(|key| Obfuscation::new(key.as_bytes()));
+
+        let padding = std::env::var("SMO_PADDING")
+            .ok()
+            .map(|padding| padding.parse::<Padding>().expect("Failed parse SMO_PADDING."));
+
+        let cover_traffic = std::env::var("SMO_COVER_TRAFFIC_INTERVAL")
+            .ok()
+            .map(|interval| Duration::from_millis(interval.parse().expect("Failed parse SMO_COVER_TRAFFIC_INTERVAL.")));
+
+        let udp_socket = UdpSocket::bind(("0.0.0.0", udp_port)).await?;
+
+        Ok(Self::new(&server, &access_token, udp_socket)
+            .with_cipher(cipher)
+            .with_tcp_data(tcp_data)
+            .with_websocket(WebSocketConnector::from_env())
+            .with_obfuscation(obfuscation)
+            .with_padding(padding)
+            .with_cover_traffic(cover_traffic))
+    }
+
+    pub async fn run(&mut self) {
+        let mut backoff = MIN_BACKOFF;
+
+        loop {
+            match self.connect().await {
+                Ok(session) => {
+                    log::info!("Session {} established, tunnel address {}", session.session_id, session.config.address);
+                    backoff = MIN_BACKOFF;
+
+                    if let Err(err) = self.transmit(session).await {
+                        log::warn!("Session lost: {err}");
+                    }
+                }
+                Err(err) => log::error!("Failed establish session: {err}"),
+            }
+
+            log::info!("Reconnecting in {backoff:?}");
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    /// Resumes the previous session when a ticket is held, falling back to the full handshake.
+    pub async fn connect(&mut self) -> io::Result<ClientSession> {
+        let control = self.open_control().await?;
+        let udp_port = self.udp_socket.local_addr()?.port();
+
+        let Some(ticket) = self.ticket.take() else {
+            return self.sign(control, udp_port).await;
+        };
+
+        match self.resume(control, &ticket, udp_port).await {
+            Ok(session) => Ok(session),
+            Err(err) => {
+                log::warn!("Resumption refused ({err}), signing in again.");
+
+                let control = self.open_control().await?;
+                self.sign(control, udp_port).await
+            }
+        }
+    }
+
+    async fn open_control(&self) -> io::Result<Box<dyn ControlStream>> {
+        if let Some(websocket) = &self.websocket {
+            return Ok(Box::new(websocket.connect().await?));
+        }
+
+        let control = TcpStream::connect(&self.server).await?;
+
+        // the server shares one port between the control and data sockets.
+        self.udp_socket.connect(control.peer_addr()?).await?;
+
+        match &self.obfuscation {
+            Some(obfuscation) => Ok(Box::new(obfuscation.stream(control))),
+            None => Ok(Box::new(control)),
+        }
+    }
+
+    async fn sign(&mut self, mut control: Box<dyn ControlStream>, udp_port: u16) -> io::Result<ClientSession> {
+        let key_pair = EphemeralPrivateKey::generate(&agreement::X25519, &SystemRandom::new())
+            .map_err(|_| io::Error::other("failed generate private key"))?;
+
+        let local_context_pk = key_pair.compute_public_key()
+            .map_err(|_| io::Error::other("failed compute public key"))?;
+
+        let mut offered_capabilities = Capabilities::RESUMPTION | self.cipher.capability();
+
+        // offered only with a policy, so both ends of a padded session pad every sealed message.
+        if self.padding.is_some() {
+            offered_capabilities = offered_capabilities | Capabilities::PADDING;
+        }
+
+        // a WebSocket is only used where UDP does not get through.
+        if self.tcp_data || self.websocket.is_some() {
+            offered_capabilities = offered_capabilities | Capabilities::TCP_DATA;
+        }
+
+        let mut packet = PacketEncoder::new();
+        packet.write_opcode(MessageType::Sign);
+        packet.write_string(local_context_pk.as_ref());
+        packet.write_u16(PROTOCOL_VERSION_MAX);
+        packet.write_u32(offered_capabilities.bits());
+
+        control.write_all(&frame(&packet.to_bytes(None))).await?;
+
+        let mut received = Vec::new();
+        let buf = Self::read_message(&mut control, &mut received).await?;
+        let mut packet = PacketDecoder::new(&buf);
+
+        match packet.read_opcode() {
+            MessageType::SignWaitApprove => {}
+            MessageType::SignReject => {
+                let reason = String::from_utf8_lossy(&packet.try_read_string()?).into_owned();
+                let (min, max) = (packet.try_read_uint16()?, packet.try_read_uint16()?);
+
+                return Err(io::Error::other(format!("Sign rejected: {reason} (server speaks versions {min}..={max})")));
+            }
+            _ => return Err(io::Error::other("unexpected reply to Sign")),
+        }
+
+        let remote_pk = packet.try_read_string()?;
+
+        // servers predating negotiation end the message after the key.
+        let negotiating = packet.remaining() >= 6;
+        let (protocol_version, capabilities) = if negotiating {
+            (packet.try_read_uint16()?, Capabilities::from_bits(packet.try_read_uint32()?))
+        } else {
+            (PROTOCOL_VERSION_LEGACY, Capabilities::legacy())
+        };
+
+        let negotiation = Negotiation {
+            offered_version: PROTOCOL_VERSION_MAX,
+            offered_capabilities,
+            protocol_version,
+            capabilities,
+        };
+
+        let less_safe_key = agreement::agree_ephemeral(
+            key_pair,
+            &UnparsedPublicKey::new(&agreement::X25519, remote_pk),
+            |material| CipherSuite::negotiated(capabilities).less_safe_key(material))
+            .map_err(|_| io::Error::other("failed agree session key"))?
+            .ok_or_else(|| io::Error::other("failed create session key"))?;
+
+        let mut packet = PacketEncoder::new().with_padding(self.negotiated_padding(capabilities));
+        packet.write_opcode(MessageType::SignApprove);
+        packet.write_string(self.access_token.as_bytes());
+        packet.write_u16(udp_port);
+        negotiation.write(&mut packet);
+
+        control.write_all(&frame(&packet.to_bytes(Some(less_safe_key.clone())))).await?;
+
+        let buf = Self::read_message(&mut control, &mut received).await?;
+        let mut packet = PacketDecoder::open_padded(&buf, &less_safe_key, &[], capabilities.contains(Capabilities::PADDING))
+            .ok_or_else(|| io::Error::other("undecryptable SignApprove"))?;
+
+        if packet.read_opcode() != MessageType::SignApprove {
+            return Err(io::Error::other("unexpected reply to SignApprove"));
+        }
+
+        let _greeting = packet.try_read_string()?;
+
+        self.approved(&mut packet, less_safe_key, control, received, |packet| {
+            negotiation.verify(packet, negotiating).map(|_| negotiation)
+        })
+    }
+
+    async fn resume(&mut self, mut control: Box<dyn ControlStream>, ticket: &ResumptionTicket, udp_port: u16) -> io::Result<ClientSession> {
+        let key_pair = EphemeralPrivateKey::generate(&agreement::X25519, &SystemRandom::new())
+            .map_err(|_| io::Error::other("failed generate private key"))?;
+
+        let local_context_pk = key_pair.compute_public_key()
+            .map_err(|_| io::Error::other("failed compute public key"))?;
+
+        let mut packet = PacketEncoder::new();
+        packet.write_opcode(MessageType::Resume);
+        packet.write_string(&ticket.ticket);
+        packet.write_string(local_context_pk.as_ref());
+        packet.write_u16(udp_port);
+        packet.write_string(&resumption_proof(&ticket.secret, &ticket.ticket, local_context_pk.as_ref(), udp_port));
+
+        control.write_all(&frame(&packet.to_bytes(None))).await?;
+
+        let mut received = Vec::new();
+        let buf = Self::read_message(&mut control, &mut received).await?;
+        let mut packet = PacketDecoder::new(&buf);
+
+        if packet.read_opcode() != MessageType::ResumeApprove {
+            return Err(io::Error::other("unexpected reply to Resume"));
+        }
+
+        let remote_pk = packet.try_read_string()?;
+        let sealed = packet.try_read_string()?;
+
+        let less_safe_key = agreement::agree_ephemeral(
+            key_pair,
+            &UnparsedPublicKey::new(&agreement::X25519, remote_pk),
+            |material| resumption_key(&ticket.secret, material, CipherSuite::negotiated(ticket.capabilities)))
+            .map_err(|_| io::Error::other("failed agree session key"))?
+            .ok_or_else(|| io::Error::other("failed create session key"))?;
+
+        let mut packet = PacketDecoder::open_padded(&sealed, &less_safe_key, &[], ticket.capabilities.contains(Capabilities::PADDING))
+            .ok_or_else(|| io::Error::other("undecryptable ResumeApprove"))?;
+
+        self.approved(&mut packet, less_safe_key, control, received, |packet| {
+            Negotiation::resumed(packet, ticket.protocol_version, ticket.capabilities)
+        })
+    }
+
+    /// Padding policy for a session with the negotiated `capabilities`.
+    fn negotiated_padding(&self, capabilities: Capabilities) -> Option<Padding> {
+        self.padding.clone().filter(|_| capabilities.contains(Capabilities::PADDING))
+    }
+
+    /// Reads the approval body shared by `SignApprove` and `ResumeApprove`.
+    /// `received` holds control bytes that arrived after it. `terms` checks the
+    /// sealed echo closing the body and yields the granted negotiation.
+    fn approved(
+        &mut self,
+        packet: &mut PacketDecoder,
+        less_safe_key: LessSafeKey,
+        control: Box<dyn ControlStream>,
+        received: Vec<u8>,
+        terms: impl FnOnce(&mut PacketDecoder) -> io::Result<Negotiation>,
+    ) -> io::Result<ClientSession> {
+        let session_id = packet.try_read_uint32()?;
+        let ticket = packet.try_read_string()?;
+        let secret = packet.try_read_string()?;
+        let config = TunnelConfig::read(packet)?;
+
+        let negotiation = terms(packet)?;
+
+        let (protocol_version, capabilities) = (negotiation.protocol_version, negotiation.capabilities);
+
+        // no ticket is issued unless resumption was negotiated.
+        self.ticket = (!ticket.is_empty()).then_some(ResumptionTicket {
+            ticket,
+            secret,
+            protocol_version,
+            capabilities,
+        });
+
+        Ok(ClientSession {
+            session_id,
+            config,
+            protocol_version,
+            capabilities,
+            cipher: CipherSuite::negotiated(capabilities),
+            less_safe_key,
+            control,
+            received,
+        })
+    }
+
+    /// Moves packets between the tunnel device and the udp socket, or the
+    /// control connection when `Capabilities::TCP_DATA` was negotiated, until
+    /// the control connection drops.
+    pub async fn transmit(&mut self, session: ClientSession) -> io::Result<()> {
+        let ClientSession { session_id, config, capabilities, less_safe_key, mut control, received, .. } = session;
+
+        let tunnel = match &self.tunnel {
+            Some((tunnel_config, tunnel)) if *tunnel_config == config => tunnel.clone(),
+            _ => {
+                let tunnel = (self.tunnel_factory)(&config);
+                self.tunnel = Some((config, tunnel.clone()));
+                tunnel
+            }
+        };
+
+        let padding = self.negotiated_padding(capabilities);
+        let mut cover = self.cover_traffic.filter(|_| padding.is_some()).map(tokio::time::interval);
+
+        if capabilities.contains(Capabilities::TCP_DATA) {
+            return Self::transmit_framed(session_id, tunnel, less_safe_key, control, received, padding, cover).await;
+        }
+
+        let udp_socket = &self.udp_socket;
+        let obfuscation = &self.obfuscation;
+        let mut counter = 0u64;
+        let mut replay_window = ReplayWindow::new();
+        let mut trace = tokio::time::interval(TRACE_INTERVAL);
+        let mut idle = true;
+
+        let mut tunnel_buf = [0u8; 2048];
+        let mut udp_buf = [0u8; DATAGRAM_BUFFER_LEN];
+        let mut control_buf = [0u8; 2048];
+
+        loop {
+            tokio::select! {
+                n = tunnel.recv(&mut tunnel_buf) => {
+                    let n = n?;
+
+                    let header = DataHeader::new(MessageType::Data, session_id, counter);
+                    counter += 1;
+                    idle = false;
+
+                    send_datagram(udp_socket, obfuscation, &seal_data(header, Some(&tunnel_buf[..n]), &less_safe_key, padding.as_ref())).await?;
+                }
+                _ = tick(&mut cover) => {
+                    if idle {
+                        let header = DataHeader::new(MessageType::Data, session_id, counter);
+                        counter += 1;
+
+                        send_datagram(udp_socket, obfuscation, &seal_data(header, None, &less_safe_key, padding.as_ref())).await?;
+                    }
+
+                    idle = true;
+                }
+                n = udp_socket.recv(&mut udp_buf) => {
+                    let n = n?;
+
+                    let datagram = match obfuscation {
+                        Some(obfuscation) => match obfuscation.open_datagram(&udp_buf[..n]) {
+                            Some(datagram) => datagram,
+                            None => continue,
+                        },
+                        None => udp_buf[..n].to_vec(),
+                    };
+
+                    let Some(header) = DataHeader::parse(&datagram) else {
+                        continue;
+                    };
+
+                    if header.message_type != MessageType::Data || header.session_id != session_id {
+                        continue;
+                    }
+
+                    let Some(mut packet) = PacketDecoder::open_padded(&datagram[DATA_HEADER_LEN..], &less_safe_key, &datagram[..DATA_HEADER_LEN], padding.is_some()) else {
+                        continue;
+                    };
+
+                    // cover traffic carries no frame.
+                    if !replay_window.accept(header.counter) || packet.remaining() == 0 {
+                        continue;
+                    }
+
+                    let Ok(frame_bytes) = packet.try_read_string() else {
+                        continue;
+                    };
+
+                    tunnel.send(&frame_bytes).await?;
+                }
+                _ = trace.tick() => {
+                    let mut packet = PacketEncoder::new().with_padding(padding.clone());
+                    packet.write_opcode(MessageType::Trace);
+
+                    control.write_all(&frame(&packet.to_bytes(Some(less_safe_key.clone())))).await?;
+                }
+                n = control.read(&mut control_buf) => {
+                    if n? == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                }
+            }
+        }
+    }
+
+    /// Data packets as frames on the control connection; traces are frames too,
+    /// with a `Trace` header.
+    async fn transmit_framed(
+        session_id: u32,
+        tunnel: Arc<dyn TunnelDevice>,
+        less_safe_key: LessSafeKey,
+        mut control: Box<dyn ControlStream>,
+        mut frames: Vec<u8>,
+        padding: Option<Padding>,
+        mut cover: Option<Interval>,
+    ) -> io::Result<()> {
+        let mut counter = 0u64;
+        let mut replay_window = ReplayWindow::new();
+        let mut trace = tokio::time::interval(TRACE_INTERVAL);
+        let mut idle = true;
+
+        let mut tunnel_buf = [0u8; 2048];
+        let mut control_buf = [0u8; 4096];
+
+        loop {
+            tokio::select! {
+                n = tunnel.recv(&mut tunnel_buf) => {
+                    let n = n?;
+
+                    let header = DataHeader::new(MessageType::Data, session_id, counter);
+                    counter += 1;
+
+                    idle = false;
+
+                    control.write_all(&frame(&seal_data(header, Some(&tunnel_buf[..n]), &less_safe_key, padding.as_ref()))).await?;
+                }
+                _ = trace.tick() => {
+                    let header = DataHeader::new(MessageType::Trace, session_id, counter);
+                    counter += 1;
+
+                    control.write_all(&frame(&seal_data(header, Some(&[]), &less_safe_key, padding.as_ref()))).await?;
+                }
+                _ = tick(&mut cover) => {
+                    if idle {
+                        let header = DataHeader::new(MessageType::Data, session_id, counter);
+                        counter += 1;
+
+                        control.write_all(&frame(&seal_data(header, None, &less_safe_key, padding.as_ref()))).await?;
+                    }
+
+                    idle = true;
+                }
+                n = control.read(&mut control_buf) => {
+                    let n = n?;
+
+                    if n == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+
+                    frames.extend_from_slice(&control_buf[..n]);
+
+                    while let Some(packet) = next_frame(&mut frames) {
+                        let Some(header) = DataHeader::parse(&packet) else {
+                            continue;
+                        };
+
+                        if header.message_type != MessageType::Data || header.session_id != session_id {
+                            continue;
+                        }
+
+                        let Some(mut packet) = PacketDecoder::open_padded(&packet[DATA_HEADER_LEN..], &less_safe_key, &packet[..DATA_HEADER_LEN], padding.is_some()) else {
+                            continue;
+                        };
+
+                        if !replay_window.accept(header.counter) || packet.remaining() == 0 {
+                            continue;
+                        }
+
+                        let Ok(frame_bytes) = packet.try_read_string() else {
+                            continue;
+                        };
+
+                        tunnel.send(&frame_bytes).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Next control message, framed like data packets. Bytes received past
+    /// it are kept in `received` for the following reads.
+    async fn read_message(control: &mut Box<dyn ControlStream>, received: &mut Vec<u8>) -> io::Result<Vec<u8>> {
+        let read = async {
+            let mut buf = [0u8; 4096];
+
+            loop {
+                if let Some(message) = next_frame(received) {
+                    return Ok(message);
+                }
+
+                let n = control.read(&mut buf).await?;
+
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+
+                received.extend_from_slice(&buf[..n]);
+            }
+        };
+
+        tokio::time::timeout(CONTROL_TIMEOUT, read)
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))?
+    }
+}
+
+/// Seals a tunnel packet behind its header, which is authenticated as AAD.
+/// Without a packet it is a cover packet, only padding.
+fn seal_data(header: DataHeader, packet: Option<&[u8]>, less_safe_key: &LessSafeKey, padding: Option<&Padding>) -> Vec<u8> {
+    let header_bytes = header.to_bytes();
+
+    let mut encoder = PacketEncoder::new().with_padding(padding.cloned());
+
+    if let Some(packet) = packet {
+        encoder.write_string(packet);
+    }
+
+    let mut packet_bytes = header_bytes.to_vec();
+    packet_bytes.extend(encoder.to_bytes_with_aad(Some(less_safe_key.clone()), &header_bytes));
+    packet_bytes
+}
+
+async fn send_datagram(udp_socket: &UdpSocket, obfuscation: &Option<Obfuscation>, datagram: &[u8]) -> io::Result<usize> {
+    match obfuscation {
+        Some(obfuscation) => udp_socket.send(&obfuscation.seal_datagram(datagram)).await,
+        None => udp_socket.send(datagram).await,
+    }
+}
+
+/// Next tick of the cover traffic interval, never without one.
+async fn tick(cover: &mut Option<Interval>) {
+    match cover {
+        Some(cover) => {
+            cover.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
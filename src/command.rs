@@ -0,0 +1,77 @@
+use std::net::Ipv4Addr;
+use clap::{Parser, Subcommand};
+use sqlx::{MySql, MySqlPool, Pool};
+
+#[derive(Parser)]
+#[command(name = "smo", about = "smo vpn server")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Runs the vpn server (default).
+    Serve,
+    /// Issues and inspects access tokens.
+    #[command(subcommand)]
+    Token(TokenCommand),
+    /// Manages users of the configured user store.
+    #[command(subcommand)]
+    User(UserCommand),
+}
+
+#[derive(Subcommand)]
+pub enum TokenCommand {
+    /// Issues an access token for an enabled user.
+    Issue {
+        /// User id.
+        #[arg(long)]
+        user: u32,
+        /// Token lifetime: seconds or a number with s/m/h/d/w suffix.
+        #[arg(long, default_value = "365d", value_parser = crate::token_command::parse_ttl)]
+        ttl: time::Duration,
+    },
+    /// Decodes a token and verifies it against the configured keys.
+    Inspect {
+        token: String,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum UserCommand {
+    /// Adds an enabled user, picking the first free tunnel address unless given.
+    Add {
+        username: String,
+        #[arg(long)]
+        address: Option<Ipv4Addr>,
+    },
+    /// Lists users.
+    List,
+    /// Allows the user to establish sessions.
+    Enable {
+        id: u32,
+    },
+    /// Refuses new sessions of the user.
+    Disable {
+        id: u32,
+    },
+    /// Moves the user to another tunnel address.
+    SetAddress {
+        id: u32,
+        address: Ipv4Addr,
+    },
+    /// Deletes the user.
+    Delete {
+        id: u32,
+    },
+}
+
+pub async fn mysql_pool() -> Pool<MySql> {
+    let database_url = std::env::var("MYSQL_DSN")
+        .expect("example env error");
+
+    MySqlPool::connect(database_url.as_str())
+        .await
+        .expect("Failed initialized MySQL Connection.")
+}
@@ -0,0 +1,20 @@
+/// Data packets carried on a stream, each prefixed with its u16 length.
+pub fn frame(packet: &[u8]) -> Vec<u8> {
+    let mut frame = (packet.len() as u16).to_be_bytes().to_vec();
+    frame.extend_from_slice(packet);
+    frame
+}
+
+/// Takes the first complete frame off `buf`, `None` until it has fully arrived.
+pub fn next_frame(buf: &mut Vec<u8>) -> Option<Vec<u8>> {
+    let len = u16::from_be_bytes([*buf.first()?, *buf.get(1)?]) as usize;
+
+    if buf.len() < 2 + len {
+        return None;
+    }
+
+    let packet = buf[2..2 + len].to_vec();
+    buf.drain(..2 + len);
+
+    Some(packet)
+}
@@ -0,0 +1,57 @@
+use std::io::{Cursor, Read};
+use crate::message_type::MessageType;
+
+pub const DATA_HEADER_LEN: usize = 13;
+
+/// Receive buffer for one datagram on either side.
+pub const DATAGRAM_BUFFER_LEN: usize = 2048;
+
+/// Plaintext header of every udp datagram: type, server assigned session id
+/// and per-direction counter. Authenticated as AAD of the sealed body.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct DataHeader {
+    pub message_type: MessageType,
+    pub session_id: u32,
+    pub counter: u64,
+}
+
+impl DataHeader {
+    pub fn new(message_type: MessageType, session_id: u32, counter: u64) -> Self {
+        Self {
+            message_type,
+            session_id,
+            counter,
+        }
+    }
+
+    pub fn parse(buf: &[u8]) -> Option<Self> {
+        if buf.len() < DATA_HEADER_LEN {
+            return None;
+        }
+
+        let mut cursor = Cursor::new(&buf[..DATA_HEADER_LEN]);
+        let mut opcode = [0u8; 1];
+        let mut session_id = [0u8; 4];
+        let mut counter = [0u8; 8];
+
+        cursor.read_exact(&mut opcode).ok()?;
+        cursor.read_exact(&mut session_id).ok()?;
+        cursor.read_exact(&mut counter).ok()?;
+
+        Some(Self {
+            message_type: MessageType::try_from(opcode[0]).ok()?,
+            session_id: u32::from_be_bytes(session_id),
+            counter: u64::from_be_bytes(counter),
+        })
+    }
+
+    pub fn to_bytes(self) -> [u8; DATA_HEADER_LEN] {
+        let mut buf = [0u8; DATA_HEADER_LEN];
+
+        buf[0] = self.message_type.into();
+        buf[1..5].copy_from_slice(&self.session_id.to_be_bytes());
+        buf[5..].copy_from_slice(&self.counter.to_be_bytes());
+
+        buf
+    }
+}
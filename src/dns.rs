@@ -1,56 +1,457 @@
+use std::io;
+use std::sync::Arc;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use ring::aead::LessSafeKey;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::Semaphore;
+use crate::data_header::{DataHeader, DATA_HEADER_LEN};
+use crate::dns_cache::{DnsCache, DnsCacheStats};
+use crate::dns_filter::DnsFilter;
+use crate::dns_message;
+use crate::dns_query_log::DnsQueryLog;
+use crate::dns_upstream::DnsUpstreams;
+use crate::dns_zone::DnsZone;
+use crate::message_type::MessageType;
+use crate::obfuscation::Obfuscation;
 use crate::packet_decoder::PacketDecoder;
 use crate::packet_encoder::PacketEncoder;
+use crate::session::SessionsPool;
+use crate::user::User;
 
+const CACHE_STATS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a TCP client may stay silent before its connection is closed.
+const TCP_IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a new TCP connection may take to send its first authenticated
+/// query, so unauthenticated sockets do not hold a connection slot.
+const TCP_FIRST_QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Largest answer sealed into one TCP frame, leaving room for the
+/// `DataHeader`, length prefix, tag and nonce.
+const TCP_ANSWER_MAX: usize = u16::MAX as usize - 64;
+
+/// Encrypted DNS proxy for established sessions. Every datagram is a `DataHeader`
+/// (`DnsQuery` / `DnsAnswer`, session id, query counter echoed in the answer)
+/// authenticating the message sealed with the key of that live session, so each
+/// query is tied to its user. Anything else, including a counter the session
+/// already used, is dropped without an answer.
+///
+/// The same packets are accepted over TCP on the same port, each prefixed with
+/// its u16 length. UDP answers larger than the query's EDNS0 payload size are
+/// truncated so the client retries over TCP. With obfuscation, datagrams are
+/// masked and TCP connections are obfuscated streams, like the session's.
 pub struct Dns {
-    pub async_socket: tokio::net::UdpSocket,
-    pub shared: Vec<u8>,
+    pub async_socket: Arc<UdpSocket>,
+    listener: TcpListener,
+    sessions_pool: SessionsPool,
+    upstreams: Arc<DnsUpstreams>,
+    cache: Arc<DnsCache>,
+    filter: DnsFilter,
+    zone: Option<DnsZone>,
+    query_log: Option<DnsQueryLog>,
+    obfuscation: Option<Obfuscation>,
+    in_flight: Arc<Semaphore>,
+    tcp_connections: Arc<Semaphore>,
 }
 
 impl Dns {
-    pub fn new(addr: &str, shared: Vec<u8>) -> std::io::Result<Self> {
+    pub fn new(addr: &str, sessions_pool: SessionsPool) -> std::io::Result<Self> {
         let socket = std::net::UdpSocket::bind(addr)?;
         log::info!("Success initialize UDP Socket for crypt DNS");
 
         socket.set_nonblocking(true)?;
 
-        let async_socket = tokio::net::UdpSocket::from_std(socket)?;
+        let async_socket = UdpSocket::from_std(socket)?;
+
+        // on the UDP socket's address, so an ephemeral port is shared too.
+        let listener = std::net::TcpListener::bind(async_socket.local_addr()?)?;
+        listener.set_nonblocking(true)?;
 
         Ok(Self {
-            async_socket,
-            shared,
+            async_socket: Arc::new(async_socket),
+            listener: TcpListener::from_std(listener)?,
+            sessions_pool,
+            upstreams: Arc::new(DnsUpstreams::from_env()),
+            cache: Arc::new(DnsCache::from_env()),
+            filter: DnsFilter::from_env(),
+            zone: None,
+            query_log: DnsQueryLog::from_env(),
+            obfuscation: None,
+            in_flight: Arc::new(Semaphore::new(Self::max_in_flight_from_env())),
+            tcp_connections: Arc::new(Semaphore::new(Self::max_tcp_connections_from_env())),
         })
     }
 
-    pub async fn expose(&self) {
-        let mut buf = [0u8; 2048];
+    /// Reads `DNS_MAX_IN_FLIGHT`, the upstream queries awaiting an answer at
+    /// once (default 1024); queries beyond it are dropped.
+    fn max_in_flight_from_env() -> usize {
+        std::env::var("DNS_MAX_IN_FLIGHT")
+            .ok()
+            .map(|max_in_flight| max_in_flight.parse::<usize>().expect("Failed parse DNS_MAX_IN_FLIGHT."))
+            .unwrap_or(1024)
+    }
+
+    pub fn with_max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.in_flight = Arc::new(Semaphore::new(max_in_flight));
+        self
+    }
+
+    /// Reads `DNS_MAX_TCP_CONNECTIONS`, the TCP clients served at once
+    /// (default 256); connections beyond it are closed on accept.
+    fn max_tcp_connections_from_env() -> usize {
+        std::env::var("DNS_MAX_TCP_CONNECTIONS")
+            .ok()
+            .map(|max_tcp_connections| max_tcp_connections.parse::<usize>().expect("Failed parse DNS_MAX_TCP_CONNECTIONS."))
+            .unwrap_or(256)
+    }
+
+    pub fn with_max_tcp_connections(mut self, max_tcp_connections: usize) -> Self {
+        self.tcp_connections = Arc::new(Semaphore::new(max_tcp_connections));
+        self
+    }
+
+    /// Answers the internal zone authoritatively instead of forwarding it.
+    pub fn with_zone(mut self, zone: Option<DnsZone>) -> Self {
+        self.zone = zone;
+        self
+    }
+
+    pub fn with_query_log(mut self, query_log: Option<DnsQueryLog>) -> Self {
+        self.query_log = query_log;
+        self
+    }
+
+    /// Uses the obfuscation of the sessions, clients obfuscate their queries alike.
+    pub fn with_obfuscation(mut self, obfuscation: Option<Obfuscation>) -> Self {
+        self.obfuscation = obfuscation;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: DnsFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_cache(mut self, cache: DnsCache) -> Self {
+        self.cache = Arc::new(cache);
+        self
+    }
+
+    pub fn cache_stats(&self) -> DnsCacheStats {
+        self.cache.stats()
+    }
+
+    pub fn with_upstreams(mut self, upstreams: DnsUpstreams) -> Self {
+        self.upstreams = Arc::new(upstreams);
+        self
+    }
+
+    /// Key and user of the live session a query claims to come from.
+    async fn session(&self, session_id: u32) -> Option<(LessSafeKey, User)> {
+        let sessions = self.sessions_pool.read().await;
+        let session = sessions.get(&session_id)?;
+
+        Some((session.less_safe_key()?, session.user().clone()))
+    }
+
+    /// Checks the counter of an authenticated query against the session's replay window.
+    async fn accept_counter(&self, session_id: u32, counter: u64) -> bool {
+        self.sessions_pool.read().await
+            .get(&session_id)
+            .is_some_and(|session| session.accept_dns_counter(counter))
+    }
+
+    pub async fn expose(self: Arc<Self>) {
+        tokio::select! {
+            x = self.serve() => x,
+            x = self.serve_tcp() => x,
+            x = self.report_cache_stats() => x,
+            x = self.reload_on_hangup() => x,
+            x = async {
+                match &self.zone {
+                    Some(zone) => zone.poll().await,
+                    None => std::future::pending().await,
+                }
+            } => x,
+        }
+    }
+
+    /// Reloads the filter policy and blocklists on SIGHUP.
+    async fn reload_on_hangup(&self) {
+        let Ok(mut hangup) = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) else {
+            log::error!("Failed install SIGHUP handler, DNS filter reload disabled.");
+            return std::future::pending().await;
+        };
+
+        while hangup.recv().await.is_some() {
+            match self.filter.reload() {
+                Ok(()) => log::info!("DNS filter reloaded"),
+                Err(err) => log::error!("Failed reload DNS filter: {err}"),
+            }
+        }
+    }
+
+    /// Answer produced without asking an upstream: the internal zone,
+    /// blocked names and cache hits.
+    fn local_answer(&self, user: &User, query: &[u8]) -> Option<Vec<u8>> {
+        if let Some((question, _)) = dns_message::question(query) {
+            if let Some(answer) = self.zone.as_ref().and_then(|zone| zone.answer(query, &question)) {
+                return Some(answer);
+            }
+
+            if let Some(blocklist) = self.filter.blocked_by(user, &question.name) {
+                // names stay out of the process log, the query log decides how to record them.
+                log::info!("DNS query from {} blocked by {blocklist}", user.username);
+                return self.filter.blocked_answer(query, &question);
+            }
+        }
+
+        self.cache.get(query)
+    }
+
+    async fn report_cache_stats(&self) {
+        let mut interval = tokio::time::interval(CACHE_STATS_INTERVAL);
+        let mut query_log_dropped = 0;
+        interval.tick().await;
+
+        loop {
+            interval.tick().await;
+
+            let stats = self.cache.stats();
+            log::info!("DNS cache: {} hits, {} misses, {} entries", stats.hits, stats.misses, stats.entries);
+
+            let dropped = self.query_log.as_ref().map_or(0, DnsQueryLog::dropped);
+
+            if dropped > query_log_dropped {
+                log::warn!("DNS query log dropped {} entries, the disk is falling behind", dropped - query_log_dropped);
+                query_log_dropped = dropped;
+            }
+        }
+    }
+
+    /// Authenticated query of a live session: its header, the session key,
+    /// the user and the DNS message. Logs and returns `None` for anything else.
+    async fn open_query(&self, buf: &[u8], sock_addr: SocketAddr) -> Option<(DataHeader, LessSafeKey, User, Vec<u8>)> {
+        let Some(header) = DataHeader::parse(buf) else {
+            log::warn!("DNS packet from {sock_addr} without header");
+            return None;
+        };
+
+        if header.message_type != MessageType::DnsQuery {
+            return None;
+        }
+
+        let Some((less_safe_key, user)) = self.session(header.session_id).await else {
+            log::warn!("DNS query from {sock_addr} without an active session {}", header.session_id);
+            return None;
+        };
+
+        let Some(bytes) = open(buf, &less_safe_key) else {
+            log::warn!("DNS query from {sock_addr} failed authentication for session {}", header.session_id);
+            return None;
+        };
+
+        if !self.accept_counter(header.session_id, header.counter).await {
+            log::warn!("DNS query from {sock_addr} replayed counter {} of session {}", header.counter, header.session_id);
+            return None;
+        }
+
+        Some((header, less_safe_key, user, bytes))
+    }
+
+    fn log_query(&self, user: &User, sock_addr: SocketAddr, query: &[u8], answer: &[u8], upstream: Option<Duration>) {
+        if let Some(query_log) = &self.query_log {
+            query_log.record(user, sock_addr, query, answer, upstream);
+        }
+    }
+
+    /// Asks the upstreams and caches their answer, SERVFAIL when all of them failed.
+    async fn forward(&self, query: &[u8]) -> Option<Vec<u8>> {
+        match self.upstreams.resolve(query).await {
+            Some(answer) => {
+                self.cache.insert(query, &answer);
+                Some(answer)
+            }
+            None => {
+                log::error!("All DNS upstreams failed, answering SERVFAIL.");
+                dns_message::response(query, dns_message::RCODE_SERVFAIL)
+            }
+        }
+    }
+
+    /// Answers a plain query of `user` like a sealed one: from the zone, filter
+    /// or cache, else from the upstreams once an in-flight slot is free.
+    pub async fn resolve(&self, user: &User, sock_addr: SocketAddr, query: &[u8]) -> Option<Vec<u8>> {
+        let (answer, upstream) = match self.local_answer(user, query) {
+            Some(answer) => (answer, None),
+            None => {
+                let _permit = self.in_flight.acquire().await.ok()?;
+                let started = Instant::now();
+
+                (self.forward(query).await?, Some(started.elapsed()))
+            }
+        };
+
+        self.log_query(user, sock_addr, query, &answer, upstream);
+        Some(answer)
+    }
+
+    async fn serve(self: &Arc<Self>) {
+        let mut buf = vec![0u8; 65535];
         while let Ok((n, sock_addr)) = self.async_socket.recv_from(&mut buf).await {
-            let mut packet = PacketDecoder::new_xor(&buf[..n], self.shared.clone());
-            let bytes = packet.read_string();
-            let shared = self.shared.clone();
+            let datagram = match &self.obfuscation {
+                Some(obfuscation) => match obfuscation.open_datagram(&buf[..n]) {
+                    Some(datagram) => datagram,
+                    None => {
+                        log::warn!("Malformed obfuscated DNS datagram from {sock_addr}");
+                        continue;
+                    }
+                },
+                None => buf[..n].to_vec(),
+            };
+
+            let Some((header, less_safe_key, user, bytes)) = self.open_query(&datagram, sock_addr).await else {
+                continue;
+            };
+
+            if let Some(answer) = self.local_answer(&user, &bytes) {
+                self.log_query(&user, sock_addr, &bytes, &answer, None);
+                self.reply(header, &bytes, &answer, &less_safe_key, sock_addr).await;
+                continue;
+            }
+
+            let Ok(permit) = self.in_flight.clone().try_acquire_owned() else {
+                log::warn!("Too many DNS queries in flight, dropping query from {}", user.username);
+                continue;
+            };
+
+            let dns = self.clone();
 
             tokio::task::spawn(async move {
-                let mut buf = [0u8; 2048];
-                let Ok(socket) = tokio::net::UdpSocket::bind("0.0.0.0:0").await else {
-                    log::error!("failed bind dns-client socket.");
-                    return;
-                };
-
-                if let Ok(n) = socket.send_to(&bytes, "1.1.1.1:53").await {
-                    log::info!("success sent to dns server.")
+                let _permit = permit;
+
+                let started = Instant::now();
+
+                if let Some(answer) = dns.forward(&bytes).await {
+                    dns.log_query(&user, sock_addr, &bytes, &answer, Some(started.elapsed()));
+                    dns.reply(header, &bytes, &answer, &less_safe_key, sock_addr).await;
                 }
+            });
+        }
+    }
 
-                if let Ok(n) = socket.recv(&mut buf).await {
+    /// Seals the answer to `query` and sends it back, truncated beyond the
+    /// query's EDNS0 payload size.
+    async fn reply(&self, query_header: DataHeader, query: &[u8], answer: &[u8], less_safe_key: &LessSafeKey, sock_addr: SocketAddr) {
+        let mut packet = seal_answer(query_header, answer, less_safe_key, dns_message::udp_payload_size(query));
 
-                    let bytes = &buf[..n];
-                    let mut packet = PacketEncoder::new();
+        if let Some(obfuscation) = &self.obfuscation {
+            packet = obfuscation.seal_datagram(&packet);
+        }
 
-                    packet.write_string(bytes);
+        if self.async_socket.send_to(&packet, sock_addr).await.is_err() {
+            log::error!("Failed sent DNS answer to {sock_addr}.");
+        }
+    }
 
-                    if let Ok(n) = socket.send_to(&packet.to_bytes_with_xor(shared), sock_addr).await {
-                        log::info!("Success sent dns info to another socket.")
-                    }
+    async fn serve_tcp(self: &Arc<Self>) {
+        while let Ok((stream, sock_addr)) = self.listener.accept().await {
+            let Ok(permit) = self.tcp_connections.clone().try_acquire_owned() else {
+                log::warn!("Too many DNS TCP connections, closing connection from {sock_addr}");
+                continue;
+            };
+
+            let dns = self.clone();
+
+            tokio::task::spawn(async move {
+                let _permit = permit;
+
+                match &dns.obfuscation {
+                    Some(obfuscation) => dns.serve_connection(obfuscation.stream(stream), sock_addr).await,
+                    None => dns.serve_connection(stream, sock_addr).await,
                 }
             });
         }
     }
-}
\ No newline at end of file
+
+    /// Answers the queries of one TCP client in order until it disconnects,
+    /// stays idle or sends a packet that does not authenticate. The first
+    /// query has to arrive within `TCP_FIRST_QUERY_TIMEOUT`.
+    async fn serve_connection<S: AsyncRead + AsyncWrite + Unpin>(&self, mut stream: S, sock_addr: SocketAddr) {
+        let mut timeout = TCP_FIRST_QUERY_TIMEOUT;
+
+        while let Ok(Ok(buf)) = tokio::time::timeout(timeout, read_frame(&mut stream)).await {
+            let Some((header, less_safe_key, user, bytes)) = self.open_query(&buf, sock_addr).await else {
+                break;
+            };
+
+            timeout = TCP_IDLE_TIMEOUT;
+
+            let Some(answer) = self.resolve(&user, sock_addr, &bytes).await else {
+                continue;
+            };
+
+            if write_frame(&mut stream, &seal_answer(header, &answer, &less_safe_key, TCP_ANSWER_MAX)).await.is_err() {
+                log::error!("Failed sent DNS answer to {sock_addr}.");
+                break;
+            }
+        }
+    }
+}
+
+/// Seals the answer to the query under `query_header`, cut down to its
+/// question with TC set when it is longer than `limit`.
+fn seal_answer(query_header: DataHeader, answer: &[u8], less_safe_key: &LessSafeKey, limit: usize) -> Vec<u8> {
+    let header = DataHeader::new(MessageType::DnsAnswer, query_header.session_id, query_header.counter);
+
+    match dns_message::truncated(answer) {
+        Some(truncated) if answer.len() > limit => seal(header, &truncated, less_safe_key),
+        _ => seal(header, answer, less_safe_key),
+    }
+}
+
+/// Seals a DNS message behind `header`, which is authenticated as AAD.
+pub fn seal(header: DataHeader, message: &[u8], less_safe_key: &LessSafeKey) -> Vec<u8> {
+    let header_bytes = header.to_bytes();
+
+    let mut packet = PacketEncoder::new();
+    packet.write_string(message);
+
+    let mut packet_bytes = header_bytes.to_vec();
+    packet_bytes.extend(packet.to_bytes_with_aad(Some(less_safe_key.clone()), &header_bytes));
+    packet_bytes
+}
+
+/// Opens a datagram produced by `seal`, `None` when it does not authenticate.
+pub fn open(buf: &[u8], less_safe_key: &LessSafeKey) -> Option<Vec<u8>> {
+    if buf.len() < DATA_HEADER_LEN {
+        return None;
+    }
+
+    PacketDecoder::open(&buf[DATA_HEADER_LEN..], less_safe_key, &buf[..DATA_HEADER_LEN])?
+        .try_read_string()
+        .ok()
+}
+
+/// Reads one message prefixed with its u16 length, as DNS over TCP frames them.
+pub async fn read_frame<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let len = reader.read_u16().await?;
+
+    let mut frame = vec![0u8; len as usize];
+    reader.read_exact(&mut frame).await?;
+
+    Ok(frame)
+}
+
+pub async fn write_frame<W: AsyncWrite + Unpin>(writer: &mut W, message: &[u8]) -> io::Result<()> {
+    let len = u16::try_from(message.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "message longer than a frame"))?;
+
+    let mut frame = len.to_be_bytes().to_vec();
+    frame.extend_from_slice(message);
+
+    writer.write_all(&frame).await
+}
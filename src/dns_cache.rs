@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use crate::dns_message::{self, DnsQuestion, DnsSection};
+
+struct DnsCacheEntry {
+    answer: Vec<u8>,
+    stored_at: Instant,
+    ttl: Duration,
+}
+
+/// Hit/miss counters and current size of a `DnsCache`.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct DnsCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// Upstream answers by question, kept for their TTL clamped to `min_ttl..=max_ttl`.
+/// NXDOMAIN and NODATA answers are cached for the SOA minimum (RFC 2308),
+/// clamped to `negative_ttl`, and not at all without a SOA.
+pub struct DnsCache {
+    entries: Mutex<HashMap<DnsQuestion, DnsCacheEntry>>,
+    capacity: usize,
+    min_ttl: u32,
+    max_ttl: u32,
+    negative_ttl: u32,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl DnsCache {
+    pub fn new(capacity: usize, min_ttl: u32, max_ttl: u32, negative_ttl: u32) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            capacity,
+            min_ttl,
+            max_ttl,
+            negative_ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Reads `DNS_CACHE_SIZE` (entries, default 10000, 0 disables the cache),
+    /// `DNS_CACHE_MIN_TTL`, `DNS_CACHE_MAX_TTL` and `DNS_CACHE_NEGATIVE_TTL`
+    /// (seconds, default 0, 86400 and 900).
+    pub fn from_env() -> Self {
+        let var = |name: &str, default: u32| std::env::var(name)
+            .ok()
+            .map(|value| value.parse::<u32>().unwrap_or_else(|_| panic!("Failed parse {name}.")))
+            .unwrap_or(default);
+
+        Self::new(
+            var("DNS_CACHE_SIZE", 10000) as usize,
+            var("DNS_CACHE_MIN_TTL", 0),
+            var("DNS_CACHE_MAX_TTL", 86400),
+            var("DNS_CACHE_NEGATIVE_TTL", 900),
+        )
+    }
+
+    /// Cached answer to `query` under the query's transaction id,
+    /// TTLs lowered by the time spent in the cache.
+    pub fn get(&self, query: &[u8]) -> Option<Vec<u8>> {
+        if self.capacity == 0 {
+            return None;
+        }
+
+        let (question, _) = dns_message::question(query)?;
+        let mut entries = self.entries.lock().unwrap();
+
+        let Some(entry) = entries.get(&question) else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+
+        let elapsed = entry.stored_at.elapsed();
+
+        if elapsed >= entry.ttl {
+            entries.remove(&question);
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        let mut answer = entry.answer.clone();
+        drop(entries);
+
+        let elapsed = elapsed.as_secs() as u32;
+
+        for record in dns_message::records(&answer)? {
+            if record.rtype != dns_message::TYPE_OPT {
+                dns_message::set_ttl(&mut answer, record.ttl_offset, record.ttl.saturating_sub(elapsed));
+            }
+        }
+
+        dns_message::set_id(&mut answer, dns_message::id(query)?);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+
+        Some(answer)
+    }
+
+    /// Caches an upstream answer to `query` when it is cacheable.
+    pub fn insert(&self, query: &[u8], answer: &[u8]) {
+        if self.capacity == 0 || dns_message::is_truncated(answer) {
+            return;
+        }
+
+        let Some((question, _)) = dns_message::question(query) else {
+            return;
+        };
+
+        if dns_message::question(answer).is_none_or(|(answered, _)| answered != question) {
+            return;
+        }
+
+        let Some(ttl) = self.ttl(answer) else {
+            return;
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+
+        if entries.len() >= self.capacity && !entries.contains_key(&question) {
+            entries.retain(|_, entry| entry.stored_at.elapsed() < entry.ttl);
+
+            // still full: make room by dropping what expires first.
+            if entries.len() >= self.capacity {
+                let expiring = entries.iter()
+                    .min_by_key(|(_, entry)| entry.stored_at + entry.ttl)
+                    .map(|(question, _)| question.clone());
+
+                if let Some(expiring) = expiring {
+                    entries.remove(&expiring);
+                }
+            }
+        }
+
+        entries.insert(question, DnsCacheEntry {
+            answer: answer.to_vec(),
+            stored_at: Instant::now(),
+            ttl: Duration::from_secs(ttl as u64),
+        });
+    }
+
+    /// How long `answer` may be cached, `None` when it must not be.
+    fn ttl(&self, answer: &[u8]) -> Option<u32> {
+        let rcode = dns_message::rcode(answer)?;
+
+        if rcode != dns_message::RCODE_NOERROR && rcode != dns_message::RCODE_NXDOMAIN {
+            return None;
+        }
+
+        let records = dns_message::records(answer)?;
+
+        let positive = records.iter()
+            .filter(|record| record.section == DnsSection::Answer)
+            .map(|record| record.ttl)
+            .min();
+
+        let ttl = match positive {
+            Some(ttl) if rcode == dns_message::RCODE_NOERROR => ttl.clamp(self.min_ttl, self.max_ttl.max(self.min_ttl)),
+            _ => {
+                let soa = records.iter()
+                    .find(|record| record.section == DnsSection::Authority && record.rtype == dns_message::TYPE_SOA)?;
+
+                let minimum = answer.get(soa.rdata.end.checked_sub(4)?..soa.rdata.end)?;
+                let minimum = u32::from_be_bytes(minimum.try_into().ok()?);
+
+                soa.ttl.min(minimum).clamp(self.min_ttl.min(self.negative_ttl), self.negative_ttl)
+            }
+        };
+
+        (ttl > 0).then_some(ttl)
+    }
+
+    pub fn stats(&self) -> DnsCacheStats {
+        DnsCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().len(),
+        }
+    }
+}
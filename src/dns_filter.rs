@@ -0,0 +1,219 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use serde::Deserialize;
+use crate::dns_message::{self, DnsQuestion};
+use crate::user::User;
+
+const SINKHOLE_TTL: u32 = 60;
+
+/// Domains of one blocklist file. Hosts-file lines (`0.0.0.0 ads.example.com`)
+/// and plain domain lines are accepted; `*.example.com` blocks every subdomain.
+#[derive(Debug, Default)]
+pub struct DnsBlocklist {
+    exact: HashSet<String>,
+    wildcard: HashSet<String>,
+}
+
+impl DnsBlocklist {
+    pub fn parse(text: &str) -> Self {
+        let mut blocklist = Self::default();
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace().peekable();
+
+            // hosts-file format: an address followed by the names it is pinned to.
+            if fields.peek().is_some_and(|field| field.parse::<IpAddr>().is_ok()) {
+                fields.next();
+            }
+
+            for domain in fields {
+                let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+
+                if matches!(domain.as_str(), "" | "localhost" | "localhost.localdomain" | "broadcasthost" | "local") {
+                    continue;
+                }
+
+                match domain.strip_prefix("*.") {
+                    Some(suffix) => blocklist.wildcard.insert(suffix.to_string()),
+                    None => blocklist.exact.insert(domain),
+                };
+            }
+        }
+
+        blocklist
+    }
+
+    pub fn is_blocked(&self, name: &str) -> bool {
+        if self.exact.contains(name) {
+            return true;
+        }
+
+        name.match_indices('.')
+            .any(|(index, _)| self.wildcard.contains(&name[index + 1..]))
+    }
+
+    pub fn len(&self) -> usize {
+        self.exact.len() + self.wildcard.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// What a blocked query is answered with.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsFilterAction {
+    #[default]
+    Nxdomain,
+    /// A/AAAA queries get the sinkhole address, other types an empty answer.
+    Sinkhole,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct DnsFilterGroup {
+    #[serde(default)]
+    users: Vec<String>,
+    #[serde(default)]
+    blocklists: Vec<String>,
+}
+
+/// Which blocklists apply to whom, read from the `DNS_FILTER_POLICY` JSON file:
+///
+/// ```json
+/// {
+///   "blocklists": { "malware": "/etc/smo/malware.hosts", "tracking": "/etc/smo/tracking.txt" },
+///   "default": ["malware"],
+///   "groups": { "managed": { "users": ["alice"], "blocklists": ["tracking"] } },
+///   "users": { "bob": ["tracking"] },
+///   "action": "sinkhole",
+///   "sinkhole_v4": "0.0.0.0",
+///   "sinkhole_v6": "::"
+/// }
+/// ```
+#[derive(Debug, Default, Deserialize)]
+struct DnsFilterPolicy {
+    #[serde(default)]
+    blocklists: HashMap<String, PathBuf>,
+    #[serde(default)]
+    default: Vec<String>,
+    #[serde(default)]
+    groups: HashMap<String, DnsFilterGroup>,
+    #[serde(default)]
+    users: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    action: DnsFilterAction,
+    sinkhole_v4: Option<Ipv4Addr>,
+    sinkhole_v6: Option<Ipv6Addr>,
+}
+
+#[derive(Default)]
+struct DnsFilterState {
+    policy: DnsFilterPolicy,
+    blocklists: HashMap<String, DnsBlocklist>,
+}
+
+/// Per-user domain filtering. Users get the default blocklists plus those of
+/// their groups and their own; `reload` re-reads the policy and every list.
+pub struct DnsFilter {
+    path: Option<PathBuf>,
+    state: RwLock<DnsFilterState>,
+}
+
+impl DnsFilter {
+    /// Filter without any blocklist.
+    pub fn disabled() -> Self {
+        Self {
+            path: None,
+            state: RwLock::new(DnsFilterState::default()),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error + Send + Sync>> {
+        let filter = Self {
+            path: Some(PathBuf::from(path)),
+            state: RwLock::new(DnsFilterState::default()),
+        };
+
+        filter.reload()?;
+        Ok(filter)
+    }
+
+    /// Reads `DNS_FILTER_POLICY`, filtering is off when unset.
+    pub fn from_env() -> Self {
+        match std::env::var("DNS_FILTER_POLICY") {
+            Ok(path) => Self::load(&path)
+                .unwrap_or_else(|err| panic!("Failed load DNS filter policy {path}: {err}")),
+            Err(_) => Self::disabled(),
+        }
+    }
+
+    /// Re-reads the policy and its blocklists, keeping the current ones on error.
+    pub fn reload(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let policy = serde_json::from_slice::<DnsFilterPolicy>(&std::fs::read(path)?)?;
+        let mut blocklists = HashMap::new();
+
+        for (name, path) in &policy.blocklists {
+            let text = std::fs::read_to_string(path)
+                .map_err(|err| format!("blocklist {name} ({}): {err}", path.display()))?;
+
+            let blocklist = DnsBlocklist::parse(&text);
+            log::info!("DNS blocklist {name}: {} domains", blocklist.len());
+
+            blocklists.insert(name.clone(), blocklist);
+        }
+
+        *self.state.write().unwrap() = DnsFilterState {
+            policy,
+            blocklists,
+        };
+
+        Ok(())
+    }
+
+    /// Name of the first blocklist applying to `user` that blocks `name`.
+    pub fn blocked_by(&self, user: &User, name: &str) -> Option<String> {
+        let state = self.state.read().unwrap();
+        let policy = &state.policy;
+
+        let groups = policy.groups.values()
+            .filter(|group| group.users.contains(&user.username))
+            .flat_map(|group| group.blocklists.iter());
+
+        let own = policy.users.get(&user.username).into_iter().flatten();
+
+        policy.default.iter()
+            .chain(groups)
+            .chain(own)
+            .find(|blocklist| state.blocklists.get(*blocklist).is_some_and(|blocklist| blocklist.is_blocked(name)))
+            .cloned()
+    }
+
+    /// Answer to a blocked query according to the policy action.
+    pub fn blocked_answer(&self, query: &[u8], question: &DnsQuestion) -> Option<Vec<u8>> {
+        let state = self.state.read().unwrap();
+        let policy = &state.policy;
+
+        match (policy.action, question.qtype) {
+            (DnsFilterAction::Nxdomain, _) => dns_message::response(query, dns_message::RCODE_NXDOMAIN),
+            (DnsFilterAction::Sinkhole, dns_message::TYPE_A) => {
+                let sinkhole = policy.sinkhole_v4.unwrap_or(Ipv4Addr::UNSPECIFIED);
+                dns_message::answer(query, dns_message::TYPE_A, SINKHOLE_TTL, &sinkhole.octets())
+            }
+            (DnsFilterAction::Sinkhole, dns_message::TYPE_AAAA) => {
+                let sinkhole = policy.sinkhole_v6.unwrap_or(Ipv6Addr::UNSPECIFIED);
+                dns_message::answer(query, dns_message::TYPE_AAAA, SINKHOLE_TTL, &sinkhole.octets())
+            }
+            (DnsFilterAction::Sinkhole, _) => dns_message::response(query, dns_message::RCODE_NOERROR),
+        }
+    }
+}
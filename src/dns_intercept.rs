@@ -0,0 +1,423 @@
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::sync::mpsc;
+use crate::dns::Dns;
+use crate::dns_message;
+use crate::user::User;
+
+const PROTOCOL_TCP: u8 = 6;
+const PROTOCOL_UDP: u8 = 17;
+const DNS_PORT: u16 = 53;
+
+const TCP_FIN: u8 = 0x01;
+const TCP_SYN: u8 = 0x02;
+const TCP_RST: u8 = 0x04;
+const TCP_PSH: u8 = 0x08;
+const TCP_ACK: u8 = 0x10;
+
+/// Segment size assumed when the SYN carries no MSS option (RFC 879).
+const TCP_DEFAULT_MSS: u16 = 536;
+
+/// TCP flows idle for longer are dropped once the flow table is full.
+const TCP_FLOW_IDLE: Duration = Duration::from_secs(30);
+const TCP_FLOWS_MAX: usize = 4096;
+
+const REPLIES_CAPACITY: usize = 1024;
+
+/// Client and server ends of an intercepted flow.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+struct Flow {
+    client: SocketAddrV4,
+    server: SocketAddrV4,
+}
+
+/// Just enough TCP to take length-framed queries and send the answers back.
+/// Our segments are not retransmitted: a client missing an answer retries the query.
+struct TcpFlow {
+    /// Tells the flow apart from earlier ones between the same ends,
+    /// whose answers may still be resolving.
+    generation: u64,
+    client_next: u32,
+    server_next: u32,
+    mss: u16,
+    buffer: Vec<u8>,
+    pending: usize,
+    closing: bool,
+    last_seen: Instant,
+}
+
+/// Answers port 53 IPv4 packets of sessions through the DNS proxy, with its
+/// zone, filter, cache and query log, instead of routing them to the internet.
+/// Replies are synthesized IP packets picked up by `TunnelTransmitter`.
+pub struct DnsInterceptor {
+    dns: Arc<Dns>,
+    mtu: u16,
+    tcp_flows: Mutex<HashMap<Flow, TcpFlow>>,
+    tcp_generation: AtomicU64,
+    replies_tx: mpsc::Sender<Vec<u8>>,
+    replies_rx: tokio::sync::Mutex<mpsc::Receiver<Vec<u8>>>,
+    rng: SystemRandom,
+}
+
+impl DnsInterceptor {
+    pub fn new(dns: Arc<Dns>, mtu: u16) -> Self {
+        let (replies_tx, replies_rx) = mpsc::channel(REPLIES_CAPACITY);
+
+        Self {
+            dns,
+            mtu,
+            tcp_flows: Mutex::new(HashMap::new()),
+            tcp_generation: AtomicU64::new(0),
+            replies_tx,
+            replies_rx: tokio::sync::Mutex::new(replies_rx),
+            rng: SystemRandom::new(),
+        }
+    }
+
+    /// Next synthesized reply, addressed to a session's tunnel address.
+    pub async fn next_reply(&self) -> Option<Vec<u8>> {
+        self.replies_rx.lock().await.recv().await
+    }
+
+    /// Takes over the packet when it is a DNS query from `tunnel_address`,
+    /// `false` leaves it to the tunnel.
+    pub fn intercept(self: &Arc<Self>, user: &User, tunnel_address: Ipv4Addr, packet: &[u8]) -> bool {
+        let Some((protocol, source, destination, payload)) = parse_ipv4(packet) else {
+            return false;
+        };
+
+        if source != tunnel_address || payload.len() < 4 {
+            return false;
+        }
+
+        let flow = Flow {
+            client: SocketAddrV4::new(source, u16::from_be_bytes([payload[0], payload[1]])),
+            server: SocketAddrV4::new(destination, u16::from_be_bytes([payload[2], payload[3]])),
+        };
+
+        if flow.server.port() != DNS_PORT {
+            return false;
+        }
+
+        match protocol {
+            PROTOCOL_UDP => self.intercept_udp(user, flow, payload),
+            PROTOCOL_TCP => self.intercept_tcp(user, flow, payload),
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn intercept_udp(self: &Arc<Self>, user: &User, flow: Flow, datagram: &[u8]) {
+        if datagram.len() < 8 {
+            return;
+        }
+
+        let end = (u16::from_be_bytes([datagram[4], datagram[5]]) as usize).clamp(8, datagram.len());
+        let query = datagram[8..end].to_vec();
+
+        if dns_message::question(&query).is_none() {
+            return;
+        }
+
+        let interceptor = self.clone();
+        let user = user.clone();
+
+        tokio::task::spawn(async move {
+            let Some(answer) = interceptor.dns.resolve(&user, SocketAddr::V4(flow.client), &query).await else {
+                return;
+            };
+
+            // unfragmented, the answer has to fit one tunnel packet beside the IP and UDP headers.
+            let limit = dns_message::udp_payload_size(&query).min(interceptor.mtu.saturating_sub(28) as usize);
+
+            let answer = match dns_message::truncated(&answer) {
+                Some(truncated) if answer.len() > limit => truncated,
+                _ => answer,
+            };
+
+            interceptor.reply(udp_packet(flow.server, flow.client, &answer));
+        });
+    }
+
+    fn intercept_tcp(self: &Arc<Self>, user: &User, flow: Flow, segment: &[u8]) {
+        if segment.len() < 20 {
+            return;
+        }
+
+        let seq = u32::from_be_bytes([segment[4], segment[5], segment[6], segment[7]]);
+        let data_offset = (segment[12] >> 4) as usize * 4;
+        let flags = segment[13];
+
+        if data_offset < 20 || data_offset > segment.len() {
+            return;
+        }
+
+        let data = &segment[data_offset..];
+        let mut flows = self.tcp_flows.lock().unwrap();
+
+        if flags & TCP_RST != 0 {
+            flows.remove(&flow);
+            return;
+        }
+
+        if flags & TCP_SYN != 0 {
+            if flows.len() >= TCP_FLOWS_MAX {
+                flows.retain(|_, tcp_flow| tcp_flow.last_seen.elapsed() < TCP_FLOW_IDLE);
+
+                if flows.len() >= TCP_FLOWS_MAX {
+                    log::warn!("Too many intercepted DNS TCP flows, dropping SYN from {}", flow.client);
+                    return;
+                }
+            }
+
+            let mut isn = [0u8; 4];
+            self.rng.fill(&mut isn).unwrap();
+            let isn = u32::from_be_bytes(isn);
+
+            let mss = tcp_mss(&segment[20..data_offset])
+                .unwrap_or(TCP_DEFAULT_MSS)
+                .min(self.mtu.saturating_sub(40))
+                .max(1);
+
+            flows.insert(flow, TcpFlow {
+                generation: self.tcp_generation.fetch_add(1, Ordering::Relaxed),
+                client_next: seq.wrapping_add(1),
+                server_next: isn.wrapping_add(1),
+                mss,
+                buffer: Vec::new(),
+                pending: 0,
+                closing: false,
+                last_seen: Instant::now(),
+            });
+
+            self.reply(tcp_packet(flow.server, flow.client, isn, seq.wrapping_add(1), TCP_SYN | TCP_ACK, &[]));
+            return;
+        }
+
+        let Some(tcp_flow) = flows.get_mut(&flow) else {
+            return;
+        };
+
+        tcp_flow.last_seen = Instant::now();
+
+        if data.is_empty() && flags & TCP_FIN == 0 {
+            return;
+        }
+
+        // retransmitted or out of order, acknowledge what we are still waiting for.
+        if seq != tcp_flow.client_next {
+            self.reply(tcp_packet(flow.server, flow.client, tcp_flow.server_next, tcp_flow.client_next, TCP_ACK, &[]));
+            return;
+        }
+
+        tcp_flow.client_next = tcp_flow.client_next.wrapping_add(data.len() as u32);
+        tcp_flow.buffer.extend_from_slice(data);
+
+        let mut queries = Vec::new();
+
+        while tcp_flow.buffer.len() >= 2 {
+            let len = u16::from_be_bytes([tcp_flow.buffer[0], tcp_flow.buffer[1]]) as usize;
+
+            if tcp_flow.buffer.len() < 2 + len {
+                break;
+            }
+
+            queries.push(tcp_flow.buffer[2..2 + len].to_vec());
+            tcp_flow.buffer.drain(..2 + len);
+        }
+
+        tcp_flow.pending += queries.len();
+        let generation = tcp_flow.generation;
+
+        if flags & TCP_FIN != 0 {
+            tcp_flow.client_next = tcp_flow.client_next.wrapping_add(1);
+            tcp_flow.closing = true;
+        }
+
+        if tcp_flow.closing && tcp_flow.pending == 0 {
+            self.reply(tcp_packet(flow.server, flow.client, tcp_flow.server_next, tcp_flow.client_next, TCP_FIN | TCP_ACK, &[]));
+            flows.remove(&flow);
+        } else {
+            self.reply(tcp_packet(flow.server, flow.client, tcp_flow.server_next, tcp_flow.client_next, TCP_ACK, &[]));
+        }
+
+        drop(flows);
+
+        for query in queries {
+            let interceptor = self.clone();
+            let user = user.clone();
+
+            tokio::task::spawn(async move {
+                let answer = interceptor.dns.resolve(&user, SocketAddr::V4(flow.client), &query).await;
+                interceptor.answer_tcp(flow, generation, answer);
+            });
+        }
+    }
+
+    /// Sends the framed answer in MSS sized segments, closing the flow
+    /// once the client closed it and nothing is pending any more. Answers
+    /// to a flow that was reset or reopened meanwhile are dropped.
+    fn answer_tcp(&self, flow: Flow, generation: u64, answer: Option<Vec<u8>>) {
+        let mut flows = self.tcp_flows.lock().unwrap();
+
+        let Some(tcp_flow) = flows.get_mut(&flow).filter(|tcp_flow| tcp_flow.generation == generation) else {
+            return;
+        };
+
+        tcp_flow.pending -= 1;
+
+        if let Some(answer) = answer {
+            let mut frame = (answer.len() as u16).to_be_bytes().to_vec();
+            frame.extend_from_slice(&answer);
+
+            for chunk in frame.chunks(tcp_flow.mss as usize) {
+                self.reply(tcp_packet(flow.server, flow.client, tcp_flow.server_next, tcp_flow.client_next, TCP_ACK | TCP_PSH, chunk));
+                tcp_flow.server_next = tcp_flow.server_next.wrapping_add(chunk.len() as u32);
+            }
+        }
+
+        if tcp_flow.closing && tcp_flow.pending == 0 {
+            self.reply(tcp_packet(flow.server, flow.client, tcp_flow.server_next, tcp_flow.client_next, TCP_FIN | TCP_ACK, &[]));
+            flows.remove(&flow);
+        }
+    }
+
+    fn reply(&self, packet: Vec<u8>) {
+        if self.replies_tx.try_send(packet).is_err() {
+            log::warn!("Too many intercepted DNS replies queued, dropping one");
+        }
+    }
+}
+
+/// Protocol, source, destination and payload of an unfragmented IPv4 packet.
+fn parse_ipv4(packet: &[u8]) -> Option<(u8, Ipv4Addr, Ipv4Addr, &[u8])> {
+    if packet.len() < 20 || packet[0] >> 4 != 4 {
+        return None;
+    }
+
+    let header_len = (packet[0] & 0x0f) as usize * 4;
+    let total_len = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+
+    if header_len < 20 || total_len < header_len || total_len > packet.len() {
+        return None;
+    }
+
+    // fragments carry no or partial transport headers, leave them to the tunnel.
+    if u16::from_be_bytes([packet[6], packet[7]]) & 0x3fff != 0 {
+        return None;
+    }
+
+    Some((
+        packet[9],
+        Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]),
+        Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]),
+        &packet[header_len..total_len],
+    ))
+}
+
+/// MSS option (kind 2) of a SYN's options.
+fn tcp_mss(mut options: &[u8]) -> Option<u16> {
+    while let [kind, rest @ ..] = options {
+        match kind {
+            0 => return None,
+            1 => options = rest,
+            _ => {
+                let len = *rest.first()? as usize;
+
+                if len < 2 || len > options.len() {
+                    return None;
+                }
+
+                if *kind == 2 && len == 4 {
+                    return Some(u16::from_be_bytes([options[2], options[3]]));
+                }
+
+                options = &options[len..];
+            }
+        }
+    }
+
+    None
+}
+
+fn ipv4_packet(source: Ipv4Addr, destination: Ipv4Addr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0u8; 20];
+    packet[0] = 0x45;
+    packet[2..4].copy_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+    // don't fragment
+    packet[6] = 0x40;
+    packet[8] = 64;
+    packet[9] = protocol;
+    packet[12..16].copy_from_slice(&source.octets());
+    packet[16..20].copy_from_slice(&destination.octets());
+
+    let header_checksum = checksum(&packet, 0);
+    packet[10..12].copy_from_slice(&header_checksum.to_be_bytes());
+
+    packet.extend_from_slice(payload);
+    packet
+}
+
+fn udp_packet(source: SocketAddrV4, destination: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = Vec::with_capacity(8 + payload.len());
+    datagram.extend_from_slice(&source.port().to_be_bytes());
+    datagram.extend_from_slice(&destination.port().to_be_bytes());
+    datagram.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+
+    // zero means no checksum for UDP, an all-ones sum is sent instead.
+    let udp_checksum = match transport_checksum(*source.ip(), *destination.ip(), PROTOCOL_UDP, &datagram) {
+        0 => 0xffff,
+        udp_checksum => udp_checksum,
+    };
+
+    datagram[6..8].copy_from_slice(&udp_checksum.to_be_bytes());
+    ipv4_packet(*source.ip(), *destination.ip(), PROTOCOL_UDP, &datagram)
+}
+
+fn tcp_packet(source: SocketAddrV4, destination: SocketAddrV4, seq: u32, ack: u32, flags: u8, data: &[u8]) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(20 + data.len());
+    segment.extend_from_slice(&source.port().to_be_bytes());
+    segment.extend_from_slice(&destination.port().to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.extend_from_slice(&[5 << 4, flags]);
+    segment.extend_from_slice(&u16::MAX.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    segment.extend_from_slice(data);
+
+    let tcp_checksum = transport_checksum(*source.ip(), *destination.ip(), PROTOCOL_TCP, &segment);
+    segment[16..18].copy_from_slice(&tcp_checksum.to_be_bytes());
+
+    ipv4_packet(*source.ip(), *destination.ip(), PROTOCOL_TCP, &segment)
+}
+
+/// UDP/TCP checksum over the IPv4 pseudo header and the segment.
+fn transport_checksum(source: Ipv4Addr, destination: Ipv4Addr, protocol: u8, segment: &[u8]) -> u16 {
+    let mut pseudo_header = Vec::with_capacity(12);
+    pseudo_header.extend_from_slice(&source.octets());
+    pseudo_header.extend_from_slice(&destination.octets());
+    pseudo_header.extend_from_slice(&[0, protocol]);
+    pseudo_header.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+
+    checksum(segment, !checksum(&pseudo_header, 0) as u32)
+}
+
+/// Internet checksum (RFC 1071) of `data`, continuing the partial `sum`.
+fn checksum(data: &[u8], mut sum: u32) -> u16 {
+    for word in data.chunks(2) {
+        sum += u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32;
+    }
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
@@ -0,0 +1,250 @@
+use std::ops::Range;
+
+/// Fixed DNS header (RFC 1035 4.1.1): id, flags and the four section counts.
+pub const DNS_HEADER_LEN: usize = 12;
+
+pub const RCODE_NOERROR: u8 = 0;
+pub const RCODE_SERVFAIL: u8 = 2;
+pub const RCODE_NXDOMAIN: u8 = 3;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_SOA: u16 = 6;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_OPT: u16 = 41;
+
+/// Largest UDP answer a client without EDNS0 accepts (RFC 1035 4.2.1).
+pub const UDP_PAYLOAD_MIN: usize = 512;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DnsSection {
+    Answer,
+    Authority,
+    Additional,
+}
+
+/// Resource record located in a message, see `records`.
+#[derive(Debug, Clone)]
+pub struct DnsRecord {
+    pub section: DnsSection,
+    pub rtype: u16,
+    pub ttl: u32,
+    /// Offset of the TTL field, for `set_ttl`.
+    pub ttl_offset: usize,
+    pub rdata: Range<usize>,
+}
+
+/// First entry of the question section.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct DnsQuestion {
+    /// Lowercase, dot separated, without the trailing dot.
+    pub name: String,
+    pub qtype: u16,
+    pub qclass: u16,
+}
+
+pub fn id(message: &[u8]) -> Option<u16> {
+    Some(u16::from_be_bytes(message.get(..2)?.try_into().ok()?))
+}
+
+pub fn set_id(message: &mut [u8], id: u16) {
+    message[..2].copy_from_slice(&id.to_be_bytes());
+}
+
+pub fn rcode(message: &[u8]) -> Option<u8> {
+    Some(message.get(3)? & 0x0f)
+}
+
+/// AA flag: the answer comes from the zone's own data.
+pub fn set_authoritative(message: &mut [u8]) {
+    message[2] |= 0x04;
+}
+
+/// TC flag: the answer did not fit and was cut short.
+pub fn is_truncated(message: &[u8]) -> bool {
+    message.get(2).is_some_and(|flags| flags & 0x02 != 0)
+}
+
+/// Largest UDP answer the sender of `query` accepts: the payload size of its
+/// EDNS0 OPT record (RFC 6891 6.2.3), `UDP_PAYLOAD_MIN` without one.
+pub fn udp_payload_size(query: &[u8]) -> usize {
+    let opt = records(query)
+        .unwrap_or_default()
+        .into_iter()
+        .find(|record| record.section == DnsSection::Additional && record.rtype == TYPE_OPT);
+
+    // the OPT record's class field carries the payload size.
+    let payload_size = opt.and_then(|record| query.get(record.ttl_offset - 2..record.ttl_offset))
+        .map_or(0, |class| u16::from_be_bytes([class[0], class[1]]) as usize);
+
+    payload_size.max(UDP_PAYLOAD_MIN)
+}
+
+/// The answer cut down to its header and first question with TC set,
+/// telling the client to retry over TCP.
+pub fn truncated(answer: &[u8]) -> Option<Vec<u8>> {
+    let (_, question_end) = question(answer)?;
+
+    let mut message = answer[..question_end].to_vec();
+    message[2] |= 0x02;
+    message[4..6].copy_from_slice(&1u16.to_be_bytes());
+    message[6..12].fill(0);
+
+    Some(message)
+}
+
+pub fn set_ttl(message: &mut [u8], ttl_offset: usize, ttl: u32) {
+    message[ttl_offset..ttl_offset + 4].copy_from_slice(&ttl.to_be_bytes());
+}
+
+fn count(message: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([message[offset], message[offset + 1]])
+}
+
+/// Parses the first question, returns it with the offset right after it.
+pub fn question(message: &[u8]) -> Option<(DnsQuestion, usize)> {
+    if message.len() < DNS_HEADER_LEN || count(message, 4) == 0 {
+        return None;
+    }
+
+    let (name, offset) = read_name(message, DNS_HEADER_LEN)?;
+    let fixed = message.get(offset..offset + 4)?;
+
+    let question = DnsQuestion {
+        name,
+        qtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+        qclass: u16::from_be_bytes([fixed[2], fixed[3]]),
+    };
+
+    Some((question, offset + 4))
+}
+
+/// Uncompressed wire encoding of a dot separated name.
+pub fn encode_name(name: &str) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(name.len() + 2);
+
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        encoded.push(label.len().min(63) as u8);
+        encoded.extend_from_slice(&label.as_bytes()[..label.len().min(63)]);
+    }
+
+    encoded.push(0);
+    encoded
+}
+
+/// Walks the answer, authority and additional sections.
+pub fn records(message: &[u8]) -> Option<Vec<DnsRecord>> {
+    if message.len() < DNS_HEADER_LEN {
+        return None;
+    }
+
+    let answers = count(message, 6) as usize;
+    let authorities = count(message, 8) as usize;
+    let additionals = count(message, 10) as usize;
+
+    let mut offset = DNS_HEADER_LEN;
+
+    for _ in 0..count(message, 4) {
+        offset = read_name(message, offset)?.1 + 4;
+    }
+
+    // the counts are the sender's claim, a record takes at least 11 bytes.
+    let mut records = Vec::with_capacity((answers + authorities + additionals).min(message.len() / 11));
+
+    for index in 0..answers + authorities + additionals {
+        let (_, name_end) = read_name(message, offset)?;
+        let fixed = message.get(name_end..name_end + 10)?;
+
+        let rdata_start = name_end + 10;
+        let rdata_end = rdata_start + u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+
+        if rdata_end > message.len() {
+            return None;
+        }
+
+        records.push(DnsRecord {
+            section: match index {
+                index if index < answers => DnsSection::Answer,
+                index if index < answers + authorities => DnsSection::Authority,
+                _ => DnsSection::Additional,
+            },
+            rtype: u16::from_be_bytes([fixed[0], fixed[1]]),
+            ttl: u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]),
+            ttl_offset: name_end + 4,
+            rdata: rdata_start..rdata_end,
+        });
+
+        offset = rdata_end;
+    }
+
+    Some(records)
+}
+
+/// Reads a possibly compressed name at `offset`, returns it with the offset
+/// right after its encoding in place.
+pub fn read_name(message: &[u8], mut offset: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    let mut jumps = 0;
+
+    loop {
+        let length = *message.get(offset)? as usize;
+
+        match length & 0xc0 {
+            0x00 if length == 0 => {
+                return Some((labels.join("."), end.unwrap_or(offset + 1)));
+            }
+            0x00 => {
+                let label = message.get(offset + 1..offset + 1 + length)?;
+                labels.push(String::from_utf8_lossy(label).to_ascii_lowercase());
+                offset += 1 + length;
+            }
+            0xc0 => {
+                jumps += 1;
+
+                if jumps > 16 {
+                    return None;
+                }
+
+                let pointer = (length & 0x3f) << 8 | *message.get(offset + 1)? as usize;
+                end.get_or_insert(offset + 2);
+                offset = pointer;
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Answer carrying only the query's first question, e.g. SERVFAIL when no
+/// upstream could be reached.
+pub fn response(query: &[u8], rcode: u8) -> Option<Vec<u8>> {
+    let (_, question_end) = question(query)?;
+
+    let mut message = query[..question_end].to_vec();
+
+    // QR, the query's opcode and RD, RA.
+    message[2] = 0x80 | (query[2] & 0x79);
+    message[3] = 0x80 | (rcode & 0x0f);
+    message[4..6].copy_from_slice(&1u16.to_be_bytes());
+    message[6..12].fill(0);
+
+    Some(message)
+}
+
+/// NOERROR answer to the query's first question with a single record.
+pub fn answer(query: &[u8], rtype: u16, ttl: u32, rdata: &[u8]) -> Option<Vec<u8>> {
+    let (question, _) = question(query)?;
+    let mut message = response(query, RCODE_NOERROR)?;
+
+    message[6..8].copy_from_slice(&1u16.to_be_bytes());
+
+    // the owner name points at the question name right after the header.
+    message.extend_from_slice(&[0xc0, DNS_HEADER_LEN as u8]);
+    message.extend_from_slice(&rtype.to_be_bytes());
+    message.extend_from_slice(&question.qclass.to_be_bytes());
+    message.extend_from_slice(&ttl.to_be_bytes());
+    message.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    message.extend_from_slice(rdata);
+
+    Some(message)
+}
@@ -0,0 +1,259 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::time::Duration;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+use crate::dns_message;
+use crate::user::User;
+
+/// How query names appear in the log.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum DnsQueryLogNames {
+    #[default]
+    Plain,
+    /// HMAC-SHA256 of the name: equal names stay correlatable, the names do not show.
+    Hash,
+    /// Names are left out.
+    Off,
+}
+
+impl FromStr for DnsQueryLogNames {
+    type Err = String;
+
+    fn from_str(names: &str) -> Result<Self, Self::Err> {
+        match names {
+            "plain" => Ok(Self::Plain),
+            "hash" => Ok(Self::Hash),
+            "off" => Ok(Self::Off),
+            names => Err(format!("unknown query log names mode {names}")),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DnsQueryLogEntry<'a> {
+    timestamp: String,
+    user: &'a str,
+    source: SocketAddr,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(rename = "type")]
+    qtype: Option<u16>,
+    rcode: Option<u8>,
+    /// Milliseconds spent waiting for the upstreams, absent for local answers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    upstream_ms: Option<u64>,
+}
+
+/// Lines waiting for the writer thread; entries beyond it are dropped.
+const QUEUE_CAPACITY: usize = 4096;
+
+enum DnsQueryLogMessage {
+    Line(Vec<u8>),
+    Flush(mpsc::Sender<()>),
+}
+
+struct DnsQueryLogFile {
+    file: File,
+    size: u64,
+}
+
+/// Answered queries as JSON lines. The file is rotated to `path.1` .. `path.N`
+/// once it would grow beyond `max_size` bytes.
+///
+/// Lines are written by a dedicated thread, so resolving never waits on the
+/// disk; when it falls behind by `QUEUE_CAPACITY` lines, entries are dropped
+/// and counted instead.
+pub struct DnsQueryLog {
+    names: DnsQueryLogNames,
+    hash_key: hmac::Key,
+    lines: SyncSender<DnsQueryLogMessage>,
+    dropped: AtomicU64,
+}
+
+/// Owns the log file on the writer thread.
+struct DnsQueryLogWriter {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: DnsQueryLogFile,
+}
+
+impl DnsQueryLog {
+    pub fn open(path: &str, max_size: u64, max_files: usize, names: DnsQueryLogNames) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+
+        let mut key = [0u8; 32];
+        SystemRandom::new().fill(&mut key).unwrap();
+
+        let writer = DnsQueryLogWriter {
+            file: DnsQueryLogWriter::open_file(&path)?,
+            path,
+            max_size,
+            max_files,
+        };
+
+        let (lines, queued) = mpsc::sync_channel(QUEUE_CAPACITY);
+
+        std::thread::Builder::new()
+            .name(String::from("dns-query-log"))
+            .spawn(move || writer.run(queued))?;
+
+        Ok(Self {
+            names,
+            hash_key: hmac::Key::new(hmac::HMAC_SHA256, &key),
+            lines,
+            dropped: AtomicU64::new(0),
+        })
+    }
+
+    /// Hashes names with `key` instead of a key random to this process,
+    /// so hashes can be matched across restarts.
+    pub fn with_hash_key(mut self, key: &[u8]) -> Self {
+        self.hash_key = hmac::Key::new(hmac::HMAC_SHA256, key);
+        self
+    }
+
+    /// Reads `DNS_QUERY_LOG` (file path, no query log when unset),
+    /// `DNS_QUERY_LOG_MAX_SIZE` (bytes, default 10 MiB), `DNS_QUERY_LOG_FILES`
+    /// (rotated files kept, default 5), `DNS_QUERY_LOG_NAMES` (`plain`, `hash`
+    /// or `off`) and `DNS_QUERY_LOG_HASH_KEY`.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("DNS_QUERY_LOG").ok()?;
+
+        let max_size = std::env::var("DNS_QUERY_LOG_MAX_SIZE")
+            .ok()
+            .map(|max_size| max_size.parse::<u64>().expect("Failed parse DNS_QUERY_LOG_MAX_SIZE."))
+            .unwrap_or(10 * 1024 * 1024);
+
+        let max_files = std::env::var("DNS_QUERY_LOG_FILES")
+            .ok()
+            .map(|max_files| max_files.parse::<usize>().expect("Failed parse DNS_QUERY_LOG_FILES."))
+            .unwrap_or(5);
+
+        let names = std::env::var("DNS_QUERY_LOG_NAMES")
+            .ok()
+            .map(|names| names.parse::<DnsQueryLogNames>().expect("Failed parse DNS_QUERY_LOG_NAMES."))
+            .unwrap_or_default();
+
+        let query_log = Self::open(&path, max_size, max_files, names)
+            .unwrap_or_else(|err| panic!("Failed open DNS query log {path}: {err}"));
+
+        Some(match std::env::var("DNS_QUERY_LOG_HASH_KEY") {
+            Ok(key) => query_log.with_hash_key(key.as_bytes()),
+            Err(_) => query_log,
+        })
+    }
+
+    /// Records the answer to `query` from `user`, `upstream` being the
+    /// time spent on the upstreams when it was forwarded.
+    pub fn record(&self, user: &User, source: SocketAddr, query: &[u8], answer: &[u8], upstream: Option<Duration>) {
+        let question = dns_message::question(query).map(|(question, _)| question);
+
+        let name = question.as_ref().and_then(|question| match self.names {
+            DnsQueryLogNames::Plain => Some(question.name.clone()),
+            DnsQueryLogNames::Hash => Some(hex(hmac::sign(&self.hash_key, question.name.as_bytes()).as_ref())),
+            DnsQueryLogNames::Off => None,
+        });
+
+        let entry = DnsQueryLogEntry {
+            timestamp: OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            user: &user.username,
+            source,
+            name,
+            qtype: question.map(|question| question.qtype),
+            rcode: dns_message::rcode(answer),
+            upstream_ms: upstream.map(|upstream| upstream.as_millis() as u64),
+        };
+
+        let Ok(mut line) = serde_json::to_vec(&entry) else {
+            return;
+        };
+
+        line.push(b'\n');
+
+        if let Err(TrySendError::Full(_)) = self.lines.try_send(DnsQueryLogMessage::Line(line)) {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Entries dropped so far because the writer thread fell behind.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Blocks until the lines recorded so far are written.
+    pub fn flush(&self) {
+        let (done, flushed) = mpsc::channel();
+
+        if self.lines.send(DnsQueryLogMessage::Flush(done)).is_ok() {
+            flushed.recv().ok();
+        }
+    }
+}
+
+impl DnsQueryLogWriter {
+    fn open_file(path: &Path) -> io::Result<DnsQueryLogFile> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+
+        Ok(DnsQueryLogFile { file, size })
+    }
+
+    /// Writes queued lines until the `DnsQueryLog` is dropped.
+    fn run(mut self, queued: Receiver<DnsQueryLogMessage>) {
+        for message in queued {
+            match message {
+                DnsQueryLogMessage::Line(line) => {
+                    if let Err(err) = self.write(&line) {
+                        log::error!("Failed write DNS query log {}: {err}", self.path.display());
+                    }
+                }
+                DnsQueryLogMessage::Flush(done) => {
+                    done.send(()).ok();
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.file.size > 0 && self.file.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+            self.file = Self::open_file(&self.path)?;
+        }
+
+        self.file.file.write_all(line)?;
+        self.file.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    /// Shifts `path.N-1` to `path.N` down to `path` to `path.1`, dropping the oldest.
+    fn rotate(&self) -> io::Result<()> {
+        let rotated = |index: usize| PathBuf::from(format!("{}.{index}", self.path.display()));
+
+        if self.max_files == 0 {
+            return std::fs::remove_file(&self.path);
+        }
+
+        for index in (1..self.max_files).rev() {
+            if rotated(index).exists() {
+                std::fs::rename(rotated(index), rotated(index + 1))?;
+            }
+        }
+
+        std::fs::rename(&self.path, rotated(1))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
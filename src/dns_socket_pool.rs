@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::net::UdpSocket;
+use tokio::sync::oneshot;
+use crate::dns_message::{self, DnsQuestion};
+
+/// Queries sent from one socket before it is replaced by a freshly bound one,
+/// keeping the source port unpredictable.
+const ROTATE_AFTER: u32 = 1024;
+
+/// How often a retired socket checks whether its last query is settled.
+const RETIRE_CHECK: Duration = Duration::from_secs(1);
+
+struct Pending {
+    upstream: SocketAddr,
+    question: Option<DnsQuestion>,
+    answer: oneshot::Sender<Vec<u8>>,
+}
+
+struct PooledSocket {
+    socket: UdpSocket,
+    pending: Mutex<HashMap<u16, Pending>>,
+    queries: AtomicU32,
+    retired: AtomicBool,
+}
+
+impl PooledSocket {
+    /// Delivers answers to their pending queries until the socket is retired
+    /// and nothing is pending on it any more.
+    async fn receive(self: Arc<Self>) {
+        let mut buf = [0u8; 65535];
+
+        loop {
+            if self.retired.load(Ordering::Relaxed) && self.pending.lock().unwrap().is_empty() {
+                return;
+            }
+
+            let received = tokio::select! {
+                received = self.socket.recv_from(&mut buf) => received,
+                _ = tokio::time::sleep(RETIRE_CHECK) => continue,
+            };
+
+            let Ok((n, sock_addr)) = received else {
+                continue;
+            };
+
+            let Some(id) = dns_message::id(&buf[..n]) else {
+                continue;
+            };
+
+            let mut pending = self.pending.lock().unwrap();
+
+            // answers must come from the asked upstream and repeat the question,
+            // anything else is a late, stray or spoofed datagram.
+            let Some(query) = pending.get(&id) else {
+                continue;
+            };
+
+            if query.upstream != sock_addr {
+                continue;
+            }
+
+            if query.question.is_some() && dns_message::question(&buf[..n]).map(|(question, _)| question) != query.question {
+                continue;
+            }
+
+            if let Some(query) = pending.remove(&id) {
+                query.answer.send(buf[..n].to_vec()).ok();
+            }
+        }
+    }
+}
+
+/// Removes a pending query when its exchange ends, answered or not.
+struct PendingGuard {
+    socket: Arc<PooledSocket>,
+    id: u16,
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.socket.pending.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Fixed set of upstream sockets shared by all queries. Each query leaves from
+/// a random socket under a random transaction id mapped back to the caller's,
+/// sockets are rebound every `ROTATE_AFTER` queries.
+pub struct DnsSocketPool {
+    bind_addr: SocketAddr,
+    slots: Vec<tokio::sync::Mutex<Option<Arc<PooledSocket>>>>,
+    rng: SystemRandom,
+}
+
+impl DnsSocketPool {
+    pub fn new(bind_addr: SocketAddr, size: usize) -> Self {
+        Self {
+            bind_addr,
+            slots: (0..size.max(1)).map(|_| tokio::sync::Mutex::new(None)).collect(),
+            rng: SystemRandom::new(),
+        }
+    }
+
+    fn random_u16(&self) -> u16 {
+        let mut bytes = [0u8; 2];
+        self.rng.fill(&mut bytes).unwrap();
+        u16::from_be_bytes(bytes)
+    }
+
+    /// Socket of a random slot, bound on first use and after rotation.
+    async fn socket(&self) -> io::Result<Arc<PooledSocket>> {
+        let mut slot = self.slots[self.random_u16() as usize % self.slots.len()].lock().await;
+
+        if let Some(socket) = slot.as_ref() {
+            if socket.queries.fetch_add(1, Ordering::Relaxed) < ROTATE_AFTER {
+                return Ok(socket.clone());
+            }
+
+            socket.retired.store(true, Ordering::Relaxed);
+        }
+
+        let socket = Arc::new(PooledSocket {
+            socket: UdpSocket::bind(self.bind_addr).await?,
+            pending: Mutex::new(HashMap::new()),
+            queries: AtomicU32::new(1),
+            retired: AtomicBool::new(false),
+        });
+
+        tokio::spawn(socket.clone().receive());
+        *slot = Some(socket.clone());
+
+        Ok(socket)
+    }
+
+    /// Sends the query to `upstream` and waits for its answer, which carries
+    /// the query's original transaction id. Cancel-safe: dropping the future
+    /// forgets the query.
+    pub async fn exchange(&self, upstream: SocketAddr, query: &[u8]) -> io::Result<Vec<u8>> {
+        let original_id = dns_message::id(query)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "query without header"))?;
+
+        let socket = self.socket().await?;
+        let (answer_tx, answer_rx) = oneshot::channel();
+
+        let id = {
+            let mut pending = socket.pending.lock().unwrap();
+
+            if pending.len() > u16::MAX as usize / 2 {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, "upstream socket saturated"));
+            }
+
+            let id = loop {
+                let id = self.random_u16();
+
+                if !pending.contains_key(&id) {
+                    break id;
+                }
+            };
+
+            pending.insert(id, Pending {
+                upstream,
+                question: dns_message::question(query).map(|(question, _)| question),
+                answer: answer_tx,
+            });
+
+            id
+        };
+
+        let _guard = PendingGuard {
+            socket: socket.clone(),
+            id,
+        };
+
+        let mut query = query.to_vec();
+        dns_message::set_id(&mut query, id);
+
+        socket.socket.send_to(&query, upstream).await?;
+
+        let mut answer = answer_rx.await
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+
+        dns_message::set_id(&mut answer, original_id);
+        Ok(answer)
+    }
+}
@@ -0,0 +1,160 @@
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use crate::dns;
+use crate::dns_message;
+use crate::dns_socket_pool::DnsSocketPool;
+
+/// Consecutive failures after which an upstream is considered down.
+const FAILURE_THRESHOLD: u32 = 3;
+
+/// How long a down upstream is only tried after all healthy ones.
+const DOWN_PERIOD: Duration = Duration::from_secs(30);
+
+struct DnsUpstream {
+    addr: SocketAddr,
+    failures: AtomicU32,
+    down_until: Mutex<Option<Instant>>,
+}
+
+impl DnsUpstream {
+    fn is_down(&self) -> bool {
+        self.down_until.lock()
+            .unwrap()
+            .is_some_and(|down_until| down_until > Instant::now())
+    }
+
+    fn succeeded(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+        *self.down_until.lock().unwrap() = None;
+    }
+
+    fn failed(&self) {
+        if self.failures.fetch_add(1, Ordering::Relaxed) + 1 >= FAILURE_THRESHOLD {
+            let mut down_until = self.down_until.lock().unwrap();
+
+            if down_until.is_none() {
+                log::warn!("DNS upstream {} is down", self.addr);
+            }
+
+            *down_until = Some(Instant::now() + DOWN_PERIOD);
+        }
+    }
+}
+
+/// Upstream resolvers tried in order with a per-query timeout, failing over
+/// to the next one. Upstreams that keep failing are moved to the back for a while.
+/// Truncated UDP answers are asked again over TCP.
+pub struct DnsUpstreams {
+    upstreams: Vec<DnsUpstream>,
+    timeout: Duration,
+    pool_v4: DnsSocketPool,
+    pool_v6: DnsSocketPool,
+}
+
+impl DnsUpstreams {
+    pub fn new(upstreams: Vec<SocketAddr>, timeout: Duration) -> Self {
+        Self::with_sockets(upstreams, timeout, 16)
+    }
+
+    /// Same as `new`, with `sockets` shared upstream sockets per address family.
+    pub fn with_sockets(upstreams: Vec<SocketAddr>, timeout: Duration, sockets: usize) -> Self {
+        Self {
+            upstreams: upstreams.into_iter()
+                .map(|addr| DnsUpstream {
+                    addr,
+                    failures: AtomicU32::new(0),
+                    down_until: Mutex::new(None),
+                })
+                .collect(),
+            timeout,
+            pool_v4: DnsSocketPool::new(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0), sockets),
+            pool_v6: DnsSocketPool::new(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0), sockets),
+        }
+    }
+
+    /// Reads `DNS_UPSTREAMS` (comma separated, port 53 when omitted, default 1.1.1.1)
+    /// `DNS_UPSTREAM_TIMEOUT` (milliseconds per attempt, default 2000) and
+    /// `DNS_UPSTREAM_SOCKETS` (shared sockets per address family, default 16).
+    pub fn from_env() -> Self {
+        let upstreams = std::env::var("DNS_UPSTREAMS")
+            .unwrap_or_else(|_| String::from("1.1.1.1"))
+            .split(',')
+            .map(str::trim)
+            .filter(|upstream| !upstream.is_empty())
+            .map(|upstream| upstream.parse::<SocketAddr>()
+                .or_else(|_| upstream.parse().map(|ip| SocketAddr::new(ip, 53)))
+                .unwrap_or_else(|_| panic!("Failed parse DNS upstream {upstream}.")))
+            .collect();
+
+        let timeout = std::env::var("DNS_UPSTREAM_TIMEOUT")
+            .ok()
+            .map(|timeout| timeout.parse::<u64>().expect("Failed parse DNS_UPSTREAM_TIMEOUT."))
+            .unwrap_or(2000);
+
+        let sockets = std::env::var("DNS_UPSTREAM_SOCKETS")
+            .ok()
+            .map(|sockets| sockets.parse::<usize>().expect("Failed parse DNS_UPSTREAM_SOCKETS."))
+            .unwrap_or(16);
+
+        Self::with_sockets(upstreams, Duration::from_millis(timeout), sockets)
+    }
+
+    /// Forwards the query to the upstreams until one answers,
+    /// `None` when all of them failed.
+    pub async fn resolve(&self, query: &[u8]) -> Option<Vec<u8>> {
+        let (healthy, down): (Vec<_>, Vec<_>) = self.upstreams.iter()
+            .partition(|upstream| !upstream.is_down());
+
+        for upstream in healthy.into_iter().chain(down) {
+            let pool = if upstream.addr.is_ipv4() { &self.pool_v4 } else { &self.pool_v6 };
+
+            match tokio::time::timeout(self.timeout, pool.exchange(upstream.addr, query)).await {
+                Ok(Ok(answer)) => {
+                    upstream.succeeded();
+
+                    if !dns_message::is_truncated(&answer) {
+                        return Some(answer);
+                    }
+
+                    // the truncated answer still tells the client to retry over TCP itself.
+                    return match tokio::time::timeout(self.timeout, exchange_tcp(upstream.addr, query)).await {
+                        Ok(Ok(answer)) => Some(answer),
+                        Ok(Err(err)) => {
+                            log::warn!("DNS upstream {} failed over TCP: {err}", upstream.addr);
+                            Some(answer)
+                        }
+                        Err(_) => {
+                            log::warn!("DNS upstream {} timed out over TCP", upstream.addr);
+                            Some(answer)
+                        }
+                    };
+                }
+                Ok(Err(err)) => log::warn!("DNS upstream {} failed: {err}", upstream.addr),
+                Err(_) => log::warn!("DNS upstream {} timed out", upstream.addr),
+            }
+
+            upstream.failed();
+        }
+
+        None
+    }
+}
+
+/// Asks `upstream` over TCP (RFC 1035 4.2.2), for answers too large for UDP.
+async fn exchange_tcp(upstream: SocketAddr, query: &[u8]) -> io::Result<Vec<u8>> {
+    let mut stream = TcpStream::connect(upstream).await?;
+
+    dns::write_frame(&mut stream, query).await?;
+    let answer = dns::read_frame(&mut stream).await?;
+
+    if dns_message::id(&answer) != dns_message::id(query)
+        || dns_message::question(&answer).map(|(question, _)| question) != dns_message::question(query).map(|(question, _)| question) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "answer to another query"));
+    }
+
+    Ok(answer)
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use crate::dns_message::{self, DnsQuestion};
+use crate::tunnel_config::TunnelConfig;
+use crate::user_store::{UserStore, UserStoreResult};
+
+const ZONE_TTL: u32 = 60;
+
+/// Users of the zone by name and by tunnel address.
+#[derive(Default)]
+struct DnsZoneRecords {
+    addresses: HashMap<String, Ipv4Addr>,
+    names: HashMap<Ipv4Addr, String>,
+}
+
+/// Authoritative zone naming every enabled user after its username
+/// (`alice.vpn.internal` A 10.8.0.2) with reverse PTR records for the tunnel
+/// subnet. Records come from a snapshot of the user store refreshed by `poll`.
+pub struct DnsZone {
+    zone: String,
+    user_store: Arc<dyn UserStore>,
+    tunnel_config: TunnelConfig,
+    records: RwLock<DnsZoneRecords>,
+    interval: Duration,
+}
+
+impl DnsZone {
+    pub fn new(zone: &str, user_store: Arc<dyn UserStore>, tunnel_config: TunnelConfig) -> Self {
+        Self {
+            zone: zone.trim_matches('.').to_ascii_lowercase(),
+            user_store,
+            tunnel_config,
+            records: RwLock::new(DnsZoneRecords::default()),
+            interval: Duration::from_secs(30),
+        }
+    }
+
+    /// Reads `DNS_ZONE` (e.g. `vpn.internal`, no internal zone when unset) and
+    /// `DNS_ZONE_REFRESH_INTERVAL` (seconds, default 30).
+    pub fn from_env(user_store: Arc<dyn UserStore>, tunnel_config: TunnelConfig) -> Option<Self> {
+        let zone = std::env::var("DNS_ZONE").ok()?;
+        let mut dns_zone = Self::new(&zone, user_store, tunnel_config);
+
+        if let Ok(interval) = std::env::var("DNS_ZONE_REFRESH_INTERVAL") {
+            dns_zone.interval = Duration::from_secs(interval.parse().expect("Failed parse DNS_ZONE_REFRESH_INTERVAL."));
+        }
+
+        Some(dns_zone)
+    }
+
+    /// Reloads the zone's records from the user store.
+    pub async fn refresh(&self) -> UserStoreResult<()> {
+        let mut records = DnsZoneRecords::default();
+
+        for user in self.user_store.list().await?.into_iter().filter(|user| user.enabled) {
+            let address = Ipv4Addr::from(user.local_tunnel_address);
+            let name = user.username.to_ascii_lowercase();
+
+            records.addresses.insert(name.clone(), address);
+            records.names.insert(address, name);
+        }
+
+        *self.records.write().unwrap() = records;
+        Ok(())
+    }
+
+    pub async fn poll(&self) {
+        loop {
+            if let Err(err) = self.refresh().await {
+                log::error!("Failed refresh DNS zone {}: {err}", self.zone);
+            }
+
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+
+    /// Authoritative answer when the question belongs to the zone or to the
+    /// reverse zone of the tunnel subnet, `None` to forward it upstream.
+    pub fn answer(&self, query: &[u8], question: &DnsQuestion) -> Option<Vec<u8>> {
+        let mut answer = if let Some(address) = Self::reverse_address(&question.name) {
+            if !self.tunnel_config.contains(address) {
+                return None;
+            }
+
+            match self.records.read().unwrap().names.get(&address) {
+                Some(name) if question.qtype == dns_message::TYPE_PTR => {
+                    let target = dns_message::encode_name(&format!("{name}.{}", self.zone));
+                    dns_message::answer(query, dns_message::TYPE_PTR, ZONE_TTL, &target)?
+                }
+                Some(_) => dns_message::response(query, dns_message::RCODE_NOERROR)?,
+                None => dns_message::response(query, dns_message::RCODE_NXDOMAIN)?,
+            }
+        } else {
+            let label = match question.name.strip_suffix(&self.zone) {
+                Some("") => None,
+                Some(prefix) => Some(prefix.strip_suffix('.')?),
+                None => return None,
+            };
+
+            let address = label.and_then(|label| self.records.read().unwrap().addresses.get(label).copied());
+
+            match (label, address) {
+                (_, Some(address)) if question.qtype == dns_message::TYPE_A => {
+                    dns_message::answer(query, dns_message::TYPE_A, ZONE_TTL, &address.octets())?
+                }
+                // the apex and users exist, without records of the asked type.
+                (None, _) | (_, Some(_)) => dns_message::response(query, dns_message::RCODE_NOERROR)?,
+                (Some(_), None) => dns_message::response(query, dns_message::RCODE_NXDOMAIN)?,
+            }
+        };
+
+        dns_message::set_authoritative(&mut answer);
+        Some(answer)
+    }
+
+    /// Address of a `d.c.b.a.in-addr.arpa` name.
+    fn reverse_address(name: &str) -> Option<Ipv4Addr> {
+        let octets = name.strip_suffix(".in-addr.arpa")?
+            .split('.')
+            .map(|octet| octet.parse::<u8>().ok())
+            .collect::<Option<Vec<_>>>()?;
+
+        match octets[..] {
+            [d, c, b, a] => Some(Ipv4Addr::new(a, b, c, d)),
+            _ => None,
+        }
+    }
+}
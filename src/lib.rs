@@ -0,0 +1,47 @@
+pub mod session;
+pub mod packet_decoder;
+pub mod message_type;
+pub mod packet_encoder;
+pub mod session_context;
+pub mod session_saturate;
+pub mod session_claims;
+pub mod user;
+pub mod session_payload;
+pub mod tunnel;
+pub mod tunnel_transmitter;
+pub mod session_transmitter;
+pub mod dns;
+pub mod data_header;
+pub mod replay_window;
+pub mod session_ticket;
+pub mod session_verifier;
+pub mod session_revocation;
+pub mod tunnel_config;
+pub mod client;
+pub mod session_signer;
+pub mod command;
+pub mod token_command;
+pub mod user_store;
+pub mod user_store_mysql;
+pub mod user_store_file;
+pub mod user_command;
+pub mod tunnel_device;
+pub mod tunnel_memory;
+pub mod user_store_memory;
+pub mod server;
+pub mod protocol;
+pub mod cipher_suite;
+pub mod dns_message;
+pub mod dns_upstream;
+pub mod dns_cache;
+pub mod dns_filter;
+pub mod dns_zone;
+pub mod dns_socket_pool;
+pub mod dns_query_log;
+pub mod dns_intercept;
+pub mod data_frame;
+pub mod session_transport;
+pub mod websocket;
+pub mod tls_listener;
+pub mod obfuscation;
+pub mod padding;
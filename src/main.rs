@@ -1,28 +1,16 @@
-mod session;
-mod packet_decoder;
-mod message_type;
-mod packet_encoder;
-mod session_context;
-mod session_saturate;
-mod session_claims;
-mod user;
-mod session_payload;
-mod tunnel;
-mod tunnel_transmitter;
-mod session_transmitter;
-mod dns;
-
-use std::env;
-use std::net::Ipv4Addr;
 use std::sync::Arc;
-use async_std::net::{TcpListener, UdpSocket};
+use clap::Parser;
 use dotenv::dotenv;
-use sqlx::MySqlPool;
-use crate::dns::Dns;
-use crate::session::Session;
-use crate::session_transmitter::SessionTransmitter;
-use crate::tunnel::Tunnel;
-use crate::tunnel_transmitter::TunnelTransmitter;
+use smo::command::{Cli, Command};
+use smo::dns::Dns;
+use smo::dns_zone::DnsZone;
+use smo::server::Server;
+use smo::obfuscation::Obfuscation;
+use smo::session::Session;
+use smo::tls_listener::TlsListener;
+use smo::tunnel::Tunnel;
+use smo::tunnel_config::TunnelConfig;
+use smo::{token_command, user_command, user_store};
 
 #[tokio::main]
 async fn main() {
@@ -31,81 +19,57 @@ async fn main() {
         .filter_level(log::LevelFilter::Debug)
         .init();
 
-    let database_url = env::var("MYSQL_DSN")
-        .expect("example env error");
+    match Cli::parse().command.unwrap_or(Command::Serve) {
+        Command::Serve => serve().await,
+        Command::Token(command) => token_command::run(command).await,
+        Command::User(command) => user_command::run(command).await,
+    }
+}
 
-    let mysql_pool = MySqlPool::connect(database_url.as_str())
-        .await
-        .expect("Failed initialized MySQL Connection.");
+async fn serve() {
+    let tunnel_config = TunnelConfig::from_env();
 
     let sessions = Arc::new(Session::new(
-        mysql_pool
+        user_store::from_env().await,
+        tunnel_config
     ));
 
     let tunnel = Tunnel::create(
-        Ipv4Addr::new(10, 8, 0, 1),
-        Ipv4Addr::new(255, 255, 0, 0),
-        1450,
+        tunnel_config.address,
+        tunnel_config.netmask,
+        tunnel_config.mtu as i32,
     );
 
-    let (mut tunnel_rx, tunnel_tx)
-        = tokio::io::split(tunnel);
+    let dns_transmitter = Arc::new(Dns::new("0.0.0.0:5533", sessions.sessions_pool.clone())
+        .expect("Failed initialize DNS decryptor.")
+        .with_zone(DnsZone::from_env(sessions.user_store.clone(), tunnel_config))
+        .with_obfuscation(Obfuscation::from_env()));
 
-    let socket = TcpListener::bind("0.0.0.0:30423")
+    let server = Server::bind("0.0.0.0:30423", sessions, Arc::new(tunnel))
         .await
         .expect("Socket initialization error.");
 
+    let dns_intercept = std::env::var("DNS_INTERCEPT")
+        .map(|dns_intercept| dns_intercept.parse::<bool>().expect("Failed parse DNS_INTERCEPT."))
+        .unwrap_or(false);
 
-    let udp_socket = UdpSocket::bind("0.0.0.0:30423")
-        .await
-        .unwrap();
-
-    let mut session_transmitter = SessionTransmitter::new(
-        &sessions.sessions_pool,
-        tunnel_tx,
-        &udp_socket,
-    );
+    let server = match dns_intercept {
+        true => server.with_dns_interception(dns_transmitter.clone()),
+        false => server,
+    };
 
-    let mut tunnel_transmitter = TunnelTransmitter::new(
-        &sessions.sessions_pool,
-        &mut tunnel_rx,
-        &udp_socket,
-    );
+    let server = match Obfuscation::from_env() {
+        Some(obfuscation) => server.with_obfuscation(obfuscation),
+        None => server,
+    };
 
-    let dns_transmitter = Dns::new("0.0.0.0:5533", Vec::from("example world!"))
-        .expect("Failed initialize DNS decryptor.");
+    let server = match TlsListener::from_env().await {
+        Some(tls) => server.with_tls(tls),
+        None => server,
+    };
 
     tokio::select! {
-        x = async {
-            while let Ok(sock) = socket.accept().await {
-                let sessions_a = sessions.clone();
-                let sessions_b = sessions.clone();
-
-                tokio::task::spawn(async move {
-                    let (sock_stream, sock_addr) = sock.clone();
-                    sessions_a.accept((sock_stream, sock_addr)).await;
-
-                    let session_link = {
-                        let sessions = sessions_b.sessions_pool.read().await;
-
-                        if let Some((session_link, _)) = sessions.iter().find(|((_, _, session_addr), _)| session_addr == &sock_addr) {
-                            session_link.clone()
-                        } else {
-                            log::warn!("Session not found, skipping disconnect.");
-                            return;
-                        }
-                    };
-
-                    log::warn!("Session disconnected");
-
-                    if sessions_b.sessions_pool.write().await.remove(&session_link).is_some() {
-                        log::info!("success session deleted.")
-                    };
-                });
-            }
-        } => x,
-        x = session_transmitter.poll() => x,
-        x = tunnel_transmitter.poll() => x,
+        x = server.serve() => x,
         x = dns_transmitter.expose() => x
     }
-}
\ No newline at end of file
+}
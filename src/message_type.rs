@@ -5,6 +5,12 @@ pub enum MessageType {
     SignWaitApprove = 0x23,
     SignApprove = 0x24,
     Trace = 0x25,
+    Data = 0x26,
+    Resume = 0x27,
+    ResumeApprove = 0x28,
+    SignReject = 0x29,
+    DnsQuery = 0x2a,
+    DnsAnswer = 0x2b,
     Undefined = 0x99,
 }
 
@@ -23,6 +29,12 @@ impl TryFrom<u8> for MessageType {
             x if x == MessageType::SignWaitApprove as u8 => Ok(MessageType::SignWaitApprove),
             x if x == MessageType::SignApprove as u8 => Ok(MessageType::SignApprove),
             x if x == MessageType::Trace as u8 => Ok(MessageType::Trace),
+            x if x == MessageType::Data as u8 => Ok(MessageType::Data),
+            x if x == MessageType::Resume as u8 => Ok(MessageType::Resume),
+            x if x == MessageType::ResumeApprove as u8 => Ok(MessageType::ResumeApprove),
+            x if x == MessageType::SignReject as u8 => Ok(MessageType::SignReject),
+            x if x == MessageType::DnsQuery as u8 => Ok(MessageType::DnsQuery),
+            x if x == MessageType::DnsAnswer as u8 => Ok(MessageType::DnsAnswer),
             _ => Err(()),
         }
     }
@@ -0,0 +1,297 @@
+use std::env;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use chacha20::cipher::{KeyIvInit, StreamCipher};
+use chacha20::XChaCha20;
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use crate::data_header::DATA_HEADER_LEN;
+
+/// XChaCha20 nonce opening each direction of an obfuscated stream.
+const STREAM_NONCE_LEN: usize = 24;
+
+/// Upper bound of the random padding of each stream record.
+const STREAM_PAD_MAX: u16 = 255;
+
+/// Padding and data lengths opening each stream record.
+const RECORD_HEADER_LEN: usize = 4;
+
+/// Upper bound of the random padding after a datagram header.
+const DATAGRAM_PAD_MAX: u8 = 32;
+
+/// Most bytes `seal_datagram` adds: the pad length and the padding.
+pub const DATAGRAM_OVERHEAD_MAX: usize = 1 + DATAGRAM_PAD_MAX as usize;
+
+/// Tail of the sealed body (tag and nonce) the datagram header mask is derived from.
+const MASK_SAMPLE_LEN: usize = 16;
+
+/// Pre-shared key hiding the protocol from passive fingerprinting: control
+/// streams are encrypted end to end from their first byte, datagram headers
+/// are masked, and both carry random padding. It provides no authentication
+/// of its own, that is still the session handshake's job.
+#[derive(Clone)]
+pub struct Obfuscation {
+    stream_key: [u8; 32],
+    mask_key: hmac::Key,
+}
+
+impl Obfuscation {
+    pub fn new(key: &[u8]) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+
+        let mut stream_key = [0u8; 32];
+        stream_key.copy_from_slice(hmac::sign(&key, b"smo obfuscation stream").as_ref());
+
+        Self {
+            stream_key,
+            mask_key: hmac::Key::new(hmac::HMAC_SHA256, hmac::sign(&key, b"smo obfuscation mask").as_ref()),
+        }
+    }
+
+    /// Reads OBFUSCATION_KEY, no obfuscation when unset. Clients must be
+    /// configured with the same key, plain clients are no longer understood.
+    pub fn from_env() -> Option<Self> {
+        let key = env::var("OBFUSCATION_KEY").ok().filter(|key| !key.is_empty())?;
+
+        Some(Self::new(key.as_bytes()))
+    }
+
+    pub fn stream<S>(&self, inner: S) -> ObfuscatedStream<S> {
+        ObfuscatedStream {
+            inner,
+            key: self.stream_key,
+            reader: None,
+            reader_nonce: Vec::with_capacity(STREAM_NONCE_LEN),
+            reader_header: Vec::with_capacity(RECORD_HEADER_LEN),
+            reader_pad_left: 0,
+            reader_data_left: 0,
+            writer: None,
+            pending: Vec::new(),
+            pending_pos: 0,
+            pending_len: 0,
+        }
+    }
+
+    /// Masks the data header of `datagram` (header followed by a sealed body)
+    /// and inserts random padding after it.
+    pub fn seal_datagram(&self, datagram: &[u8]) -> Vec<u8> {
+        let rng = SystemRandom::new();
+
+        let mut pad_len = [0u8; 1];
+        rng.fill(&mut pad_len).unwrap();
+        let pad_len = pad_len[0] % (DATAGRAM_PAD_MAX + 1);
+
+        let mut pad = vec![0u8; pad_len as usize];
+        rng.fill(&mut pad).unwrap();
+
+        let header_len = DATA_HEADER_LEN.min(datagram.len());
+        let mut buf = Vec::with_capacity(datagram.len() + 1 + pad.len());
+        buf.extend_from_slice(&datagram[..header_len]);
+        buf.push(pad_len);
+        buf.extend_from_slice(&pad);
+        buf.extend_from_slice(&datagram[header_len..]);
+
+        let mask = self.mask(&buf[buf.len().saturating_sub(MASK_SAMPLE_LEN)..]);
+
+        for (byte, mask) in buf[..header_len + 1].iter_mut().zip(mask.as_ref()) {
+            *byte ^= mask;
+        }
+
+        buf
+    }
+
+    /// Reverses `seal_datagram`, `None` when the padding does not fit.
+    pub fn open_datagram(&self, datagram: &[u8]) -> Option<Vec<u8>> {
+        if datagram.len() < DATA_HEADER_LEN + 1 + MASK_SAMPLE_LEN {
+            return None;
+        }
+
+        let mask = self.mask(&datagram[datagram.len() - MASK_SAMPLE_LEN..]);
+
+        let mut header = [0u8; DATA_HEADER_LEN + 1];
+        for ((byte, datagram), mask) in header.iter_mut().zip(datagram).zip(mask.as_ref()) {
+            *byte = datagram ^ mask;
+        }
+
+        let body = DATA_HEADER_LEN + 1 + header[DATA_HEADER_LEN] as usize;
+
+        if body + MASK_SAMPLE_LEN > datagram.len() {
+            return None;
+        }
+
+        let mut buf = header[..DATA_HEADER_LEN].to_vec();
+        buf.extend_from_slice(&datagram[body..]);
+
+        Some(buf)
+    }
+
+    fn mask(&self, sample: &[u8]) -> hmac::Tag {
+        hmac::sign(&self.mask_key, sample)
+    }
+}
+
+/// Byte stream encrypted with a keystream of the obfuscation key. Each
+/// direction opens with a random nonce, then every write becomes a record
+/// (u16 padding length, u16 data length, random padding, data), so no
+/// message of the session has fixed bytes or a telling length.
+pub struct ObfuscatedStream<S> {
+    inner: S,
+    key: [u8; 32],
+    reader: Option<XChaCha20>,
+    reader_nonce: Vec<u8>,
+    reader_header: Vec<u8>,
+    reader_pad_left: usize,
+    reader_data_left: usize,
+    writer: Option<XChaCha20>,
+    pending: Vec<u8>,
+    pending_pos: usize,
+    pending_len: usize,
+}
+
+impl<S> ObfuscatedStream<S> {
+    /// Decrypts `received` in place, moves the record data to its front and
+    /// returns its length; nonce, record headers and padding are dropped.
+    fn open(&mut self, received: &mut [u8]) -> usize {
+        let mut pos = 0;
+
+        if self.reader.is_none() {
+            pos = (STREAM_NONCE_LEN - self.reader_nonce.len()).min(received.len());
+            self.reader_nonce.extend_from_slice(&received[..pos]);
+
+            if self.reader_nonce.len() < STREAM_NONCE_LEN {
+                return 0;
+            }
+
+            self.reader = Some(XChaCha20::new(&self.key.into(), self.reader_nonce[..].into()));
+        }
+
+        if let Some(reader) = &mut self.reader {
+            reader.apply_keystream(&mut received[pos..]);
+        }
+
+        let mut data_len = 0;
+
+        while pos < received.len() {
+            if self.reader_header.len() < RECORD_HEADER_LEN {
+                self.reader_header.push(received[pos]);
+                pos += 1;
+
+                if self.reader_header.len() == RECORD_HEADER_LEN {
+                    self.reader_pad_left = u16::from_be_bytes([self.reader_header[0], self.reader_header[1]]) as usize;
+                    self.reader_data_left = u16::from_be_bytes([self.reader_header[2], self.reader_header[3]]) as usize;
+                }
+            } else if self.reader_pad_left > 0 {
+                let pad = self.reader_pad_left.min(received.len() - pos);
+                self.reader_pad_left -= pad;
+                pos += pad;
+            } else {
+                let data = self.reader_data_left.min(received.len() - pos);
+                received.copy_within(pos..pos + data, data_len);
+                self.reader_data_left -= data;
+                data_len += data;
+                pos += data;
+            }
+
+            if self.reader_header.len() == RECORD_HEADER_LEN && self.reader_pad_left == 0 && self.reader_data_left == 0 {
+                self.reader_header.clear();
+            }
+        }
+
+        data_len
+    }
+
+    /// Encrypts up to a record's worth of `buf` as the next pending write,
+    /// behind our nonce on the first one.
+    fn seal(&mut self, buf: &[u8]) {
+        let rng = SystemRandom::new();
+        let data = &buf[..buf.len().min(u16::MAX as usize)];
+
+        self.pending.clear();
+        self.pending_pos = 0;
+        self.pending_len = data.len();
+
+        let start = match &self.writer {
+            Some(_) => 0,
+            None => {
+                let mut nonce = [0u8; STREAM_NONCE_LEN];
+                rng.fill(&mut nonce).unwrap();
+
+                self.writer = Some(XChaCha20::new(&self.key.into(), &nonce.into()));
+                self.pending.extend_from_slice(&nonce);
+
+                STREAM_NONCE_LEN
+            }
+        };
+
+        let mut pad_len = [0u8; 2];
+        rng.fill(&mut pad_len).unwrap();
+        let pad_len = u16::from_be_bytes(pad_len) % (STREAM_PAD_MAX + 1);
+
+        self.pending.extend_from_slice(&pad_len.to_be_bytes());
+        self.pending.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        self.pending.resize(self.pending.len() + pad_len as usize, 0);
+        self.pending.extend_from_slice(data);
+
+        if let Some(writer) = &mut self.writer {
+            writer.apply_keystream(&mut self.pending[start..]);
+        }
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ObfuscatedStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            let start = buf.filled().len();
+            ready!(Pin::new(&mut this.inner).poll_read(cx, buf))?;
+
+            let received = &mut buf.filled_mut()[start..];
+
+            if received.is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+
+            let data_len = this.open(received);
+            buf.set_filled(start + data_len);
+
+            // a read of only nonce, record headers or padding must not look like the end of the stream.
+            if data_len > 0 {
+                return Poll::Ready(Ok(()));
+            }
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ObfuscatedStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        // encrypted once, a pending write is retried with the same buf.
+        if this.pending_pos == this.pending.len() {
+            this.seal(buf);
+        }
+
+        while this.pending_pos < this.pending.len() {
+            let n = ready!(Pin::new(&mut this.inner).poll_write(cx, &this.pending[this.pending_pos..]))?;
+
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+
+            this.pending_pos += n;
+        }
+
+        Poll::Ready(Ok(this.pending_len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
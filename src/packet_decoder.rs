@@ -1,67 +1,102 @@
+use std::io;
 use std::io::{Cursor, Read};
-use ring::aead::{Aad, Nonce, LessSafeKey};
+use ring::aead::{Aad, Nonce, LessSafeKey, NONCE_LEN};
 use crate::message_type::MessageType;
+use crate::padding::PADDING_HEADER_LEN;
 
 pub struct PacketDecoder {
     cursor: Cursor<Vec<u8>>,
 }
 
 impl PacketDecoder {
-    pub fn new_xor(buf: &[u8], shared: Vec<u8>) -> Self {
+    /// Reads a plaintext packet, as exchanged before a session key exists.
+    pub fn new(buf: &[u8]) -> Self {
         Self {
-            cursor: Cursor::new(buf.iter()
-                .enumerate()
-                .map(|(i, &byte)| byte ^ shared[i % shared.len()])
-                .collect())
+            cursor: Cursor::new(buf.to_vec())
         }
     }
 
-    pub fn new(buf: &[u8], shared: Option<LessSafeKey>) -> Self {
-        if let Some(shared) = shared {
-            let packet_len = buf.len();
-            let nonce = &buf[packet_len - 12..packet_len];
-            let nonce = Nonce::try_assume_unique_for_key(nonce).unwrap();
-
-            let mut data = buf[..packet_len - 12].to_vec();
-            if shared.open_in_place(nonce, Aad::empty(), &mut data).is_ok() {
-                return Self {
-                    cursor: Cursor::new(data)
-                };
-            }
+    /// Opens a sealed packet authenticated together with `aad`, whatever its
+    /// cipher suite; `None` when the tag does not verify.
+    pub fn open(buf: &[u8], shared: &LessSafeKey, aad: &[u8]) -> Option<Self> {
+        Self::open_padded(buf, shared, aad, false)
+    }
+
+    /// Same as `open`, stripping the padding of a `padded` session, one that
+    /// negotiated `Capabilities::PADDING`; `None` when it carries none.
+    pub fn open_padded(buf: &[u8], shared: &LessSafeKey, aad: &[u8], padded: bool) -> Option<Self> {
+        if buf.len() < NONCE_LEN + shared.algorithm().tag_len() {
+            return None;
         }
 
-        Self {
-            cursor: Cursor::new(buf.to_vec())
+        let packet_len = buf.len();
+        let nonce = Nonce::try_assume_unique_for_key(&buf[packet_len - NONCE_LEN..packet_len]).ok()?;
+
+        let mut data = buf[..packet_len - NONCE_LEN].to_vec();
+        let plain_len = shared.open_in_place(nonce, Aad::from(aad), &mut data).ok()?.len();
+        data.truncate(plain_len);
+
+        Some(Self {
+            cursor: Cursor::new(strip_padding(data, padded)?)
+        })
+    }
+
+    /// Bytes left unread, lets optional trailing fields be detected.
+    pub fn remaining(&self) -> usize {
+        self.cursor.get_ref().len().saturating_sub(self.cursor.position() as usize)
+    }
+
+    /// `MessageType::Undefined` for unknown opcodes and empty messages.
+    pub fn read_opcode(&mut self) -> MessageType {
+        let mut opcode = [0u8; 1];
+
+        match self.cursor.read_exact(&mut opcode) {
+            Ok(()) => MessageType::try_from(opcode[0]).unwrap_or(MessageType::Undefined),
+            Err(_) => MessageType::Undefined,
         }
     }
 
-    pub fn read_uint32(&mut self) -> u32 {
+    /// Length prefixed bytes, an error on a truncated or malformed message
+    /// from a peer. Lengths beyond the message are refused before allocating.
+    pub fn try_read_string(&mut self) -> io::Result<Vec<u8>> {
+        let length = self.try_read_uint32()?;
+
+        if length as usize > self.remaining() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let mut buf = vec![0; length as usize];
+        self.cursor.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    pub fn try_read_uint32(&mut self) -> io::Result<u32> {
         let mut buf = [0u8; 4];
-        self.cursor.read_exact(&mut buf).unwrap();
-        u32::from_be_bytes(buf)
+        self.cursor.read_exact(&mut buf)?;
+        Ok(u32::from_be_bytes(buf))
     }
 
-    pub fn read_uint16(&mut self) -> u16 {
+    pub fn try_read_uint16(&mut self) -> io::Result<u16> {
         let mut buf = [0u8; 2];
-        self.cursor.read_exact(&mut buf).unwrap();
-        u16::from_be_bytes(buf)
+        self.cursor.read_exact(&mut buf)?;
+        Ok(u16::from_be_bytes(buf))
     }
+}
 
-    pub fn read_opcode(&mut self) -> MessageType {
-        let opcode = self.read_uint8();
-        MessageType::try_from(opcode).unwrap_or(MessageType::Undefined)
+/// Drops the padding `PacketEncoder::with_padding` put in front of a
+/// `padded` message, `None` when the pad length runs past the end.
+fn strip_padding(mut data: Vec<u8>, padded: bool) -> Option<Vec<u8>> {
+    if !padded {
+        return Some(data);
     }
 
-    pub fn read_string(&mut self) -> Vec<u8> {
-        let length = self.read_uint32();
-        let mut buf = vec![0; length as usize];
-        self.cursor.read_exact(&mut buf).unwrap();
-        buf
-    }
+    let pad_len = u16::from_be_bytes(data.get(..PADDING_HEADER_LEN)?.try_into().ok()?) as usize;
 
-    pub fn read_uint8(&mut self) -> u8 {
-        let mut buf = [0u8; 1];
-        self.cursor.read_exact(&mut buf).unwrap();
-        buf[0]
+    if PADDING_HEADER_LEN + pad_len > data.len() {
+        return None;
     }
+
+    data.drain(..PADDING_HEADER_LEN + pad_len);
+
+    Some(data)
 }
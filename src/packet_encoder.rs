@@ -1,19 +1,31 @@
-use ring::aead::{Aad, LessSafeKey, Nonce};
+use ring::aead::{Aad, LessSafeKey, Nonce, NONCE_LEN};
 use ring::rand::{SecureRandom, SystemRandom};
 use std::io::Write;
 use crate::message_type::MessageType;
+use crate::padding::{Padding, PADDING_HEADER_LEN};
 
+#[derive(Default)]
 pub struct PacketEncoder {
     buf: Vec<u8>,
+    padding: Option<Padding>,
 }
 
 impl PacketEncoder {
     pub fn new() -> Self {
         Self {
             buf: Vec::new(),
+            padding: None,
         }
     }
 
+    /// Pads the sealed output by `padding`, inside the authenticated plaintext
+    /// so `PacketDecoder` strips it when opened as padded. Unsealed output is
+    /// never padded.
+    pub fn with_padding(mut self, padding: Option<Padding>) -> Self {
+        self.padding = padding;
+        self
+    }
+
     #[allow(dead_code)]
     pub fn write_u8(&mut self, value: u8) {
         self.buf.write_all(&[value]).unwrap();
@@ -40,19 +52,37 @@ impl PacketEncoder {
         self.buf.write_all(value).unwrap();
     }
 
+    /// Seals with the session key when given, in the key's cipher suite.
     #[allow(dead_code)]
     pub fn to_bytes(&self, shared: Option<LessSafeKey>) -> Vec<u8> {
+        self.to_bytes_with_aad(shared, &[])
+    }
+
+    /// Same as `to_bytes`, additionally authenticating `aad` (e.g. a data header).
+    pub fn to_bytes_with_aad(&self, shared: Option<LessSafeKey>, aad: &[u8]) -> Vec<u8> {
         if let Some(shared) = shared {
             let rng = SystemRandom::new();
-            let mut nonce_bytes = [0u8; 12];
+            let mut nonce_bytes = [0u8; NONCE_LEN];
 
             rng.fill(&mut nonce_bytes).unwrap();
 
             let nonce = Nonce::assume_unique_for_key(nonce_bytes);
 
-            let mut buf = self.buf.clone();
+            let mut buf = match &self.padding {
+                Some(padding) => {
+                    let len = PADDING_HEADER_LEN + self.buf.len() + shared.algorithm().tag_len() + NONCE_LEN;
+                    let pad_len = padding.pad_len(len).min(u16::MAX as usize);
 
-            shared.seal_in_place_append_tag(nonce, Aad::empty(), &mut buf).unwrap();
+                    let mut buf = Vec::with_capacity(PADDING_HEADER_LEN + pad_len + self.buf.len());
+                    buf.extend_from_slice(&(pad_len as u16).to_be_bytes());
+                    buf.resize(PADDING_HEADER_LEN + pad_len, 0);
+                    buf.extend_from_slice(&self.buf);
+                    buf
+                }
+                None => self.buf.clone(),
+            };
+
+            shared.seal_in_place_append_tag(nonce, Aad::from(aad), &mut buf).unwrap();
             buf.extend_from_slice(&nonce_bytes);
 
             return buf;
@@ -60,11 +90,4 @@ impl PacketEncoder {
 
         self.buf.clone()
     }
-
-    pub fn to_bytes_with_xor(&self, shared: Vec<u8>) -> Vec<u8> {
-        self.buf.iter()
-            .enumerate()
-            .map(|(i, &byte)| byte ^ shared[i % shared.len()])
-            .collect()
-    }
 }
\ No newline at end of file
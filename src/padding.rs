@@ -0,0 +1,93 @@
+use std::env;
+use std::str::FromStr;
+use std::time::Duration;
+use ring::rand::{SecureRandom, SystemRandom};
+use crate::data_header::{DATAGRAM_BUFFER_LEN, DATA_HEADER_LEN};
+use crate::obfuscation::DATAGRAM_OVERHEAD_MAX;
+
+/// Pad length in front of the pad. Every sealed message of a session that
+/// negotiated `Capabilities::PADDING` starts with it, none of the others do.
+pub const PADDING_HEADER_LEN: usize = 2;
+
+/// Longest sealed message padding grows to, so a padded datagram still fits
+/// the peer's receive buffer with its data header and obfuscation.
+pub const PADDED_LEN_MAX: usize = DATAGRAM_BUFFER_LEN - DATA_HEADER_LEN - DATAGRAM_OVERHEAD_MAX;
+
+/// Length policy for sealed messages on the control and data channels, so
+/// their sizes tell less about what they carry. Lengths are those of the
+/// sealed message, data headers excluded; padding never takes a message
+/// beyond `PADDED_LEN_MAX`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Padding {
+    /// Up to the smallest bucket that fits, multiples of the largest beyond.
+    Buckets(Vec<usize>),
+    /// Up to a fixed size, e.g. the path MTU.
+    Mtu(usize),
+    /// Between zero and the given number of random bytes.
+    Random(usize),
+}
+
+impl Padding {
+    /// Reads PADDING (`buckets:256,512,1024`, `mtu:1400` or `random:64`,
+    /// default: no padding).
+    pub fn from_env() -> Option<Self> {
+        let padding = env::var("PADDING").ok().filter(|padding| !padding.is_empty())?;
+
+        Some(padding.parse().expect("Failed parse PADDING."))
+    }
+
+    /// Pad bytes for a sealed message of `len` bytes, padding header included.
+    pub fn pad_len(&self, len: usize) -> usize {
+        let pad_len = match self {
+            Self::Buckets(buckets) => {
+                let Some(largest) = buckets.iter().max().copied().filter(|largest| *largest > 0) else {
+                    return 0;
+                };
+
+                match buckets.iter().filter(|bucket| **bucket >= len).min() {
+                    Some(bucket) => bucket - len,
+                    None => len.div_ceil(largest) * largest - len,
+                }
+            }
+            Self::Mtu(mtu) => mtu.saturating_sub(len),
+            Self::Random(max) => {
+                let mut random = [0u8; 4];
+                SystemRandom::new().fill(&mut random).unwrap();
+
+                u32::from_be_bytes(random) as usize % (max + 1)
+            }
+        };
+
+        pad_len.min(PADDED_LEN_MAX.saturating_sub(len))
+    }
+}
+
+impl FromStr for Padding {
+    type Err = String;
+
+    fn from_str(padding: &str) -> Result<Self, Self::Err> {
+        let (policy, value) = padding.split_once(':')
+            .ok_or_else(|| format!("padding {padding} misses its size"))?;
+
+        let size = |value: &str| match value.trim().parse::<usize>() {
+            Ok(size) if size <= PADDED_LEN_MAX => Ok(size),
+            Ok(_) => Err(format!("padding size {value} exceeds {PADDED_LEN_MAX}")),
+            Err(_) => Err(format!("invalid padding size {value}")),
+        };
+
+        match policy {
+            "buckets" => Ok(Self::Buckets(value.split(',').map(size).collect::<Result<_, _>>()?)),
+            "mtu" => Ok(Self::Mtu(size(value)?)),
+            "random" => Ok(Self::Random(size(value)?)),
+            policy => Err(format!("unknown padding policy {policy}")),
+        }
+    }
+}
+
+/// Reads COVER_TRAFFIC_INTERVAL in milliseconds (default: no cover traffic).
+/// Idle sessions with a padding policy then get a padded empty packet per interval.
+pub fn cover_traffic_from_env() -> Option<Duration> {
+    env::var("COVER_TRAFFIC_INTERVAL")
+        .ok()
+        .map(|interval| Duration::from_millis(interval.parse().expect("Failed parse COVER_TRAFFIC_INTERVAL.")))
+}
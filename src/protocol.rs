@@ -0,0 +1,156 @@
+use std::io;
+use std::ops::BitOr;
+use crate::packet_decoder::PacketDecoder;
+use crate::packet_encoder::PacketEncoder;
+
+/// Oldest protocol version the server still accepts in `Sign`.
+pub const PROTOCOL_VERSION_MIN: u16 = 1;
+
+/// Newest protocol version spoken by this build.
+pub const PROTOCOL_VERSION_MAX: u16 = 1;
+
+/// Version assumed for clients whose `Sign` predates version negotiation.
+pub const PROTOCOL_VERSION_LEGACY: u16 = 1;
+
+/// Optional protocol features, offered by the client in `Sign` and narrowed
+/// to the common subset by the server in `SignWaitApprove`.
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// Resumption tickets are issued on approval.
+    pub const RESUMPTION: Self = Self(1 << 0);
+
+    /// Session packets are sealed with ChaCha20-Poly1305 instead of AES-256-GCM.
+    pub const CHACHA20_POLY1305: Self = Self(1 << 1);
+
+    /// Data packets travel framed on the control connection instead of UDP.
+    pub const TCP_DATA: Self = Self(1 << 2);
+
+    /// Sealed messages may carry padding and cover packets may be sent.
+    pub const PADDING: Self = Self(1 << 3);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    /// Features implemented by this build.
+    pub const fn supported() -> Self {
+        Self(Self::RESUMPTION.0 | Self::CHACHA20_POLY1305.0 | Self::TCP_DATA.0 | Self::PADDING.0)
+    }
+
+    /// Features of clients whose `Sign` predates capability negotiation.
+    pub const fn legacy() -> Self {
+        Self::RESUMPTION
+    }
+
+    /// Keeps unknown bits, so a bitmap can be passed through unchanged.
+    pub const fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    pub const fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+}
+
+/// Version and capability words of a `Sign` exchange as one side saw them,
+/// echoed inside the sealed approvals so a tampered plaintext negotiation
+/// aborts the session instead of silently downgrading it.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Negotiation {
+    pub offered_version: u16,
+    pub offered_capabilities: Capabilities,
+    pub protocol_version: u16,
+    pub capabilities: Capabilities,
+}
+
+impl Negotiation {
+    /// Bytes taken by `write`.
+    pub const LEN: usize = 12;
+
+    /// Reads the echo closing a resumption of a ticket with `protocol_version`
+    /// and `capabilities`. The server may grant fewer capabilities than the
+    /// ticket carries, never others; servers predating the echo send none.
+    pub fn resumed(packet: &mut PacketDecoder, protocol_version: u16, capabilities: Capabilities) -> io::Result<Self> {
+        let ticket = Self {
+            offered_version: protocol_version,
+            offered_capabilities: capabilities,
+            protocol_version,
+            capabilities,
+        };
+
+        if packet.remaining() < Self::LEN {
+            return Ok(ticket);
+        }
+
+        let echo = Self::read(packet)?;
+
+        if (echo.offered_version, echo.offered_capabilities, echo.protocol_version) != (protocol_version, capabilities, protocol_version)
+            || !capabilities.contains(echo.capabilities) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "resumption does not match its ticket"));
+        }
+
+        Ok(echo)
+    }
+
+    pub fn write(&self, packet: &mut PacketEncoder) {
+        packet.write_u16(self.offered_version);
+        packet.write_u32(self.offered_capabilities.bits());
+        packet.write_u16(self.protocol_version);
+        packet.write_u32(self.capabilities.bits());
+    }
+
+    /// Reads an echo written by `write`, an error when the message ends early.
+    pub fn read(packet: &mut PacketDecoder) -> io::Result<Self> {
+        Ok(Self {
+            offered_version: packet.try_read_uint16()?,
+            offered_capabilities: Capabilities::from_bits(packet.try_read_uint32()?),
+            protocol_version: packet.try_read_uint16()?,
+            capabilities: Capabilities::from_bits(packet.try_read_uint32()?),
+        })
+    }
+
+    /// Checks the echo at the end of an approval against the terms seen here.
+    /// Peers predating the echo send none, which passes unless it is `required`.
+    pub fn verify(&self, packet: &mut PacketDecoder, required: bool) -> io::Result<()> {
+        if packet.remaining() < Self::LEN && !required {
+            return Ok(());
+        }
+
+        if Self::read(packet)? != *self {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "negotiation does not match its sealed echo"));
+        }
+
+        Ok(())
+    }
+}
+
+/// Highest version both sides speak, `None` when the client is too old.
+pub fn negotiate_version(client_version: u16) -> Option<u16> {
+    if client_version < PROTOCOL_VERSION_MIN {
+        return None;
+    }
+
+    Some(client_version.min(PROTOCOL_VERSION_MAX))
+}
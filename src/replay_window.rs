@@ -0,0 +1,43 @@
+/// Sliding window over the last 64 data packet counters of a session.
+#[derive(Default)]
+pub struct ReplayWindow {
+    last: u64,
+    bitmap: u64,
+}
+
+impl ReplayWindow {
+    pub fn new() -> Self {
+        Self {
+            last: 0,
+            bitmap: 0,
+        }
+    }
+
+    /// Marks the counter as seen, returns `false` for replayed or too old counters.
+    pub fn accept(&mut self, counter: u64) -> bool {
+        if counter > self.last {
+            let shift = counter - self.last;
+
+            self.bitmap = if shift >= 64 { 0 } else { self.bitmap << shift };
+            self.bitmap |= 1;
+            self.last = counter;
+
+            return true;
+        }
+
+        let offset = self.last - counter;
+
+        if offset >= 64 {
+            return false;
+        }
+
+        let mask = 1u64 << offset;
+
+        if self.bitmap & mask != 0 {
+            return false;
+        }
+
+        self.bitmap |= mask;
+        true
+    }
+}
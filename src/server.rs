@@ -0,0 +1,173 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use async_std::net::{TcpListener, UdpSocket};
+use futures::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+use tokio_util::compat::{FuturesAsyncReadCompatExt, TokioAsyncReadCompatExt};
+use crate::dns::Dns;
+use crate::dns_intercept::DnsInterceptor;
+use crate::obfuscation::Obfuscation;
+use crate::session::Session;
+use crate::session_transmitter::SessionTransmitter;
+use crate::tls_listener::TlsListener;
+use crate::tunnel_device::TunnelDevice;
+use crate::tunnel_transmitter::TunnelTransmitter;
+
+/// Data frames from control connections awaiting `SessionTransmitter`.
+const TCP_DATA_CAPACITY: usize = 1024;
+
+/// Time a connection to the TLS listener gets for the TLS and WebSocket handshakes.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Control listener and data socket sharing one port, wired to a tunnel device.
+pub struct Server {
+    sessions: Arc<Session>,
+    tunnel: Arc<dyn TunnelDevice>,
+    listener: TcpListener,
+    udp_socket: UdpSocket,
+    dns_interceptor: Option<Arc<DnsInterceptor>>,
+    tls: Option<Arc<TlsListener>>,
+    obfuscation: Option<Obfuscation>,
+}
+
+impl Server {
+    pub async fn bind(addr: &str, sessions: Arc<Session>, tunnel: Arc<dyn TunnelDevice>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let udp_socket = UdpSocket::bind(listener.local_addr()?).await?;
+
+        Ok(Self {
+            sessions,
+            tunnel,
+            listener,
+            udp_socket,
+            dns_interceptor: None,
+            tls: None,
+            obfuscation: None,
+        })
+    }
+
+    /// Answers port 53 traffic of sessions with `dns` instead of routing it.
+    pub fn with_dns_interception(mut self, dns: Arc<Dns>) -> Self {
+        self.dns_interceptor = Some(Arc::new(DnsInterceptor::new(dns, self.sessions.tunnel_config.mtu)));
+        self
+    }
+
+    /// Also accepts control connections as WebSockets over TLS.
+    pub fn with_tls(mut self, tls: TlsListener) -> Self {
+        self.tls = Some(Arc::new(tls));
+        self
+    }
+
+    /// Expects control streams and datagrams of the plain port obfuscated with
+    /// the shared key.
+    pub fn with_obfuscation(mut self, obfuscation: Obfuscation) -> Self {
+        self.obfuscation = Some(obfuscation);
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub async fn serve(&self) {
+        // revoked tokens are refused from the first connection on.
+        self.sessions.revocation.refresh()
+            .await
+            .expect("Failed load revoked tokens.");
+
+        let (tcp_data_tx, tcp_data_rx) = mpsc::channel(TCP_DATA_CAPACITY);
+
+        let mut session_transmitter = SessionTransmitter::new(
+            &self.sessions.sessions_pool,
+            self.tunnel.clone(),
+            &self.udp_socket,
+        )
+            .with_dns_interceptor(self.dns_interceptor.clone())
+            .with_obfuscation(self.obfuscation.clone())
+            .with_tcp_data(tcp_data_rx);
+
+        let mut tunnel_transmitter = TunnelTransmitter::new(
+            &self.sessions.sessions_pool,
+            self.tunnel.clone(),
+            &self.udp_socket,
+        )
+            .with_dns_interceptor(self.dns_interceptor.clone())
+            .with_obfuscation(self.obfuscation.clone())
+            .with_cover_traffic(self.sessions.cover_traffic);
+
+        tokio::select! {
+            x = async {
+                while let Ok(sock) = self.listener.accept().await {
+                    let sessions = self.sessions.clone();
+                    let tcp_data = tcp_data_tx.clone();
+                    let obfuscation = self.obfuscation.clone();
+
+                    tokio::task::spawn(async move {
+                        let (sock_stream, sock_addr) = sock;
+
+                        match obfuscation {
+                            Some(obfuscation) => {
+                                let sock_stream = obfuscation.stream(sock_stream.compat()).compat();
+                                serve_control(sessions, sock_stream, sock_addr, tcp_data).await;
+                            }
+                            None => serve_control(sessions, sock_stream, sock_addr, tcp_data).await,
+                        }
+                    });
+                }
+            } => x,
+            x = async {
+                let Some(tls) = &self.tls else {
+                    return std::future::pending().await;
+                };
+
+                while let Ok((stream, sock_addr)) = tls.accept().await {
+                    let sessions = self.sessions.clone();
+                    let tcp_data = tcp_data_tx.clone();
+                    let tls = tls.clone();
+
+                    tokio::task::spawn(async move {
+                        let stream = match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.upgrade(stream)).await {
+                            Ok(Ok(stream)) => stream,
+                            Ok(Err(err)) => {
+                                log::warn!("Failed TLS handshake with {sock_addr}: {err}");
+                                return;
+                            }
+                            Err(_) => {
+                                log::warn!("TLS handshake with {sock_addr} timed out.");
+                                return;
+                            }
+                        };
+
+                        serve_control(sessions, stream.compat(), sock_addr, tcp_data).await;
+                    });
+                }
+            } => x,
+            x = session_transmitter.poll() => x,
+            x = tunnel_transmitter.poll() => x,
+            x = self.sessions.revocation.poll(&self.sessions.sessions_pool) => x
+        }
+    }
+}
+
+/// Runs one control connection and drops its sessions once it closes.
+async fn serve_control<S>(sessions: Arc<Session>, stream: S, sock_addr: SocketAddr, tcp_data: mpsc::Sender<(SocketAddr, Vec<u8>)>)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    sessions.clone().accept((stream, sock_addr), tcp_data).await;
+
+    log::warn!("Session disconnected");
+
+    let mut sessions = sessions.sessions_pool.write().await;
+    let session_count = sessions.len();
+
+    sessions.retain(|_, session| session.control_address() != sock_addr);
+
+    if sessions.len() < session_count {
+        log::info!("success session deleted.")
+    } else {
+        log::warn!("Session not found, skipping disconnect.");
+    }
+}
@@ -4,74 +4,195 @@ use std::sync::Arc;
 use std::time::Duration;
 use async_std::future;
 use async_std::io::ReadExt;
-use async_std::net::{TcpStream};
-use futures::AsyncWriteExt;
-use jsonwebtoken::{DecodingKey};
-use ring::aead::{LessSafeKey, UnboundKey};
+use futures::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use ring::agreement;
 use ring::agreement::{EphemeralPrivateKey, UnparsedPublicKey};
-use ring::rand::SystemRandom;
-use sqlx::{MySql, Pool};
-use tokio::sync::RwLock;
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::sync::{mpsc, Notify, RwLock};
 
+use crate::data_frame::{frame, next_frame};
 use crate::message_type::MessageType;
 use crate::packet_decoder::PacketDecoder;
 use crate::packet_encoder::PacketEncoder;
-use crate::session_claims::SessionClaims;
+use crate::padding::{cover_traffic_from_env, Padding};
+use crate::protocol::{negotiate_version, Capabilities, PROTOCOL_VERSION_LEGACY, PROTOCOL_VERSION_MAX, PROTOCOL_VERSION_MIN};
 use crate::session_context::SessionContext;
 use crate::session_payload::SessionPayload;
+use crate::session_revocation::SessionRevocation;
 use crate::session_saturate::SessionSaturate;
+use crate::session_ticket::{resumption_key, verify_resumption_proof, SessionTickets};
+use crate::session_transport::SessionTransport;
+use crate::session_verifier::SessionVerifier;
+use crate::tunnel_config::TunnelConfig;
 use crate::user::User;
+use crate::user_store::UserStore;
+
+/// Sessions by the server assigned id carried in every data header.
+pub type SessionsPool = Arc<RwLock<HashMap<u32, SessionPayload>>>;
 
 pub struct Session {
-    pub mysql_pool: Pool<MySql>,
-    pub jwk: DecodingKey,
-    pub sessions_pool: Arc<RwLock<HashMap<(SocketAddr, Ipv4Addr, SocketAddr), SessionPayload>>>
+    pub user_store: Arc<dyn UserStore>,
+    pub verifier: SessionVerifier,
+    pub tickets: SessionTickets,
+    pub revocation: SessionRevocation,
+    pub tunnel_config: TunnelConfig,
+    pub sessions_pool: SessionsPool,
+    pub padding: Option<Padding>,
+    pub cover_traffic: Option<Duration>,
 }
 
 impl Session {
-    pub fn new(mysql_pool: Pool<MySql>) -> Self {
-        let jwt_shared_secret = std::env::var("JWT_SHARED_SECRET")
-            .expect("Failed import JWT_SHARED_SECRET.");
-
-        let jwk = DecodingKey::from_secret(
-            &jwt_shared_secret.as_ref()
-        );
+    pub fn new(user_store: Arc<dyn UserStore>, tunnel_config: TunnelConfig) -> Self {
+        Self::with_verifier(user_store, tunnel_config, SessionVerifier::from_env())
+    }
 
+    /// Same as `new` with the given token verifier instead of the `JWT_*` configuration.
+    pub fn with_verifier(user_store: Arc<dyn UserStore>, tunnel_config: TunnelConfig, verifier: SessionVerifier) -> Self {
         let sessions_pool = Arc::new(
             RwLock::new(HashMap::new())
         );
 
         Self {
-            mysql_pool,
-            jwk,
-            sessions_pool
+            revocation: SessionRevocation::new(user_store.clone()),
+            user_store,
+            verifier,
+            tickets: SessionTickets::from_env(),
+            tunnel_config,
+            sessions_pool,
+            padding: Padding::from_env(),
+            cover_traffic: cover_traffic_from_env(),
         }
     }
 
-    pub async fn accept(self: Arc<Self>, (mut socket_stream, socket_address): (TcpStream, SocketAddr)) {
-        let mut buf = [0u8; 2048];
+    /// Seals resumption tickets with `tickets` instead of the `RESUMPTION_TICKET_*` configuration.
+    pub fn with_tickets(mut self, tickets: SessionTickets) -> Self {
+        self.tickets = tickets;
+        self
+    }
+
+    /// Pads sealed messages to sessions that negotiated `Capabilities::PADDING`.
+    pub fn with_padding(mut self, padding: Option<Padding>) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Reloads the token deny list and disabled users every `interval`.
+    pub fn with_revocation_interval(mut self, interval: Duration) -> Self {
+        self.revocation = self.revocation.with_interval(interval);
+        self
+    }
+
+    /// Sends idle padded sessions a cover packet per `interval`.
+    pub fn with_cover_traffic(mut self, interval: Option<Duration>) -> Self {
+        self.cover_traffic = interval;
+        self
+    }
+
+    /// Padding policy for a session with the negotiated `context`.
+    fn padding(&self, context: &SessionContext) -> Option<Padding> {
+        self.padding.clone().filter(|_| context.capabilities.contains(Capabilities::PADDING))
+    }
+
+    /// Padding of `ResumeApprove`, framed as the ticket's `capabilities` promise
+    /// since the client opens it before learning what was granted: empty when
+    /// our policy is gone.
+    fn resume_padding(&self, capabilities: Capabilities) -> Option<Padding> {
+        capabilities.contains(Capabilities::PADDING)
+            .then(|| self.padding.clone().unwrap_or(Padding::Mtu(0)))
+    }
+
+    /// Features granted in `Sign`: padding only with a policy of our own, so
+    /// both ends of a padded session pad every sealed message.
+    fn supported(&self) -> Capabilities {
+        match self.padding {
+            Some(_) => Capabilities::supported(),
+            None => Capabilities::supported().without(Capabilities::PADDING),
+        }
+    }
+
+    /// Runs the control connection of one client. Data frames of sessions that
+    /// negotiated `Capabilities::TCP_DATA` are handed to `tcp_data`. The stream is
+    /// a plain TCP connection or a WebSocket from the TLS listener.
+    pub async fn accept<S>(self: Arc<Self>, (mut socket_stream, socket_address): (S, SocketAddr), tcp_data: mpsc::Sender<(SocketAddr, Vec<u8>)>)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let mut received = Vec::new();
+        let mut framed = None;
         let mut context = SessionContext::new();
 
+        let terminated = context.terminated.clone();
+
         loop {
-            let handle = future::timeout(
-                Duration::from_secs(10),
-                ReadExt::read(&mut socket_stream, &mut buf),
-            ).await;
+            let handle = tokio::select! {
+                _ = terminated.notified() => {
+                    log::warn!("Session terminated by server.");
+                    break;
+                }
+                handle = future::timeout(
+                    Duration::from_secs(10),
+                    Self::next_message(&mut socket_stream, &mut received, &mut framed),
+                ) => handle,
+            };
 
             match handle {
-                Ok(Ok(0)) => {
+                Ok(Ok(None)) => {
                     println!("socket disconnect");
                     break;
                 }
-                Ok(Ok(n)) => {
-                    let pk = context.pk();
-                    let mut packet = PacketDecoder::new(&buf[..n], pk);
+                Ok(Ok(Some(message))) => {
+                    // once a key is agreed every message is sealed, anything else is dropped.
+                    let packet = match context.pk() {
+                        Some(pk) => PacketDecoder::open_padded(&message, &pk, &[], context.capabilities.contains(Capabilities::PADDING)),
+                        None => Some(PacketDecoder::new(&message)),
+                    };
+
+                    let Some(mut packet) = packet else {
+                        log::warn!("Unsealed message from {socket_address}, abort.");
+                        break;
+                    };
+
                     let opcode = packet.read_opcode();
 
                     match opcode {
                         MessageType::Sign if context.saturate == SessionSaturate::Init => {
-                            let remote_client_pk = packet.read_string();
+                            let Ok(remote_client_pk) = packet.try_read_string() else {
+                                log::warn!("Malformed Sign, abort.");
+                                break;
+                            };
+
+                            // clients predating negotiation end the message after the key.
+                            let (client_version, client_capabilities) = if packet.remaining() >= 6 {
+                                let (Ok(version), Ok(capabilities)) = (packet.try_read_uint16(), packet.try_read_uint32()) else {
+                                    log::warn!("Malformed Sign, abort.");
+                                    break;
+                                };
+
+                                let offered = (version, Capabilities::from_bits(capabilities));
+                                context.offered(offered.0, offered.1);
+                                offered
+                            } else {
+                                (PROTOCOL_VERSION_LEGACY, Capabilities::legacy())
+                            };
+
+                            let Some(protocol_version) = negotiate_version(client_version) else {
+                                log::warn!("Unsupported protocol version {client_version}, rejecting.");
+
+                                let mut packet = PacketEncoder::new();
+
+                                packet.write_opcode(MessageType::SignReject);
+                                packet.write_string("unsupported protocol version".as_ref());
+                                packet.write_u16(PROTOCOL_VERSION_MIN);
+                                packet.write_u16(PROTOCOL_VERSION_MAX);
+
+                                socket_stream.write_all(&Self::reply(packet.to_bytes(None), framed)).await.ok();
+                                break;
+                            };
+
+                            context.negotiated(
+                                protocol_version,
+                                client_capabilities.intersection(self.supported())
+                            );
 
                             let Ok(key_pair) = EphemeralPrivateKey::generate(
                                 &agreement::X25519,
@@ -90,20 +211,17 @@ impl Session {
                             // Используем borrow для получения изменяемой ссылки на key_pair
                             let Ok(ctx_shared_key) = agreement::agree_ephemeral(
                                 key_pair,
-                                &UnparsedPublicKey::new(&agreement::X25519, remote_client_pk),
+                                &UnparsedPublicKey::new(&agreement::X25519, &remote_client_pk),
                                 |material| material.to_vec()) else {
                                 log::error!("Failed create shared_key for session");
                                 break;
                             };
 
-                            let Ok(ctx_unbound_key) = UnboundKey::new(
-                                &ring::aead::AES_256_GCM, &ctx_shared_key) else {
+                            let Some(ctx_less_safe_key) = context.cipher.less_safe_key(&ctx_shared_key) else {
                                 log::error!("Failed create unbound_context_key.");
                                 break;
                             };
 
-                            let ctx_less_safe_key = LessSafeKey::new(ctx_unbound_key);
-
                             // клонируем ключ в сессию.
                             context.set_pk(
                                 ctx_less_safe_key.clone()
@@ -118,66 +236,205 @@ impl Session {
                             packet.write_string(
                                 local_context_pk.as_ref()
                             );
+                            packet.write_u16(context.protocol_version);
+                            packet.write_u32(context.capabilities.bits());
 
-                            let packet_bytes = packet.to_bytes(None);
+                            let packet_bytes = Self::reply(packet.to_bytes(None), framed);
 
-                            if socket_stream.write(&packet_bytes).await.is_err() {
+                            if socket_stream.write_all(&packet_bytes).await.is_err() {
                                 log::error!("Failed sent packet to session, abort.");
                                 break;
                             }
                         }
                         MessageType::SignApprove if context.saturate == SessionSaturate::WaitApprove => {
-                            let Ok(access_token) = String::from_utf8(packet.read_string()) else {
+                            let Ok(access_token) = packet.try_read_string().map(String::from_utf8) else {
+                                log::warn!("Malformed SignApprove, abort.");
+                                break;
+                            };
+
+                            let Ok(access_token) = access_token else {
                                 log::error!("failed convert access_token to str");
                                 break;
                             };
 
-                            let Ok(token_data_payload) = jsonwebtoken::decode::<SessionClaims>(&access_token, &self.jwk, &jsonwebtoken::Validation::new(jsonwebtoken::Algorithm::HS512)) else {
+                            let Ok(token_data_payload) = self.verifier.verify(&access_token) else {
                                 log::error!("failed decode session token payload");
                                 break;
                             };
 
-                            let Ok(payload) = sqlx::query_as::<_, User>("SELECT id, username, local_tunnel_address, INET_NTOA(local_tunnel_address) as local_tunnel_address_str FROM users WHERE id = ? and enabled = 1")
-                                .bind(token_data_payload.claims.identifier)
-                                .fetch_one(&self.mysql_pool).await else {
+                            if self.revocation.is_revoked(token_data_payload.claims.jti.as_deref()).await {
+                                log::warn!("Session token {:?} is revoked", token_data_payload.claims.jti);
+                                break;
+                            }
+
+                            let Ok(Some(payload)) = self.user_store.find_enabled(token_data_payload.claims.identifier).await else {
+                                log::warn!("User {} not found or disabled", token_data_payload.claims.identifier);
+                                break;
+                            };
+
+                            let Ok(ctx_sock_port) = packet.try_read_uint16() else {
+                                log::warn!("Malformed SignApprove, abort.");
                                 break;
                             };
 
-                            if let Some(_) = self.sessions_pool.read()
-                                .await
-                                .iter()
-                                .find(
-                                    |((_sock_addr, tunnel_addr, _), _)|
-                                    tunnel_addr.to_bits() == payload.local_tunnel_address
-                                )
-                            {
-                                log::warn!("Session exists remove context;");
+                            // the plaintext Sign exchange is only trusted once the client confirms it sealed.
+                            if let Err(err) = context.negotiation().verify(&mut packet, context.offered.is_some()) {
+                                log::warn!("Sign negotiation tampered with: {err}, abort.");
                                 break;
                             }
 
-                            let ctx_sock_port = packet.read_uint16();
-                            let mut packet = PacketEncoder::new();
+                            context.set_jti(token_data_payload.claims.jti);
+
+                            let (transport, tcp_outbound) = SessionTransport::negotiated(context.capabilities);
+
+                            let Some(session_id) = self.register(
+                                &payload,
+                                &context,
+                                SocketAddr::new(socket_address.ip(), ctx_sock_port),
+                                socket_address,
+                                transport,
+                                false
+                            ).await else {
+                                log::warn!("Session exists remove context;");
+                                break;
+                            };
+
+                            let (ticket, ticket_secret) = if context.capabilities.contains(Capabilities::RESUMPTION) {
+                                self.tickets.issue(&payload, &context)
+                            } else {
+                                (Vec::new(), Vec::new())
+                            };
+                            let mut packet = PacketEncoder::new().with_padding(self.padding(&context));
 
                             packet.write_opcode(MessageType::SignApprove);
                             packet.write_string("Привет, Мир!".as_ref());
-
-                            socket_stream.write(&packet.to_bytes(context.pk())).await.ok();
+                            packet.write_u32(session_id);
+                            packet.write_string(&ticket);
+                            packet.write_string(&ticket_secret);
+                            self.tunnel_config
+                                .for_client(Ipv4Addr::from(payload.local_tunnel_address))
+                                .write(&mut packet);
+                            context.negotiation().write(&mut packet);
+
+                            socket_stream.write_all(&Self::reply(packet.to_bytes(context.pk()), framed)).await.ok();
                             context.saturate(SessionSaturate::Success);
 
-                            let clone_session_pk = context.pk().clone();
-                            let session_payload = SessionPayload::new(
-                                payload.clone(),
-                                clone_session_pk
-                            );
+                            if let Some(tcp_outbound) = tcp_outbound {
+                                return Self::transmit_framed(socket_stream, socket_address, terminated, received, tcp_outbound, tcp_data).await;
+                            }
+                        },
+                        MessageType::Resume if context.saturate == SessionSaturate::Init => {
+                            let (Ok(ticket_bytes), Ok(remote_client_pk), Ok(ctx_sock_port)) = (
+                                packet.try_read_string(),
+                                packet.try_read_string(),
+                                packet.try_read_uint16(),
+                            ) else {
+                                log::warn!("Malformed Resume, abort.");
+                                break;
+                            };
+                            let proof = packet.try_read_string().unwrap_or_default();
+
+                            let Some(ticket) = self.tickets.open(&ticket_bytes) else {
+                                log::warn!("Invalid or expired resumption ticket, abort.");
+                                break;
+                            };
+
+                            if !verify_resumption_proof(&ticket.secret, &ticket_bytes, &remote_client_pk, ctx_sock_port, &proof) {
+                                log::warn!("Resumption ticket presented without proof of its secret, abort.");
+                                break;
+                            }
+
+                            if !self.tickets.spend(&ticket) {
+                                log::warn!("Resumption ticket presented twice, abort.");
+                                break;
+                            }
+
+                            if self.revocation.is_revoked(ticket.jti.as_deref()).await {
+                                log::warn!("Resumption ticket token {:?} is revoked", ticket.jti);
+                                break;
+                            }
+
+                            // the ticket carries the user, resuming needs no user store query.
+                            if self.revocation.is_disabled(ticket.user.id).await {
+                                log::warn!("User {} disabled", ticket.user.id);
+                                break;
+                            }
+
+                            let user = ticket.user.clone();
+
+                            let Ok(key_pair) = EphemeralPrivateKey::generate(
+                                &agreement::X25519,
+                                &SystemRandom::new(),
+                            ) else {
+                                log::error!("failed generate private key");
+                                return;
+                            };
+
+                            let Ok(local_context_pk) = key_pair.compute_public_key() else {
+                                log::error!("Failed compuse session public_key.");
+                                return;
+                            };
+
+                            // features disabled since the ticket was issued, e.g. a removed
+                            // padding policy, are not restored.
+                            let resumed = Capabilities::from_bits(ticket.capabilities);
+                            context.offered(ticket.protocol_version, resumed);
+                            context.negotiated(ticket.protocol_version, resumed.intersection(self.supported()));
 
-                            let session_link = (
+                            let Ok(Some(ctx_less_safe_key)) = agreement::agree_ephemeral(
+                                key_pair,
+                                &UnparsedPublicKey::new(&agreement::X25519, remote_client_pk),
+                                |material| resumption_key(&ticket.secret, material, context.cipher)) else {
+                                log::error!("Failed create shared_key for resumed session");
+                                break;
+                            };
+
+                            context.set_pk(ctx_less_safe_key.clone());
+                            context.set_jti(ticket.jti.clone());
+
+                            let (transport, tcp_outbound) = SessionTransport::negotiated(context.capabilities);
+
+                            // the proof shows the client holds the ticket secret of the previous
+                            // session, which the server may not have noticed dropping yet.
+                            let Some(session_id) = self.register(
+                                &user,
+                                &context,
                                 SocketAddr::new(socket_address.ip(), ctx_sock_port),
-                                Ipv4Addr::from(payload.local_tunnel_address),
-                                socket_address
-                            );
+                                socket_address,
+                                transport,
+                                true
+                            ).await else {
+                                break;
+                            };
 
-                            let mut sessions = self.sessions_pool.write().await;
-                            sessions.insert(session_link, session_payload);
+                            let (next_ticket, next_ticket_secret) = self.tickets.issue(&user, &context);
+                            let mut sealed = PacketEncoder::new().with_padding(self.resume_padding(resumed));
+
+                            sealed.write_u32(session_id);
+                            sealed.write_string(&next_ticket);
+                            sealed.write_string(&next_ticket_secret);
+                            self.tunnel_config
+                                .for_client(Ipv4Addr::from(user.local_tunnel_address))
+                                .write(&mut sealed);
+                            context.negotiation().write(&mut sealed);
+
+                            let mut packet = PacketEncoder::new();
+
+                            packet.write_opcode(MessageType::ResumeApprove);
+                            packet.write_string(local_context_pk.as_ref());
+                            packet.write_string(&sealed.to_bytes(context.pk()));
+
+                            if socket_stream.write_all(&Self::reply(packet.to_bytes(None), framed)).await.is_err() {
+                                log::error!("Failed sent packet to session, abort.");
+                                break;
+                            }
+
+                            log::info!("Session {session_id} resumed for {}", user.username);
+                            context.saturate(SessionSaturate::Success);
+
+                            if let Some(tcp_outbound) = tcp_outbound {
+                                return Self::transmit_framed(socket_stream, socket_address, terminated, received, tcp_outbound, tcp_data).await;
+                            }
                         },
                         MessageType::Trace if context.saturate == SessionSaturate::Success => { },
                         _ => {
@@ -199,6 +456,153 @@ impl Session {
         }
     }
 
+    /// Carries the data packets of an approved session as frames on its control
+    /// connection: queued outbound packets are written, inbound frames are handed
+    /// to `tcp_data` for `SessionTransmitter` to authenticate. Client traces keep
+    /// the connection from timing out.
+    async fn transmit_framed<S>(
+        socket_stream: S,
+        control_address: SocketAddr,
+        terminated: Arc<Notify>,
+        mut frames: Vec<u8>,
+        mut tcp_outbound: mpsc::Receiver<Vec<u8>>,
+        tcp_data: mpsc::Sender<(SocketAddr, Vec<u8>)>,
+    )
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send,
+    {
+        let (mut reader, mut socket_stream) = socket_stream.split();
+        let mut buf = [0u8; 4096];
+
+        loop {
+            tokio::select! {
+                _ = terminated.notified() => {
+                    log::warn!("Session terminated by server.");
+                    break;
+                }
+                Some(packet) = tcp_outbound.recv() => {
+                    if socket_stream.write_all(&frame(&packet)).await.is_err() {
+                        log::error!("Failed sent data frame to session, abort.");
+                        break;
+                    }
+                }
+                handle = future::timeout(Duration::from_secs(10), ReadExt::read(&mut reader, &mut buf)) => {
+                    let n = match handle {
+                        Ok(Ok(0)) | Ok(Err(_)) => break,
+                        Ok(Ok(n)) => n,
+                        Err(err) => {
+                            log::warn!("Client timed out {err}");
+                            break;
+                        }
+                    };
+
+                    frames.extend_from_slice(&buf[..n]);
+
+                    while let Some(packet) = next_frame(&mut frames) {
+                        if tcp_data.send((control_address, packet)).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Next message of a control connection, `None` once the client is gone.
+    /// Clients frame their messages like data packets; clients predating
+    /// framing write one message at a time, each read is taken as one.
+    async fn next_message<S>(socket_stream: &mut S, received: &mut Vec<u8>, framed: &mut Option<bool>) -> std::io::Result<Option<Vec<u8>>>
+    where
+        S: AsyncRead + Unpin,
+    {
+        let mut buf = [0u8; 4096];
+
+        loop {
+            if *framed == Some(true) {
+                if let Some(message) = next_frame(received) {
+                    return Ok(Some(message));
+                }
+            }
+
+            let n = ReadExt::read(socket_stream, &mut buf).await?;
+
+            if n == 0 {
+                return Ok(None);
+            }
+
+            // frame lengths below 0x2200 start with a byte below every opcode.
+            if !*framed.get_or_insert(buf[0] < u8::from(MessageType::Sign)) {
+                return Ok(Some(buf[..n].to_vec()));
+            }
+
+            received.extend_from_slice(&buf[..n]);
+        }
+    }
+
+    /// Frames a reply for clients that frame their own messages.
+    fn reply(message: Vec<u8>, framed: Option<bool>) -> Vec<u8> {
+        match framed {
+            Some(true) => frame(&message),
+            _ => message,
+        }
+    }
+
+    /// Adds the session to the pool and returns its id. An existing session of
+    /// the same tunnel address is either kept (registration refused) or replaced.
+    async fn register(
+        &self,
+        user: &User,
+        context: &SessionContext,
+        endpoint: SocketAddr,
+        control_address: SocketAddr,
+        transport: SessionTransport,
+        replace: bool,
+    ) -> Option<u32> {
+        let mut sessions = self.sessions_pool.write().await;
+
+        if replace {
+            sessions.retain(|_, session| {
+                if session.tunnel_address().to_bits() != user.local_tunnel_address {
+                    return true;
+                }
+
+                session.terminate();
+                false
+            });
+        } else if sessions.values().any(|session| session.tunnel_address().to_bits() == user.local_tunnel_address) {
+            return None;
+        }
+
+        let session_id = Self::session_id(&sessions);
+
+        sessions.insert(session_id, SessionPayload::new(
+            user.clone(),
+            context,
+            endpoint,
+            control_address
+        )
+            .with_transport(transport)
+            .with_padding(self.padding(context)));
+
+        Some(session_id)
+    }
+
+    /// Picks a random id not used by another session; zero is never handed out.
+    fn session_id(sessions: &HashMap<u32, SessionPayload>) -> u32 {
+        let rng = SystemRandom::new();
+
+        loop {
+            let mut session_id = [0u8; 4];
+            rng.fill(&mut session_id).unwrap();
+
+            let session_id = u32::from_be_bytes(session_id);
+
+            if session_id != 0 && !sessions.contains_key(&session_id) {
+                return session_id;
+            }
+        }
+    }
+
     // pub async fn example(&self, x: &Arc<RwLock<HashMap<(SocketAddr, Ipv4Addr), SessionPayload>>>, mut tunnel_tx: tokio::io::WriteHalf<Tun>) {
 
 }
\ No newline at end of file
@@ -1,22 +1,33 @@
+use ring::rand::{SecureRandom, SystemRandom};
 use serde::{Deserialize, Serialize};
 use time::{Duration, OffsetDateTime};
 
 #[derive(Debug, Serialize, Deserialize, PartialEq)]
-pub(crate) struct SessionClaims {
+pub struct SessionClaims {
     #[serde(with = "jwt_numeric_date")]
     pub(crate) iat: OffsetDateTime,
     #[serde(with = "jwt_numeric_date")]
     pub(crate) exp: OffsetDateTime,
 
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) jti: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty", with = "jwt_audience")]
+    pub(crate) aud: Vec<String>,
+
     pub(crate) identifier: u32,
     pub(crate) username: String
 }
 
 impl SessionClaims {
-    #[allow(dead_code)]
     pub fn new(identifier: u32, username: &str) -> Self {
+        Self::with_ttl(identifier, username, Duration::days(365))
+    }
+
+    pub fn with_ttl(identifier: u32, username: &str, ttl: Duration) -> Self {
         let iat = OffsetDateTime::now_utc();
-        let exp = iat + Duration::days(365);
+        let exp = iat + ttl;
 
         let iat = iat
             .date()
@@ -30,13 +41,24 @@ impl SessionClaims {
             .unwrap()
             .assume_utc();
 
+        let mut jti = [0u8; 16];
+        SystemRandom::new().fill(&mut jti).unwrap();
+
         Self {
             identifier,
+            jti: Some(jti.iter().map(|byte| format!("{byte:02x}")).collect()),
             username: String::from(username),
+            iss: None,
+            aud: Vec::new(),
             exp,
             iat
         }
     }
+
+    /// Token id, the key of the revocation deny list.
+    pub fn jti(&self) -> Option<&str> {
+        self.jti.as_deref()
+    }
 }
 
 mod jwt_numeric_date {
@@ -61,4 +83,37 @@ mod jwt_numeric_date {
         OffsetDateTime::from_unix_timestamp(i64::deserialize(deserializer)?)
             .map_err(|_| serde::de::Error::custom("invalid Unix timestamp value"))
     }
+}
+
+mod jwt_audience {
+    //! `aud` is either a single string or an array of strings (RFC 7519 section 4.1.3)
+    use serde::{self, Deserialize, Deserializer, Serializer};
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Audience {
+        Single(String),
+        Multiple(Vec<String>),
+    }
+
+    /// Serializes a single audience as a plain string
+    pub fn serialize<S>(aud: &[String], serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match aud {
+            [aud] => serializer.serialize_str(aud),
+            aud => serializer.collect_seq(aud),
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(match Audience::deserialize(deserializer)? {
+            Audience::Single(aud) => vec![aud],
+            Audience::Multiple(aud) => aud,
+        })
+    }
 }
\ No newline at end of file
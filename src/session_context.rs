@@ -1,9 +1,19 @@
+use std::sync::Arc;
 use ring::aead::LessSafeKey;
+use tokio::sync::Notify;
+use crate::cipher_suite::CipherSuite;
+use crate::protocol::{Capabilities, Negotiation, PROTOCOL_VERSION_LEGACY};
 use crate::session_saturate::SessionSaturate;
 
 pub struct SessionContext {
     pub less_safe_key: Option<LessSafeKey>,
     pub saturate: SessionSaturate,
+    pub jti: Option<String>,
+    pub protocol_version: u16,
+    pub capabilities: Capabilities,
+    pub cipher: CipherSuite,
+    pub offered: Option<(u16, Capabilities)>,
+    pub terminated: Arc<Notify>,
 }
 
 impl SessionContext {
@@ -12,6 +22,12 @@ impl SessionContext {
         Self {
             less_safe_key: None,
             saturate: SessionSaturate::Init,
+            jti: None,
+            protocol_version: PROTOCOL_VERSION_LEGACY,
+            capabilities: Capabilities::legacy(),
+            cipher: CipherSuite::negotiated(Capabilities::legacy()),
+            offered: None,
+            terminated: Arc::new(Notify::new()),
         }
     }
 
@@ -23,7 +39,43 @@ impl SessionContext {
         self.less_safe_key.clone()
     }
 
+    pub fn set_jti(&mut self, jti: Option<String>) {
+        self.jti = jti;
+    }
+
+    /// Records the version and features agreed on in `Sign`, including the cipher suite.
+    pub fn negotiated(&mut self, protocol_version: u16, capabilities: Capabilities) {
+        self.protocol_version = protocol_version;
+        self.capabilities = capabilities;
+        self.cipher = CipherSuite::negotiated(capabilities);
+    }
+
+    /// Records the version and features the client offered in `Sign`,
+    /// absent for clients predating negotiation.
+    pub fn offered(&mut self, offered_version: u16, offered_capabilities: Capabilities) {
+        self.offered = Some((offered_version, offered_capabilities));
+    }
+
+    /// Terms of the exchange as the server saw them, for the sealed echo.
+    pub fn negotiation(&self) -> Negotiation {
+        let (offered_version, offered_capabilities) = self.offered
+            .unwrap_or((PROTOCOL_VERSION_LEGACY, Capabilities::legacy()));
+
+        Negotiation {
+            offered_version,
+            offered_capabilities,
+            protocol_version: self.protocol_version,
+            capabilities: self.capabilities,
+        }
+    }
+
     pub fn saturate(&mut self, saturate: SessionSaturate) {
         self.saturate = saturate;
     }
+}
+
+impl Default for SessionContext {
+    fn default() -> Self {
+        Self::new()
+    }
 }
\ No newline at end of file
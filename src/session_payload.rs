@@ -1,21 +1,128 @@
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use ring::aead::LessSafeKey;
+use tokio::sync::Notify;
+use crate::padding::Padding;
+use crate::replay_window::ReplayWindow;
+use crate::session_context::SessionContext;
+use crate::session_transport::SessionTransport;
 use crate::user::User;
 
 pub struct SessionPayload {
-    #[allow(dead_code)]
     payload: User,
     less_safe_key: Option<LessSafeKey>,
+    tunnel_address: Ipv4Addr,
+    control_address: SocketAddr,
+    endpoint: SocketAddr,
+    transport: SessionTransport,
+    padding: Option<Padding>,
+    counter: AtomicU64,
+    replay_window: Mutex<ReplayWindow>,
+    dns_replay_window: Mutex<ReplayWindow>,
+    jti: Option<String>,
+    terminated: Arc<Notify>,
 }
 
 impl SessionPayload {
-    pub fn new(payload: User, less_safe_key: Option<LessSafeKey>) -> Self {
+    pub fn new(
+        payload: User,
+        context: &SessionContext,
+        endpoint: SocketAddr,
+        control_address: SocketAddr,
+    ) -> Self {
         Self {
+            tunnel_address: Ipv4Addr::from(payload.local_tunnel_address),
             payload,
-            less_safe_key,
+            less_safe_key: context.pk(),
+            control_address,
+            endpoint,
+            transport: SessionTransport::Udp,
+            padding: None,
+            counter: AtomicU64::new(0),
+            replay_window: Mutex::new(ReplayWindow::new()),
+            dns_replay_window: Mutex::new(ReplayWindow::new()),
+            jti: context.jti.clone(),
+            terminated: context.terminated.clone(),
         }
     }
 
+    pub fn with_transport(mut self, transport: SessionTransport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn transport(&self) -> &SessionTransport {
+        &self.transport
+    }
+
+    /// Padding policy for the session's packets, when it negotiated padding.
+    pub fn with_padding(mut self, padding: Option<Padding>) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    pub fn padding(&self) -> Option<Padding> {
+        self.padding.clone()
+    }
+
+    pub fn user(&self) -> &User {
+        &self.payload
+    }
+
     pub fn less_safe_key(&self) -> Option<LessSafeKey> {
         self.less_safe_key.clone()
     }
-}
\ No newline at end of file
+
+    pub fn tunnel_address(&self) -> Ipv4Addr {
+        self.tunnel_address
+    }
+
+    pub fn control_address(&self) -> SocketAddr {
+        self.control_address
+    }
+
+    /// Udp address the client was last seen at, used as reply destination.
+    pub fn endpoint(&self) -> SocketAddr {
+        self.endpoint
+    }
+
+    pub fn set_endpoint(&mut self, endpoint: SocketAddr) {
+        self.endpoint = endpoint;
+    }
+
+    /// Counter for the next server to client data packet.
+    pub fn next_counter(&self) -> u64 {
+        self.counter.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Counter the next server to client data packet will get, without taking it.
+    pub fn counter(&self) -> u64 {
+        self.counter.load(Ordering::Relaxed)
+    }
+
+    /// Checks a client counter of an already authenticated packet against replays.
+    pub fn accept_counter(&self, counter: u64) -> bool {
+        self.replay_window.lock()
+            .map(|mut replay_window| replay_window.accept(counter))
+            .unwrap_or(false)
+    }
+
+    /// Same for the counters of the session's queries to the DNS proxy,
+    /// which the client numbers apart from its data packets.
+    pub fn accept_dns_counter(&self, counter: u64) -> bool {
+        self.dns_replay_window.lock()
+            .map(|mut replay_window| replay_window.accept(counter))
+            .unwrap_or(false)
+    }
+
+    /// Token id the session was established with, carried over on resumption.
+    pub fn jti(&self) -> Option<&str> {
+        self.jti.as_deref()
+    }
+
+    /// Wakes the control connection of the session so it closes.
+    pub fn terminate(&self) {
+        self.terminated.notify_one();
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use time::OffsetDateTime;
+use tokio::sync::RwLock;
+use crate::session::SessionsPool;
+use crate::user_store::{UserStore, UserStoreResult};
+
+/// In-memory copy of the user store's token deny list and disabled users,
+/// refreshed every `REVOCATION_REFRESH_INTERVAL` seconds (default 30).
+pub struct SessionRevocation {
+    user_store: Arc<dyn UserStore>,
+    revoked: RwLock<HashSet<String>>,
+    disabled: RwLock<HashSet<u32>>,
+    interval: Duration,
+}
+
+impl SessionRevocation {
+    pub fn new(user_store: Arc<dyn UserStore>) -> Self {
+        let interval = std::env::var("REVOCATION_REFRESH_INTERVAL")
+            .ok()
+            .map(|interval| interval.parse::<u64>().expect("Failed parse REVOCATION_REFRESH_INTERVAL."))
+            .unwrap_or(30);
+
+        Self {
+            user_store,
+            revoked: RwLock::new(HashSet::new()),
+            disabled: RwLock::new(HashSet::new()),
+            interval: Duration::from_secs(interval),
+        }
+    }
+
+    /// Refreshes every `interval` instead of the configured one.
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub async fn is_revoked(&self, jti: Option<&str>) -> bool {
+        match jti {
+            Some(jti) => self.revoked.read().await.contains(jti),
+            None => false,
+        }
+    }
+
+    /// Whether the user was disabled at the last refresh, lets resumption
+    /// skip the user store.
+    pub async fn is_disabled(&self, user_id: u32) -> bool {
+        self.disabled.read().await.contains(&user_id)
+    }
+
+    /// Reloads the deny list and disabled users, returns whether the deny list
+    /// changed. Entries of tokens past their expiry are dropped, those tokens
+    /// are refused anyway.
+    pub async fn refresh(&self) -> UserStoreResult<bool> {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        let revoked = self.user_store.revoked_tokens()
+            .await?
+            .into_iter()
+            .filter(|revoked| revoked.exp.is_none_or(|exp| exp > now))
+            .map(|revoked| revoked.jti)
+            .collect::<HashSet<_>>();
+
+        *self.disabled.write().await = self.user_store.disabled_users()
+            .await?
+            .into_iter()
+            .collect();
+
+        let mut current = self.revoked.write().await;
+
+        if *current == revoked {
+            return Ok(false);
+        }
+
+        *current = revoked;
+        Ok(true)
+    }
+
+    /// Keeps the deny list fresh and terminates live sessions whose token got revoked.
+    pub async fn poll(&self, sessions_pool: &SessionsPool) {
+        loop {
+            match self.refresh().await {
+                Ok(true) => {
+                    let revoked = self.revoked.read().await;
+                    let mut sessions = sessions_pool.write().await;
+
+                    sessions.retain(|session_id, session| {
+                        if !session.jti().is_some_and(|jti| revoked.contains(jti)) {
+                            return true;
+                        }
+
+                        log::warn!("Session {session_id} token revoked, terminating.");
+                        session.terminate();
+                        false
+                    });
+                }
+                Ok(false) => {}
+                Err(err) => log::error!("Failed refresh revoked tokens: {err}"),
+            }
+
+            tokio::time::sleep(self.interval).await;
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use std::str::FromStr;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use crate::session_claims::SessionClaims;
+
+/// Signs `SessionClaims` with the configured key, the counterpart of `SessionVerifier`.
+pub struct SessionSigner {
+    header: Header,
+    key: EncodingKey,
+    issuer: Option<String>,
+    audience: Option<String>,
+}
+
+impl SessionSigner {
+    /// Configuration:
+    /// - `JWT_SIGNING_ALGORITHM` (default: the first of `JWT_ALGORITHMS`, or `HS512`);
+    /// - `JWT_SIGNING_KEY` PEM private key for RS*/PS*/ES*/EdDSA, HS* use `JWT_SHARED_SECRET`;
+    /// - `JWT_SIGNING_KID` optional key id put in the token header;
+    /// - `JWT_ISSUER`, `JWT_AUDIENCE` the first configured value is stamped into the claims.
+    pub fn from_env() -> Self {
+        let algorithm = std::env::var("JWT_SIGNING_ALGORITHM")
+            .or_else(|_| std::env::var("JWT_ALGORITHMS")
+                .map(|algorithms| algorithms.split(',').next().unwrap_or_default().trim().to_string()))
+            .unwrap_or_else(|_| String::from("HS512"));
+
+        let algorithm = Algorithm::from_str(&algorithm)
+            .unwrap_or_else(|_| panic!("Unsupported JWT algorithm {algorithm}."));
+
+        let key = match algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                let jwt_shared_secret = std::env::var("JWT_SHARED_SECRET")
+                    .expect("Failed import JWT_SHARED_SECRET.");
+
+                EncodingKey::from_secret(jwt_shared_secret.as_ref())
+            }
+            _ => {
+                let path = std::env::var("JWT_SIGNING_KEY")
+                    .expect("Failed import JWT_SIGNING_KEY.");
+
+                let pem = std::fs::read(&path)
+                    .unwrap_or_else(|_| panic!("Failed read JWT signing key {path}."));
+
+                match algorithm {
+                    Algorithm::ES256 | Algorithm::ES384 => EncodingKey::from_ec_pem(&pem),
+                    Algorithm::EdDSA => EncodingKey::from_ed_pem(&pem),
+                    _ => EncodingKey::from_rsa_pem(&pem),
+                }.unwrap_or_else(|_| panic!("Unsupported JWT signing key {path}."))
+            }
+        };
+
+        let mut header = Header::new(algorithm);
+        header.kid = std::env::var("JWT_SIGNING_KID").ok();
+
+        let first = |name: &str| std::env::var(name)
+            .ok()
+            .and_then(|values| values.split(',').next().map(|value| value.trim().to_string()));
+
+        Self {
+            header,
+            key,
+            issuer: first("JWT_ISSUER"),
+            audience: first("JWT_AUDIENCE"),
+        }
+    }
+
+    /// Signer of `algorithm` tokens with a shared `secret`, the counterpart of
+    /// `SessionVerifier::from_secret`.
+    pub fn from_secret(algorithm: Algorithm, secret: &[u8]) -> Self {
+        Self {
+            header: Header::new(algorithm),
+            key: EncodingKey::from_secret(secret),
+            issuer: None,
+            audience: None,
+        }
+    }
+
+    pub fn sign(&self, claims: &mut SessionClaims) -> jsonwebtoken::errors::Result<String> {
+        if claims.iss.is_none() {
+            claims.iss = self.issuer.clone();
+        }
+
+        if claims.aud.is_empty() {
+            claims.aud.extend(self.audience.clone());
+        }
+
+        jsonwebtoken::encode(&self.header, claims, &self.key)
+    }
+}
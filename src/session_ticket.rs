@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use ring::aead::{LessSafeKey, UnboundKey, AES_256_GCM};
+use ring::hkdf::{Salt, HKDF_SHA256};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use time::{Duration, OffsetDateTime};
+use crate::cipher_suite::CipherSuite;
+use crate::packet_decoder::PacketDecoder;
+use crate::packet_encoder::PacketEncoder;
+use crate::protocol::{Capabilities, PROTOCOL_VERSION_LEGACY};
+use crate::session_context::SessionContext;
+use crate::user::User;
+
+const TICKET_KEY_INFO: &[u8] = b"smo resumption ticket";
+const RESUMPTION_KEY_INFO: &[u8] = b"smo resumption session";
+const RESUMPTION_PROOF_INFO: &[u8] = b"smo resumption proof";
+
+/// Ticket content, readable only by the server which sealed it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SessionTicket {
+    pub user: User,
+    pub jti: Option<String>,
+    pub secret: Vec<u8>,
+    pub exp: i64,
+    #[serde(default = "legacy_protocol_version")]
+    pub protocol_version: u16,
+    #[serde(default = "legacy_capabilities")]
+    pub capabilities: u32,
+}
+
+fn legacy_protocol_version() -> u16 {
+    PROTOCOL_VERSION_LEGACY
+}
+
+fn legacy_capabilities() -> u32 {
+    Capabilities::legacy().bits()
+}
+
+/// Issues and opens resumption tickets letting a client re-establish a session
+/// within `ttl` in one round trip, without presenting its access token again.
+/// Each ticket is accepted once.
+pub struct SessionTickets {
+    less_safe_key: LessSafeKey,
+    ttl: Duration,
+    spent: Mutex<HashMap<Vec<u8>, i64>>,
+}
+
+impl SessionTickets {
+    /// Reads `RESUMPTION_TICKET_KEY` and `RESUMPTION_TICKET_TTL` (seconds, default 3600).
+    /// Without a configured key tickets are sealed with a random one and do not
+    /// survive a restart.
+    pub fn from_env() -> Self {
+        let ttl = std::env::var("RESUMPTION_TICKET_TTL")
+            .ok()
+            .map(|ttl| ttl.parse::<i64>().expect("Failed parse RESUMPTION_TICKET_TTL."))
+            .unwrap_or(3600);
+
+        let tickets = match std::env::var("RESUMPTION_TICKET_KEY") {
+            Ok(ticket_key) => Self::from_secret(ticket_key.as_bytes()),
+            Err(_) => {
+                let mut key_bytes = [0u8; 32];
+                SystemRandom::new().fill(&mut key_bytes)
+                    .expect("Failed generate resumption ticket key.");
+
+                Self::new(UnboundKey::new(&AES_256_GCM, &key_bytes)
+                    .expect("Failed create resumption ticket key."))
+            }
+        };
+
+        Self { ttl: Duration::seconds(ttl), ..tickets }
+    }
+
+    /// Tickets sealed with a key derived from `ticket_key`, valid for an hour.
+    /// Servers sharing the key accept each other's tickets.
+    pub fn from_secret(ticket_key: &[u8]) -> Self {
+        Self::new(Salt::new(HKDF_SHA256, TICKET_KEY_INFO)
+            .extract(ticket_key)
+            .expand(&[TICKET_KEY_INFO], &AES_256_GCM)
+            .expect("Failed derive resumption ticket key.")
+            .into())
+    }
+
+    fn new(unbound_key: UnboundKey) -> Self {
+        Self {
+            less_safe_key: LessSafeKey::new(unbound_key),
+            ttl: Duration::hours(1),
+            spent: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Seals a ticket for the user, returns it with the resumption secret
+    /// that must be handed to the client over the encrypted control channel.
+    /// The negotiated version and features are carried over to the resumed session.
+    pub fn issue(&self, user: &User, context: &SessionContext) -> (Vec<u8>, Vec<u8>) {
+        let mut secret = vec![0u8; 32];
+        SystemRandom::new().fill(&mut secret).unwrap();
+
+        let ticket = SessionTicket {
+            user: user.clone(),
+            jti: context.jti.clone(),
+            secret: secret.clone(),
+            exp: (OffsetDateTime::now_utc() + self.ttl).unix_timestamp(),
+            protocol_version: context.protocol_version,
+            capabilities: context.capabilities.bits(),
+        };
+
+        let mut packet = PacketEncoder::new();
+        packet.write_string(&serde_json::to_vec(&ticket).unwrap());
+
+        (packet.to_bytes(Some(self.less_safe_key.clone())), secret)
+    }
+
+    /// Opens a ticket, `None` when it was not sealed by us or has expired.
+    pub fn open(&self, ticket: &[u8]) -> Option<SessionTicket> {
+        let mut packet = PacketDecoder::open(ticket, &self.less_safe_key, &[])?;
+        let ticket = serde_json::from_slice::<SessionTicket>(&packet.try_read_string().ok()?).ok()?;
+
+        if ticket.exp < OffsetDateTime::now_utc().unix_timestamp() {
+            return None;
+        }
+
+        Some(ticket)
+    }
+
+    /// Marks an opened ticket as used, `false` when it was presented before.
+    /// Used tickets are remembered until they expire.
+    pub fn spend(&self, ticket: &SessionTicket) -> bool {
+        let now = OffsetDateTime::now_utc().unix_timestamp();
+
+        let Ok(mut spent) = self.spent.lock() else {
+            return false;
+        };
+
+        spent.retain(|_, exp| *exp >= now);
+        spent.insert(ticket.secret.clone(), ticket.exp).is_none()
+    }
+}
+
+/// Session key of a resumed session, bound to both the fresh key agreement
+/// and the secret of the presented ticket.
+pub fn resumption_key(secret: &[u8], shared_key: &[u8], cipher: CipherSuite) -> Option<LessSafeKey> {
+    let unbound_key: UnboundKey = Salt::new(HKDF_SHA256, secret)
+        .extract(shared_key)
+        .expand(&[RESUMPTION_KEY_INFO], cipher.algorithm())
+        .ok()?
+        .into();
+
+    Some(LessSafeKey::new(unbound_key))
+}
+
+/// Proof of the ticket secret sent along with the ticket in `Resume`, bound
+/// to the client's fresh public key and udp port.
+pub fn resumption_proof(secret: &[u8], ticket: &[u8], public_key: &[u8], udp_port: u16) -> Vec<u8> {
+    hmac::sign(&resumption_proof_key(secret), &resumption_proof_input(ticket, public_key, udp_port))
+        .as_ref()
+        .to_vec()
+}
+
+/// Checks a `resumption_proof` in constant time.
+pub fn verify_resumption_proof(secret: &[u8], ticket: &[u8], public_key: &[u8], udp_port: u16, proof: &[u8]) -> bool {
+    hmac::verify(&resumption_proof_key(secret), &resumption_proof_input(ticket, public_key, udp_port), proof).is_ok()
+}
+
+fn resumption_proof_key(secret: &[u8]) -> hmac::Key {
+    Salt::new(HKDF_SHA256, secret)
+        .extract(&[])
+        .expand(&[RESUMPTION_PROOF_INFO], hmac::HMAC_SHA256)
+        .expect("Failed derive resumption proof key.")
+        .into()
+}
+
+fn resumption_proof_input(ticket: &[u8], public_key: &[u8], udp_port: u16) -> Vec<u8> {
+    let mut packet = PacketEncoder::new();
+
+    packet.write_string(ticket);
+    packet.write_string(public_key);
+    packet.write_u16(udp_port);
+
+    packet.to_bytes(None)
+}
@@ -1,49 +1,155 @@
-use std::collections::HashMap;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use async_std::net::UdpSocket;
-use tokio::io::{AsyncWriteExt, WriteHalf};
-use tokio::sync::RwLock;
-use tokio_tun::Tun;
+use tokio::sync::mpsc;
+use crate::data_header::{DataHeader, DATAGRAM_BUFFER_LEN, DATA_HEADER_LEN};
+use crate::dns_intercept::DnsInterceptor;
+use crate::message_type::MessageType;
+use crate::obfuscation::Obfuscation;
 use crate::packet_decoder::PacketDecoder;
-use crate::session_payload::SessionPayload;
+use crate::session::SessionsPool;
+use crate::tunnel_device::TunnelDevice;
 
 pub struct SessionTransmitter<'a> {
-    sessions_pool: &'a Arc<RwLock<HashMap<(SocketAddr, Ipv4Addr, SocketAddr), SessionPayload>>>,
-    tunnel_tx: WriteHalf<Tun>,
-    udp_socket: &'a UdpSocket
+    sessions_pool: &'a SessionsPool,
+    tunnel: Arc<dyn TunnelDevice>,
+    udp_socket: &'a UdpSocket,
+    dns_interceptor: Option<Arc<DnsInterceptor>>,
+    obfuscation: Option<Obfuscation>,
+    tcp_data: Option<mpsc::Receiver<(SocketAddr, Vec<u8>)>>,
 }
 
 impl<'a> SessionTransmitter<'a> {
     pub fn new(
-            sessions_pool: &'a Arc<RwLock<HashMap<(SocketAddr, Ipv4Addr, SocketAddr),
-            SessionPayload>>>,
-            tunnel_tx: WriteHalf<Tun>,
+            sessions_pool: &'a SessionsPool,
+            tunnel: Arc<dyn TunnelDevice>,
             udp_socket: &'a UdpSocket
     ) -> Self {
         Self {
             sessions_pool,
-            tunnel_tx,
-            udp_socket
+            tunnel,
+            udp_socket,
+            dns_interceptor: None,
+            obfuscation: None,
+            tcp_data: None,
         }
     }
 
+    /// Also takes data packets framed on control connections, see `Session::accept`.
+    pub fn with_tcp_data(mut self, tcp_data: mpsc::Receiver<(SocketAddr, Vec<u8>)>) -> Self {
+        self.tcp_data = Some(tcp_data);
+        self
+    }
+
+    /// Answers the sessions' port 53 packets instead of writing them to the tunnel.
+    pub fn with_dns_interceptor(mut self, dns_interceptor: Option<Arc<DnsInterceptor>>) -> Self {
+        self.dns_interceptor = dns_interceptor;
+        self
+    }
+
+    /// Unmasks datagrams obfuscated with the shared key, dropping any others.
+    pub fn with_obfuscation(mut self, obfuscation: Option<Obfuscation>) -> Self {
+        self.obfuscation = obfuscation;
+        self
+    }
+
     pub async fn poll(&mut self) {
-        let mut buf = [0u8; 2048];
+        let mut buf = [0u8; DATAGRAM_BUFFER_LEN];
+        let mut tcp_data = self.tcp_data.take();
+
+        loop {
+            tokio::select! {
+                received = self.udp_socket.recv_from(&mut buf) => {
+                    let Ok((n, sock_addr)) = received else {
+                        break;
+                    };
 
-        while let Ok((n, sock_addr)) = self.udp_socket.recv_from(&mut buf).await {
+                    match &self.obfuscation {
+                        Some(obfuscation) => match obfuscation.open_datagram(&buf[..n]) {
+                            Some(datagram) => self.receive(&datagram, sock_addr, true).await,
+                            None => log::error!("Malformed obfuscated datagram from {sock_addr}"),
+                        },
+                        None => self.receive(&buf[..n], sock_addr, true).await,
+                    }
+                }
+                Some((control_address, packet)) = async {
+                    match &mut tcp_data {
+                        Some(tcp_data) => tcp_data.recv().await,
+                        None => std::future::pending().await,
+                    }
+                } => self.receive(&packet, control_address, false).await,
+            }
+        }
+    }
+
+    /// Authenticates a data packet and writes its frame to the tunnel, or hands
+    /// it to the DNS interceptor. Only datagrams move the session's endpoint.
+    async fn receive(&self, buf: &[u8], sock_addr: SocketAddr, datagram: bool) {
+        let Some(header) = DataHeader::parse(buf) else {
+            log::error!("Data packet from {sock_addr} without data header");
+            return;
+        };
+
+        if header.message_type != MessageType::Data {
+            return;
+        }
+
+        let (frame_bytes, endpoint, intercepted) = {
             let sessions = self.sessions_pool.read().await;
 
-            let Some((_, payload)) = sessions.iter()
-                .find(|((_sock_addr, _, _), _)| _sock_addr == &sock_addr) else {
-                log::error!("Udp Session cant finding on sessions_pool");
-                continue;
+            let Some(payload) = sessions.get(&header.session_id) else {
+                log::error!("Data session {} cant finding on sessions_pool", header.session_id);
+                return;
+            };
+
+            let Some(less_safe_key) = payload.less_safe_key() else {
+                return;
+            };
+
+            // sessions get a padding policy exactly when they negotiated padding.
+            let padded = payload.padding().is_some();
+
+            let Some(mut packet) = PacketDecoder::open_padded(&buf[DATA_HEADER_LEN..], &less_safe_key, &buf[..DATA_HEADER_LEN], padded) else {
+                log::error!("Data packet from {sock_addr} failed authentication for session {}", header.session_id);
+                return;
+            };
+
+            if !payload.accept_counter(header.counter) {
+                log::warn!("Replayed data packet for session {} from {sock_addr}", header.session_id);
+                return;
+            }
+
+            // cover traffic, a padded packet without a frame.
+            if packet.remaining() == 0 {
+                return;
+            }
+
+            let Ok(frame_bytes) = packet.try_read_string() else {
+                log::warn!("Malformed data packet for session {} from {sock_addr}", header.session_id);
+                return;
             };
 
-            let mut packet = PacketDecoder::new(&buf[..n], payload.less_safe_key());
-            let frame_bytes = packet.read_string();
+            let intercepted = self.dns_interceptor.as_ref()
+                .is_some_and(|dns_interceptor| dns_interceptor.intercept(payload.user(), payload.tunnel_address(), &frame_bytes));
+
+            (frame_bytes, payload.endpoint(), intercepted)
+        };
+
+        // an authenticated packet from a new address: the client roamed
+        // (Wi-Fi/LTE switch, NAT rebinding), answer it there from now on.
+        if datagram && endpoint != sock_addr {
+            if let Some(payload) = self.sessions_pool.write().await.get_mut(&header.session_id) {
+                payload.set_endpoint(sock_addr);
+                log::info!("Session {} migrated from {endpoint} to {sock_addr}", header.session_id);
+            }
+        }
 
-            self.tunnel_tx.write(&frame_bytes).await.ok();
+        if intercepted {
+            return;
+        }
+
+        if self.tunnel.send(&frame_bytes).await.is_err() {
+            log::error!("Failed write frame to tunnel");
         }
     }
-}
\ No newline at end of file
+}
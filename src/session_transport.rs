@@ -0,0 +1,27 @@
+use tokio::sync::mpsc;
+use crate::protocol::Capabilities;
+
+/// Server to client packets queued per framed session before they are dropped.
+const TCP_DATA_CAPACITY: usize = 1024;
+
+/// How a session's data packets reach the client.
+#[derive(Debug, Clone)]
+pub enum SessionTransport {
+    /// Datagrams to the session's endpoint.
+    Udp,
+    /// Frames written by the session's control connection.
+    Tcp(mpsc::Sender<Vec<u8>>),
+}
+
+impl SessionTransport {
+    /// Transport for the negotiated capabilities, with the receiving end of
+    /// the control connection's queue when data goes over TCP.
+    pub fn negotiated(capabilities: Capabilities) -> (Self, Option<mpsc::Receiver<Vec<u8>>>) {
+        if !capabilities.contains(Capabilities::TCP_DATA) {
+            return (Self::Udp, None);
+        }
+
+        let (outbound_tx, outbound_rx) = mpsc::channel(TCP_DATA_CAPACITY);
+        (Self::Tcp(outbound_tx), Some(outbound_rx))
+    }
+}
@@ -0,0 +1,212 @@
+use std::str::FromStr;
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, TokenData, Validation};
+use crate::session_claims::SessionClaims;
+
+const HMAC_ALGORITHMS: &[Algorithm] = &[Algorithm::HS256, Algorithm::HS384, Algorithm::HS512];
+const RSA_ALGORITHMS: &[Algorithm] = &[
+    Algorithm::RS256, Algorithm::RS384, Algorithm::RS512,
+    Algorithm::PS256, Algorithm::PS384, Algorithm::PS512,
+];
+
+/// Public (or shared) key able to verify tokens of the listed algorithms.
+struct VerifierKey {
+    kid: Option<String>,
+    algorithms: Vec<Algorithm>,
+    key: DecodingKey,
+}
+
+/// Validates access tokens presented in `SignApprove` against every configured
+/// key, so keys can be rotated by keeping the old and new ones active together.
+pub struct SessionVerifier {
+    keys: Vec<VerifierKey>,
+    validation: Validation,
+}
+
+impl SessionVerifier {
+    /// Configuration:
+    /// - `JWT_ALGORITHMS` accepted algorithms, comma separated (default `HS512`);
+    /// - `JWT_SHARED_SECRET` secret for HS* algorithms;
+    /// - `JWT_PUBLIC_KEYS` comma separated PEM files, `kid=path` or `path` (kid is the file stem);
+    /// - `JWT_JWKS_FILE` local JWKS document;
+    /// - `JWT_ISSUER`, `JWT_AUDIENCE` comma separated accepted values;
+    /// - `JWT_LEEWAY` clock skew in seconds (default 60).
+    pub fn from_env() -> Self {
+        let algorithms = std::env::var("JWT_ALGORITHMS")
+            .unwrap_or_else(|_| String::from("HS512"))
+            .split(',')
+            .map(|algorithm| Algorithm::from_str(algorithm.trim())
+                .unwrap_or_else(|_| panic!("Unsupported JWT algorithm {algorithm}.")))
+            .collect::<Vec<_>>();
+
+        let mut keys = Vec::new();
+
+        if let Ok(jwt_shared_secret) = std::env::var("JWT_SHARED_SECRET") {
+            keys.push(VerifierKey {
+                kid: None,
+                algorithms: HMAC_ALGORITHMS.to_vec(),
+                key: DecodingKey::from_secret(jwt_shared_secret.as_ref()),
+            });
+        }
+
+        if let Ok(public_keys) = std::env::var("JWT_PUBLIC_KEYS") {
+            for public_key in public_keys.split(',').map(str::trim).filter(|path| !path.is_empty()) {
+                keys.push(Self::pem_key(public_key));
+            }
+        }
+
+        if let Ok(jwks_file) = std::env::var("JWT_JWKS_FILE") {
+            keys.extend(Self::jwks_keys(&jwks_file));
+        }
+
+        if keys.is_empty() {
+            panic!("No JWT verification key configured.");
+        }
+
+        let mut validation = Validation::new(algorithms[0]);
+        validation.algorithms = algorithms;
+
+        if let Ok(leeway) = std::env::var("JWT_LEEWAY") {
+            validation.leeway = leeway.parse().expect("Failed parse JWT_LEEWAY.");
+        }
+
+        // tokens lacking a configured claim are refused, not waved through.
+        let mut required_claims = vec!["exp"];
+
+        if let Ok(issuer) = std::env::var("JWT_ISSUER") {
+            validation.set_issuer(&issuer.split(',').map(str::trim).collect::<Vec<_>>());
+            required_claims.push("iss");
+        }
+
+        match std::env::var("JWT_AUDIENCE") {
+            Ok(audience) => {
+                validation.set_audience(&audience.split(',').map(str::trim).collect::<Vec<_>>());
+                required_claims.push("aud");
+            }
+            Err(_) => validation.validate_aud = false,
+        }
+
+        validation.set_required_spec_claims(&required_claims);
+
+        Self {
+            keys,
+            validation,
+        }
+    }
+
+    /// Verifier of `algorithm` tokens signed with a shared `secret`, without
+    /// issuer or audience checks, for embedding and tests.
+    pub fn from_secret(algorithm: Algorithm, secret: &[u8]) -> Self {
+        let mut validation = Validation::new(algorithm);
+        validation.validate_aud = false;
+        validation.set_required_spec_claims(&["exp"]);
+
+        Self {
+            keys: vec![VerifierKey {
+                kid: None,
+                algorithms: HMAC_ALGORITHMS.to_vec(),
+                key: DecodingKey::from_secret(secret),
+            }],
+            validation,
+        }
+    }
+
+    pub fn verify(&self, token: &str) -> jsonwebtoken::errors::Result<TokenData<SessionClaims>> {
+        let header = jsonwebtoken::decode_header(token)?;
+
+        if !self.validation.algorithms.contains(&header.alg) {
+            return Err(jsonwebtoken::errors::ErrorKind::InvalidAlgorithm.into());
+        }
+
+        let mut validation = self.validation.clone();
+        validation.algorithms = vec![header.alg];
+
+        let mut result = Err(jsonwebtoken::errors::ErrorKind::InvalidKeyFormat.into());
+
+        let candidates = self.keys.iter()
+            .filter(|key| key.algorithms.contains(&header.alg))
+            .filter(|key| match (&header.kid, &key.kid) {
+                (Some(kid), Some(key_kid)) => kid == key_kid,
+                _ => true,
+            });
+
+        for key in candidates {
+            result = jsonwebtoken::decode::<SessionClaims>(token, &key.key, &validation);
+
+            if result.is_ok() {
+                break;
+            }
+        }
+
+        result
+    }
+
+    fn pem_key(public_key: &str) -> VerifierKey {
+        let (kid, path) = match public_key.split_once('=') {
+            Some((kid, path)) => (kid.to_string(), path),
+            None => (
+                std::path::Path::new(public_key)
+                    .file_stem()
+                    .map(|stem| stem.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                public_key
+            ),
+        };
+
+        let pem = std::fs::read(path)
+            .unwrap_or_else(|_| panic!("Failed read JWT public key {path}."));
+
+        let (key, algorithms) = if let Ok(key) = DecodingKey::from_ec_pem(&pem) {
+            (key, vec![Algorithm::ES256, Algorithm::ES384])
+        } else if let Ok(key) = DecodingKey::from_ed_pem(&pem) {
+            (key, vec![Algorithm::EdDSA])
+        } else if let Ok(key) = DecodingKey::from_rsa_pem(&pem) {
+            (key, RSA_ALGORITHMS.to_vec())
+        } else {
+            panic!("Unsupported JWT public key {path}.");
+        };
+
+        VerifierKey {
+            kid: Some(kid),
+            algorithms,
+            key,
+        }
+    }
+
+    fn jwks_keys(jwks_file: &str) -> Vec<VerifierKey> {
+        let jwks = std::fs::read(jwks_file)
+            .unwrap_or_else(|_| panic!("Failed read JWKS file {jwks_file}."));
+
+        let jwks = serde_json::from_slice::<JwkSet>(&jwks)
+            .unwrap_or_else(|_| panic!("Failed parse JWKS file {jwks_file}."));
+
+        jwks.keys.iter()
+            .filter_map(|jwk| {
+                let Ok(key) = DecodingKey::from_jwk(jwk) else {
+                    log::warn!("Skipping unsupported JWKS key {:?}", jwk.common.key_id);
+                    return None;
+                };
+
+                let algorithms = match (jwk.common.key_algorithm, &jwk.algorithm) {
+                    (Some(key_algorithm), _) => Algorithm::from_str(&key_algorithm.to_string())
+                        .map(|algorithm| vec![algorithm])
+                        .ok()?,
+                    (None, AlgorithmParameters::RSA(_)) => RSA_ALGORITHMS.to_vec(),
+                    (None, AlgorithmParameters::EllipticCurve(params)) => match params.curve {
+                        EllipticCurve::P256 => vec![Algorithm::ES256],
+                        EllipticCurve::P384 => vec![Algorithm::ES384],
+                        _ => return None,
+                    },
+                    (None, AlgorithmParameters::OctetKeyPair(_)) => vec![Algorithm::EdDSA],
+                    (None, AlgorithmParameters::OctetKey(_)) => HMAC_ALGORITHMS.to_vec(),
+                };
+
+                Some(VerifierKey {
+                    kid: jwk.common.key_id.clone(),
+                    algorithms,
+                    key,
+                })
+            })
+            .collect()
+    }
+}
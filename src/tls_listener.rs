@@ -0,0 +1,91 @@
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use crate::websocket::WebSocketByteStream;
+
+/// HTTPS listener accepting control connections upgraded to a WebSocket, for
+/// networks where only port 443 gets through.
+pub struct TlsListener {
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    path: String,
+}
+
+impl TlsListener {
+    /// Binds `addr` with the PEM certificate chain and private key from files.
+    /// Upgrades are accepted on `path` only.
+    pub async fn bind(addr: &str, cert: &str, key: &str, path: &str) -> io::Result<Self> {
+        let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert)?))
+            .collect::<Result<Vec<_>, _>>()?;
+        let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key)?))?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("No private key in {key}")))?;
+
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_no_client_auth()
+            .with_single_cert(certs, key)
+            .map_err(io::Error::other)?;
+
+        Ok(Self {
+            listener: TcpListener::bind(addr).await?,
+            acceptor: TlsAcceptor::from(Arc::new(config)),
+            path: path.to_string(),
+        })
+    }
+
+    /// Reads TLS_LISTEN (no listener when unset), TLS_CERT, TLS_KEY and
+    /// TLS_WEBSOCKET_PATH (default `/`).
+    pub async fn from_env() -> Option<Self> {
+        let addr = env::var("TLS_LISTEN").ok().filter(|addr| !addr.is_empty())?;
+        let cert = env::var("TLS_CERT").expect("Failed parse TLS_CERT.");
+        let key = env::var("TLS_KEY").expect("Failed parse TLS_KEY.");
+        let path = env::var("TLS_WEBSOCKET_PATH").unwrap_or("/".to_string());
+
+        Some(Self::bind(&addr, &cert, &key, &path).await.expect("Failed bind TLS_LISTEN."))
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
+        self.listener.accept().await
+    }
+
+    /// TLS handshake and WebSocket upgrade of an accepted connection.
+    pub async fn upgrade(&self, stream: TcpStream) -> io::Result<WebSocketByteStream<TlsStream<TcpStream>>> {
+        stream.set_nodelay(true)?;
+
+        let stream = self.acceptor.accept(stream).await?;
+
+        // the callback signature is tungstenite's.
+        #[allow(clippy::result_large_err)]
+        let check_path = |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+            if request.uri().path() == self.path {
+                return Ok(response);
+            }
+
+            let mut response = ErrorResponse::new(None);
+            *response.status_mut() = StatusCode::NOT_FOUND;
+
+            Err(response)
+        };
+
+        let stream = tokio_tungstenite::accept_hdr_async(stream, check_path)
+            .await
+            .map_err(io::Error::other)?;
+
+        Ok(WebSocketByteStream::new(stream))
+    }
+}
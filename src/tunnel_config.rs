@@ -0,0 +1,89 @@
+use std::io;
+use std::net::Ipv4Addr;
+use crate::packet_decoder::PacketDecoder;
+use crate::packet_encoder::PacketEncoder;
+
+/// Tunnel network settings, pushed to the client on handshake completion.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TunnelConfig {
+    pub address: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub mtu: u16,
+}
+
+impl TunnelConfig {
+    pub fn new(address: Ipv4Addr, netmask: Ipv4Addr, mtu: u16) -> Self {
+        Self {
+            address,
+            netmask,
+            mtu,
+        }
+    }
+
+    /// Reads `TUNNEL_ADDRESS`, `TUNNEL_NETMASK` and `TUNNEL_MTU`,
+    /// defaulting to 10.8.0.1/255.255.0.0 and 1450.
+    pub fn from_env() -> Self {
+        let address = std::env::var("TUNNEL_ADDRESS")
+            .map(|address| address.parse().expect("Failed parse TUNNEL_ADDRESS."))
+            .unwrap_or(Ipv4Addr::new(10, 8, 0, 1));
+
+        let netmask = std::env::var("TUNNEL_NETMASK")
+            .map(|netmask| netmask.parse().expect("Failed parse TUNNEL_NETMASK."))
+            .unwrap_or(Ipv4Addr::new(255, 255, 0, 0));
+
+        let mtu = std::env::var("TUNNEL_MTU")
+            .map(|mtu| mtu.parse().expect("Failed parse TUNNEL_MTU."))
+            .unwrap_or(1450);
+
+        Self::new(address, netmask, mtu)
+    }
+
+    /// Config of a client owning `address` inside this tunnel network.
+    pub fn for_client(&self, address: Ipv4Addr) -> Self {
+        Self::new(address, self.netmask, self.mtu)
+    }
+
+    /// Whether the address lies in the tunnel subnet.
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        let netmask = self.netmask.to_bits();
+        address.to_bits() & netmask == self.address.to_bits() & netmask
+    }
+
+    /// Whether the address can be given to a client: inside the subnet and not
+    /// its network, broadcast or the server's own address.
+    pub fn is_assignable(&self, address: Ipv4Addr) -> bool {
+        let netmask = self.netmask.to_bits();
+        let network = self.address.to_bits() & netmask;
+        let broadcast = network | !netmask;
+
+        address.to_bits() & netmask == network
+            && address.to_bits() != network
+            && address.to_bits() != broadcast
+            && address != self.address
+    }
+
+    /// Lowest assignable address not present in `used`.
+    pub fn free_address(&self, used: &[u32]) -> Option<Ipv4Addr> {
+        let netmask = self.netmask.to_bits();
+        let network = self.address.to_bits() & netmask;
+
+        (network..=network | !netmask)
+            .map(Ipv4Addr::from)
+            .find(|address| self.is_assignable(*address) && !used.contains(&address.to_bits()))
+    }
+
+    pub fn write(&self, packet: &mut PacketEncoder) {
+        packet.write_u32(self.address.to_bits());
+        packet.write_u32(self.netmask.to_bits());
+        packet.write_u16(self.mtu);
+    }
+
+    /// Reads a config written by `write`, an error when the message ends early.
+    pub fn read(packet: &mut PacketDecoder) -> io::Result<Self> {
+        let address = Ipv4Addr::from(packet.try_read_uint32()?);
+        let netmask = Ipv4Addr::from(packet.try_read_uint32()?);
+        let mtu = packet.try_read_uint16()?;
+
+        Ok(Self::new(address, netmask, mtu))
+    }
+}
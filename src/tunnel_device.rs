@@ -0,0 +1,24 @@
+use std::io;
+use async_trait::async_trait;
+use tokio_tun::Tun;
+
+/// Source and sink of raw IP packets for the data plane.
+#[async_trait]
+pub trait TunnelDevice: Send + Sync {
+    /// Reads one IP packet, returns its length.
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Writes one IP packet.
+    async fn send(&self, packet: &[u8]) -> io::Result<()>;
+}
+
+#[async_trait]
+impl TunnelDevice for Tun {
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        Tun::recv(self, buf).await
+    }
+
+    async fn send(&self, packet: &[u8]) -> io::Result<()> {
+        Tun::send_all(self, packet).await
+    }
+}
@@ -0,0 +1,73 @@
+use std::io;
+use async_trait::async_trait;
+use tokio::sync::{mpsc, Mutex};
+use crate::tunnel_device::TunnelDevice;
+
+const MEMORY_TUNNEL_CAPACITY: usize = 1024;
+
+/// Channel backed tunnel device, runs the data plane without root or a TUN device.
+pub struct MemoryTunnel {
+    inbound: Mutex<mpsc::Receiver<Vec<u8>>>,
+    outbound: mpsc::Sender<Vec<u8>>,
+}
+
+/// The "operating system" side of a `MemoryTunnel`.
+pub struct MemoryTunnelPeer {
+    inbound: mpsc::Sender<Vec<u8>>,
+    outbound: mpsc::Receiver<Vec<u8>>,
+}
+
+impl MemoryTunnel {
+    pub fn pair() -> (Self, MemoryTunnelPeer) {
+        let (inbound_tx, inbound_rx) = mpsc::channel(MEMORY_TUNNEL_CAPACITY);
+        let (outbound_tx, outbound_rx) = mpsc::channel(MEMORY_TUNNEL_CAPACITY);
+
+        let tunnel = Self {
+            inbound: Mutex::new(inbound_rx),
+            outbound: outbound_tx,
+        };
+
+        let peer = MemoryTunnelPeer {
+            inbound: inbound_tx,
+            outbound: outbound_rx,
+        };
+
+        (tunnel, peer)
+    }
+}
+
+#[async_trait]
+impl TunnelDevice for MemoryTunnel {
+    async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let packet = self.inbound.lock()
+            .await
+            .recv()
+            .await
+            .ok_or(io::ErrorKind::BrokenPipe)?;
+
+        let n = packet.len().min(buf.len());
+        buf[..n].copy_from_slice(&packet[..n]);
+
+        Ok(n)
+    }
+
+    async fn send(&self, packet: &[u8]) -> io::Result<()> {
+        self.outbound.send(packet.to_vec())
+            .await
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+impl MemoryTunnelPeer {
+    /// Hands a packet to the device, as if routed into the tunnel by the host.
+    pub async fn inject(&self, packet: &[u8]) -> io::Result<()> {
+        self.inbound.send(packet.to_vec())
+            .await
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    /// Next packet written to the device.
+    pub async fn next(&mut self) -> Option<Vec<u8>> {
+        self.outbound.recv().await
+    }
+}
@@ -1,57 +1,184 @@
 use std::collections::HashMap;
 use std::net::{Ipv4Addr, SocketAddr};
 use std::sync::Arc;
+use std::time::Duration;
 use async_std::net::UdpSocket;
-use tokio::io::{AsyncReadExt, ReadHalf};
-use tokio::sync::RwLock;
-use tokio_tun::Tun;
+use ring::aead::LessSafeKey;
+use crate::data_header::DataHeader;
+use crate::dns_intercept::DnsInterceptor;
+use crate::message_type::MessageType;
+use crate::obfuscation::Obfuscation;
 use crate::packet_encoder::PacketEncoder;
-use crate::session_payload::SessionPayload;
+use crate::padding::Padding;
+use crate::session::SessionsPool;
+use crate::session_transport::SessionTransport;
+use crate::tunnel_device::TunnelDevice;
 
 pub struct TunnelTransmitter<'a> {
-    tunnel_rx: &'a mut ReadHalf<Tun>,
+    tunnel: Arc<dyn TunnelDevice>,
     udp_socket: &'a UdpSocket,
-    sessions_pool: &'a Arc<RwLock<HashMap<(SocketAddr, Ipv4Addr, SocketAddr), SessionPayload>>>,
+    sessions_pool: &'a SessionsPool,
+    dns_interceptor: Option<Arc<DnsInterceptor>>,
+    obfuscation: Option<Obfuscation>,
+    cover_traffic: Option<Duration>,
 }
 
 impl<'a> TunnelTransmitter<'a> {
     pub fn new(
-        sessions_pool: &'a Arc<RwLock<HashMap<(SocketAddr, Ipv4Addr, SocketAddr), SessionPayload>>>,
-        tunnel_rx: &'a mut ReadHalf<Tun>,
+        sessions_pool: &'a SessionsPool,
+        tunnel: Arc<dyn TunnelDevice>,
         udp_socket: &'a UdpSocket
     ) -> Self {
         Self {
-            tunnel_rx,
+            tunnel,
             udp_socket,
-            sessions_pool
+            sessions_pool,
+            dns_interceptor: None,
+            obfuscation: None,
+            cover_traffic: None,
         }
     }
 
+    /// Also delivers the interceptor's synthesized DNS replies to their sessions.
+    pub fn with_dns_interceptor(mut self, dns_interceptor: Option<Arc<DnsInterceptor>>) -> Self {
+        self.dns_interceptor = dns_interceptor;
+        self
+    }
+
+    /// Masks datagrams with the shared key; framed packets are covered by the
+    /// obfuscated control stream already.
+    pub fn with_obfuscation(mut self, obfuscation: Option<Obfuscation>) -> Self {
+        self.obfuscation = obfuscation;
+        self
+    }
+
+    /// Sends padded sessions that stayed idle for `interval` a cover packet.
+    pub fn with_cover_traffic(mut self, interval: Option<Duration>) -> Self {
+        self.cover_traffic = interval;
+        self
+    }
+
     pub async fn poll(&mut self) {
         let mut buf = [0u8; 2048];
+        let mut cover = self.cover_traffic.map(tokio::time::interval);
+        let mut counters = HashMap::new();
+
+        loop {
+            tokio::select! {
+                received = self.tunnel.recv(&mut buf) => {
+                    let Ok(n) = received else {
+                        break;
+                    };
 
+                    self.transmit(&buf[..n]).await;
+                }
+                Some(reply) = async {
+                    match &self.dns_interceptor {
+                        Some(dns_interceptor) => dns_interceptor.next_reply().await,
+                        None => std::future::pending().await,
+                    }
+                } => self.transmit(&reply).await,
+                _ = async {
+                    match &mut cover {
+                        Some(cover) => cover.tick().await,
+                        None => std::future::pending().await,
+                    }
+                } => self.transmit_cover(&mut counters).await,
+            }
+        }
+    }
 
-        while let Ok(n) = self.tunnel_rx.read(&mut buf).await {
-            let mut packet = PacketEncoder::new();
-            packet.write_string(&buf[..n]);
+    /// Seals an IP packet for the session owning its destination address.
+    async fn transmit(&self, packet: &[u8]) {
+        // ipv4 destination address lives at bytes 16..20 of the header.
+        if packet.len() < 20 || packet[0] >> 4 != 4 {
+            return;
+        }
+
+        let destination = Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]);
 
-            if let Some(((sock_addr, _, _), payload)) = self.sessions_pool.read()
-                .await
-                .iter()
-                .find(
-                    |((_sock_addr, tunnel_addr, _), _)|
-                    tunnel_addr.to_bits() == 168496130
-                )
-            {
-                let mut packet = PacketEncoder::new();
-                packet.write_string(&buf[..n]);
+        // the endpoint is resolved per packet, so a roamed session is
+        // picked up as soon as SessionTransmitter updates it.
+        let Some((header, sock_addr, transport, less_safe_key, padding)) = self.sessions_pool.read()
+            .await
+            .iter()
+            .find(|(_, payload)| payload.tunnel_address() == destination)
+            .map(|(session_id, payload)| (
+                DataHeader::new(MessageType::Data, *session_id, payload.next_counter()),
+                payload.endpoint(),
+                payload.transport().clone(),
+                payload.less_safe_key(),
+                payload.padding()
+            ))
+        else {
+            return;
+        };
 
-                let packet_bytes = packet.to_bytes(payload.less_safe_key());
+        self.send(header, sock_addr, transport, less_safe_key, padding, Some(packet)).await;
+    }
+
+    /// Sends a padded packet without a frame to every padded session whose
+    /// counter has not moved since the last call, recorded in `counters`.
+    async fn transmit_cover(&self, counters: &mut HashMap<u32, u64>) {
+        let idle = self.sessions_pool.read()
+            .await
+            .iter()
+            .filter(|(_, payload)| payload.padding().is_some())
+            .filter(|(session_id, payload)| {
+                let counter = payload.counter();
+                counters.insert(**session_id, counter) == Some(counter)
+            })
+            .map(|(session_id, payload)| (
+                DataHeader::new(MessageType::Data, *session_id, payload.next_counter()),
+                payload.endpoint(),
+                payload.transport().clone(),
+                payload.less_safe_key(),
+                payload.padding()
+            ))
+            .collect::<Vec<_>>();
+
+        for (header, sock_addr, transport, less_safe_key, padding) in idle {
+            counters.insert(header.session_id, header.counter + 1);
+            self.send(header, sock_addr, transport, less_safe_key, padding, None).await;
+        }
+
+        let sessions = self.sessions_pool.read().await;
+        counters.retain(|session_id, _| sessions.contains_key(session_id));
+    }
+
+    async fn send(
+        &self,
+        header: DataHeader,
+        sock_addr: SocketAddr,
+        transport: SessionTransport,
+        less_safe_key: Option<LessSafeKey>,
+        padding: Option<Padding>,
+        packet: Option<&[u8]>,
+    ) {
+        let mut encoder = PacketEncoder::new().with_padding(padding);
+
+        if let Some(packet) = packet {
+            encoder.write_string(packet);
+        }
+
+        let header_bytes = header.to_bytes();
+        let mut packet_bytes = header_bytes.to_vec();
+        packet_bytes.extend(encoder.to_bytes_with_aad(less_safe_key, &header_bytes));
+
+        match transport {
+            SessionTransport::Udp => {
+                if let Some(obfuscation) = &self.obfuscation {
+                    packet_bytes = obfuscation.seal_datagram(&packet_bytes);
+                }
 
                 if self.udp_socket.send_to(&packet_bytes, sock_addr).await.is_err() {
                     log::error!("Failed sent to client")
                 }
             }
+            // a full queue drops the packet, as a congested link would.
+            SessionTransport::Tcp(tcp_outbound) => {
+                tcp_outbound.try_send(packet_bytes).ok();
+            }
         }
     }
-}
\ No newline at end of file
+}
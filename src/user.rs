@@ -1,7 +1,24 @@
-#[derive(Debug, PartialEq, Eq, Clone, sqlx::FromRow)]
+use std::net::Ipv4Addr;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, PartialEq, Eq, Clone, sqlx::FromRow, Serialize, Deserialize)]
 pub struct User {
     pub(crate) id: u32,
     pub(crate) username: String,
     pub(crate) local_tunnel_address: u32,
-    pub(crate) local_tunnel_address_str: String
-}
\ No newline at end of file
+    pub(crate) local_tunnel_address_str: String,
+    #[serde(default)]
+    pub(crate) enabled: bool
+}
+
+impl User {
+    pub fn new(id: u32, username: &str, local_tunnel_address: Ipv4Addr, enabled: bool) -> Self {
+        Self {
+            id,
+            username: String::from(username),
+            local_tunnel_address: local_tunnel_address.to_bits(),
+            local_tunnel_address_str: local_tunnel_address.to_string(),
+            enabled
+        }
+    }
+}
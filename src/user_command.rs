@@ -0,0 +1,92 @@
+use std::net::Ipv4Addr;
+use crate::command::UserCommand;
+use crate::tunnel_config::TunnelConfig;
+use crate::user_store::{self, UserStore, UserStoreResult};
+
+pub async fn run(command: UserCommand) {
+    let user_store = user_store::from_env().await;
+
+    if let Err(err) = execute(user_store.as_ref(), &TunnelConfig::from_env(), command).await {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+}
+
+/// Runs `command` against `user_store`, addresses checked against `tunnel_config`.
+pub async fn execute(user_store: &dyn UserStore, tunnel_config: &TunnelConfig, command: UserCommand) -> UserStoreResult<()> {
+    match command {
+        UserCommand::Add { username, address } => {
+            let address = match address {
+                Some(address) => {
+                    assignable_address(user_store, tunnel_config, address, None).await?;
+                    address
+                }
+                None => {
+                    let used = user_store.list().await?
+                        .iter()
+                        .map(|user| user.local_tunnel_address)
+                        .collect::<Vec<_>>();
+
+                    tunnel_config.free_address(&used)
+                        .ok_or("No free tunnel address left in the subnet.")?
+                }
+            };
+
+            let id = user_store.add(&username, address).await?;
+            println!("{id}\t{username}\t{address}");
+        }
+        UserCommand::List => {
+            for user in user_store.list().await? {
+                println!(
+                    "{}\t{}\t{}\t{}",
+                    user.id,
+                    user.username,
+                    user.local_tunnel_address_str,
+                    if user.enabled { "enabled" } else { "disabled" }
+                );
+            }
+        }
+        UserCommand::Enable { id } => found(id, user_store.set_enabled(id, true).await?)?,
+        UserCommand::Disable { id } => found(id, user_store.set_enabled(id, false).await?)?,
+        UserCommand::SetAddress { id, address } => {
+            assignable_address(user_store, tunnel_config, address, Some(id)).await?;
+            found(id, user_store.set_address(id, address).await?)?
+        }
+        UserCommand::Delete { id } => found(id, user_store.delete(id).await?)?,
+    }
+
+    Ok(())
+}
+
+/// Rejects addresses outside the tunnel subnet or owned by another user.
+async fn assignable_address(
+    user_store: &dyn UserStore,
+    tunnel_config: &TunnelConfig,
+    address: Ipv4Addr,
+    id: Option<u32>,
+) -> UserStoreResult<()> {
+    if !tunnel_config.is_assignable(address) {
+        return Err(format!(
+            "{address} is not assignable in {}/{}.",
+            tunnel_config.address,
+            tunnel_config.netmask
+        ).into());
+    }
+
+    match user_store.find_by_address(address).await? {
+        Some(user) if Some(user.id) != id => Err(format!(
+            "{address} is already used by {} ({}).",
+            user.username,
+            user.id
+        ).into()),
+        _ => Ok(()),
+    }
+}
+
+fn found(id: u32, found: bool) -> UserStoreResult<()> {
+    if !found {
+        return Err(format!("User {id} not found.").into());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::error::Error;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use async_trait::async_trait;
+use crate::user::User;
+use crate::user_store_file::FileUserStore;
+use crate::user_store_mysql::MySqlUserStore;
+
+pub type UserStoreResult<T> = Result<T, Box<dyn Error + Send + Sync>>;
+
+/// Deny list entry, ignored once the token it revokes has expired.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RevokedToken {
+    pub jti: String,
+    /// Expiry of the revoked token in unix seconds, `None` keeps it denied.
+    pub exp: Option<i64>,
+}
+
+impl RevokedToken {
+    pub fn new(jti: &str, exp: Option<i64>) -> Self {
+        Self {
+            jti: String::from(jti),
+            exp,
+        }
+    }
+}
+
+/// Backend keeping users and their tunnel addresses.
+#[async_trait]
+pub trait UserStore: Send + Sync {
+    async fn find(&self, id: u32) -> UserStoreResult<Option<User>>;
+
+    async fn find_by_address(&self, address: Ipv4Addr) -> UserStoreResult<Option<User>>;
+
+    async fn list(&self) -> UserStoreResult<Vec<User>>;
+
+    /// Creates an enabled user, returns its id.
+    async fn add(&self, username: &str, address: Ipv4Addr) -> UserStoreResult<u32>;
+
+    /// Returns `false` when the user does not exist.
+    async fn set_enabled(&self, id: u32, enabled: bool) -> UserStoreResult<bool>;
+
+    async fn set_address(&self, id: u32, address: Ipv4Addr) -> UserStoreResult<bool>;
+
+    async fn delete(&self, id: u32) -> UserStoreResult<bool>;
+
+    /// Tokens on the deny list.
+    async fn revoked_tokens(&self) -> UserStoreResult<Vec<RevokedToken>>;
+
+    /// Ids of the users not allowed to establish a session.
+    async fn disabled_users(&self) -> UserStoreResult<Vec<u32>> {
+        Ok(self.list().await?.into_iter().filter(|user| !user.enabled).map(|user| user.id).collect())
+    }
+
+    /// User allowed to establish a session.
+    async fn find_enabled(&self, id: u32) -> UserStoreResult<Option<User>> {
+        Ok(self.find(id).await?.filter(|user| user.enabled))
+    }
+}
+
+/// Backend selected by `USER_STORE`: `mysql` (default, `MYSQL_DSN`)
+/// or `file:<path>` for a JSON document with its deny list in `<path>.revoked`.
+pub async fn from_env() -> Arc<dyn UserStore> {
+    let user_store = std::env::var("USER_STORE")
+        .unwrap_or_else(|_| String::from("mysql"));
+
+    match user_store.split_once(':') {
+        Some(("file", path)) => Arc::new(FileUserStore::new(path)),
+        None if user_store == "mysql" => Arc::new(MySqlUserStore::new(crate::command::mysql_pool().await)),
+        _ => panic!("Unsupported USER_STORE {user_store}."),
+    }
+}
@@ -0,0 +1,167 @@
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use async_trait::async_trait;
+use tokio::sync::Mutex;
+use crate::user::User;
+use crate::user_store::{RevokedToken, UserStore, UserStoreResult};
+
+/// Users kept as a JSON array in a local file, for deployments without MySQL.
+/// Revoked token ids sit next to it in `<path>.revoked`, one per line and
+/// optionally followed by the token expiry in unix seconds.
+pub struct FileUserStore {
+    path: PathBuf,
+    revoked_path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileUserStore {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: PathBuf::from(path),
+            revoked_path: PathBuf::from(format!("{path}.revoked")),
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Adds the token id to the deny list, until `exp` when given.
+    pub async fn revoke(&self, jti: &str, exp: Option<i64>) -> UserStoreResult<()> {
+        let _lock = self.lock.lock().await;
+        let mut revoked_tokens = self.load_revoked_tokens().await?;
+
+        if revoked_tokens.iter().any(|revoked| revoked.jti == jti) {
+            return Ok(());
+        }
+
+        revoked_tokens.push(RevokedToken::new(jti, exp));
+
+        let lines = revoked_tokens.iter()
+            .map(|revoked| match revoked.exp {
+                Some(exp) => format!("{} {exp}\n", revoked.jti),
+                None => format!("{}\n", revoked.jti),
+            })
+            .collect::<String>();
+
+        let temporary = self.revoked_path.with_extension("tmp");
+
+        tokio::fs::write(&temporary, lines).await?;
+        tokio::fs::rename(&temporary, &self.revoked_path).await?;
+
+        Ok(())
+    }
+
+    async fn load_revoked_tokens(&self) -> UserStoreResult<Vec<RevokedToken>> {
+        match tokio::fs::read_to_string(&self.revoked_path).await {
+            Ok(contents) => Ok(contents.lines()
+                .filter_map(|line| {
+                    let mut fields = line.split_whitespace();
+                    let jti = fields.next()?;
+
+                    // an unreadable expiry keeps the token denied.
+                    Some(RevokedToken::new(jti, fields.next().and_then(|exp| exp.parse().ok())))
+                })
+                .collect()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn load(&self) -> UserStoreResult<Vec<User>> {
+        match tokio::fs::read(&self.path).await {
+            Ok(bytes) => {
+                let mut users = serde_json::from_slice::<Vec<User>>(&bytes)?;
+
+                for user in users.iter_mut() {
+                    user.local_tunnel_address_str = Ipv4Addr::from(user.local_tunnel_address).to_string();
+                }
+
+                Ok(users)
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    async fn save(&self, users: &[User]) -> UserStoreResult<()> {
+        let temporary = self.path.with_extension("tmp");
+
+        tokio::fs::write(&temporary, serde_json::to_vec_pretty(users)?).await?;
+        tokio::fs::rename(&temporary, &self.path).await?;
+
+        Ok(())
+    }
+
+    /// Applies `update` to the user under the store lock, persisting on success.
+    async fn update(&self, id: u32, update: impl FnOnce(&mut User)) -> UserStoreResult<bool> {
+        let _lock = self.lock.lock().await;
+        let mut users = self.load().await?;
+
+        let Some(user) = users.iter_mut().find(|user| user.id == id) else {
+            return Ok(false);
+        };
+
+        update(user);
+        self.save(&users).await?;
+
+        Ok(true)
+    }
+}
+
+#[async_trait]
+impl UserStore for FileUserStore {
+    async fn find(&self, id: u32) -> UserStoreResult<Option<User>> {
+        Ok(self.load().await?.into_iter().find(|user| user.id == id))
+    }
+
+    async fn find_by_address(&self, address: Ipv4Addr) -> UserStoreResult<Option<User>> {
+        Ok(self.load().await?.into_iter().find(|user| user.local_tunnel_address == address.to_bits()))
+    }
+
+    async fn list(&self) -> UserStoreResult<Vec<User>> {
+        let mut users = self.load().await?;
+        users.sort_by_key(|user| user.id);
+
+        Ok(users)
+    }
+
+    async fn add(&self, username: &str, address: Ipv4Addr) -> UserStoreResult<u32> {
+        let _lock = self.lock.lock().await;
+        let mut users = self.load().await?;
+
+        let id = users.iter().map(|user| user.id).max().unwrap_or(0) + 1;
+
+        users.push(User::new(id, username, address, true));
+        self.save(&users).await?;
+
+        Ok(id)
+    }
+
+    async fn set_enabled(&self, id: u32, enabled: bool) -> UserStoreResult<bool> {
+        self.update(id, |user| user.enabled = enabled).await
+    }
+
+    async fn set_address(&self, id: u32, address: Ipv4Addr) -> UserStoreResult<bool> {
+        self.update(id, |user| {
+            user.local_tunnel_address = address.to_bits();
+            user.local_tunnel_address_str = address.to_string();
+        }).await
+    }
+
+    async fn delete(&self, id: u32) -> UserStoreResult<bool> {
+        let _lock = self.lock.lock().await;
+        let mut users = self.load().await?;
+        let user_count = users.len();
+
+        users.retain(|user| user.id != id);
+
+        if users.len() == user_count {
+            return Ok(false);
+        }
+
+        self.save(&users).await?;
+        Ok(true)
+    }
+
+    async fn revoked_tokens(&self) -> UserStoreResult<Vec<RevokedToken>> {
+        self.load_revoked_tokens().await
+    }
+}
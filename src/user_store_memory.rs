@@ -0,0 +1,82 @@
+use std::net::Ipv4Addr;
+use async_trait::async_trait;
+use tokio::sync::RwLock;
+use crate::user::User;
+use crate::user_store::{RevokedToken, UserStore, UserStoreResult};
+
+/// Volatile user store, for tests and the in-process harness.
+#[derive(Default)]
+pub struct MemoryUserStore {
+    users: RwLock<Vec<User>>,
+    revoked_tokens: RwLock<Vec<RevokedToken>>,
+}
+
+impl MemoryUserStore {
+    pub fn new(users: Vec<User>) -> Self {
+        Self {
+            users: RwLock::new(users),
+            revoked_tokens: RwLock::new(Vec::new()),
+        }
+    }
+
+    /// Denies the token until `exp`, for good without one.
+    pub async fn revoke(&self, jti: &str, exp: Option<i64>) {
+        self.revoked_tokens.write().await.push(RevokedToken::new(jti, exp));
+    }
+
+    async fn update(&self, id: u32, update: impl FnOnce(&mut User)) -> bool {
+        match self.users.write().await.iter_mut().find(|user| user.id == id) {
+            Some(user) => {
+                update(user);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[async_trait]
+impl UserStore for MemoryUserStore {
+    async fn find(&self, id: u32) -> UserStoreResult<Option<User>> {
+        Ok(self.users.read().await.iter().find(|user| user.id == id).cloned())
+    }
+
+    async fn find_by_address(&self, address: Ipv4Addr) -> UserStoreResult<Option<User>> {
+        Ok(self.users.read().await.iter().find(|user| user.local_tunnel_address == address.to_bits()).cloned())
+    }
+
+    async fn list(&self) -> UserStoreResult<Vec<User>> {
+        Ok(self.users.read().await.clone())
+    }
+
+    async fn add(&self, username: &str, address: Ipv4Addr) -> UserStoreResult<u32> {
+        let mut users = self.users.write().await;
+        let id = users.iter().map(|user| user.id).max().unwrap_or(0) + 1;
+
+        users.push(User::new(id, username, address, true));
+        Ok(id)
+    }
+
+    async fn set_enabled(&self, id: u32, enabled: bool) -> UserStoreResult<bool> {
+        Ok(self.update(id, |user| user.enabled = enabled).await)
+    }
+
+    async fn set_address(&self, id: u32, address: Ipv4Addr) -> UserStoreResult<bool> {
+        Ok(self.update(id, |user| {
+            user.local_tunnel_address = address.to_bits();
+            user.local_tunnel_address_str = address.to_string();
+        }).await)
+    }
+
+    async fn delete(&self, id: u32) -> UserStoreResult<bool> {
+        let mut users = self.users.write().await;
+        let user_count = users.len();
+
+        users.retain(|user| user.id != id);
+        Ok(users.len() < user_count)
+    }
+
+    async fn revoked_tokens(&self) -> UserStoreResult<Vec<RevokedToken>> {
+        Ok(self.revoked_tokens.read().await.clone())
+    }
+}
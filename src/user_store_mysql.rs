@@ -0,0 +1,101 @@
+use std::net::Ipv4Addr;
+use async_trait::async_trait;
+use sqlx::{MySql, Pool};
+use crate::user::User;
+use crate::user_store::{RevokedToken, UserStore, UserStoreResult};
+
+const SELECT_USERS: &str = "SELECT id, username, local_tunnel_address, INET_NTOA(local_tunnel_address) as local_tunnel_address_str, enabled FROM users";
+
+/// `users (id, username, local_tunnel_address, enabled)` and
+/// `revoked_tokens (jti, revoked_at, expires_at)` tables.
+pub struct MySqlUserStore {
+    mysql_pool: Pool<MySql>,
+}
+
+impl MySqlUserStore {
+    pub fn new(mysql_pool: Pool<MySql>) -> Self {
+        Self {
+            mysql_pool
+        }
+    }
+}
+
+#[async_trait]
+impl UserStore for MySqlUserStore {
+    async fn find(&self, id: u32) -> UserStoreResult<Option<User>> {
+        Ok(sqlx::query_as::<_, User>(&format!("{SELECT_USERS} WHERE id = ?"))
+            .bind(id)
+            .fetch_optional(&self.mysql_pool)
+            .await?)
+    }
+
+    async fn find_by_address(&self, address: Ipv4Addr) -> UserStoreResult<Option<User>> {
+        Ok(sqlx::query_as::<_, User>(&format!("{SELECT_USERS} WHERE local_tunnel_address = ?"))
+            .bind(address.to_bits())
+            .fetch_optional(&self.mysql_pool)
+            .await?)
+    }
+
+    async fn list(&self) -> UserStoreResult<Vec<User>> {
+        Ok(sqlx::query_as::<_, User>(&format!("{SELECT_USERS} ORDER BY id"))
+            .fetch_all(&self.mysql_pool)
+            .await?)
+    }
+
+    async fn add(&self, username: &str, address: Ipv4Addr) -> UserStoreResult<u32> {
+        let result = sqlx::query("INSERT INTO users (username, local_tunnel_address, enabled) VALUES (?, ?, 1)")
+            .bind(username)
+            .bind(address.to_bits())
+            .execute(&self.mysql_pool)
+            .await?;
+
+        Ok(result.last_insert_id() as u32)
+    }
+
+    async fn set_enabled(&self, id: u32, enabled: bool) -> UserStoreResult<bool> {
+        let result = sqlx::query("UPDATE users SET enabled = ? WHERE id = ?")
+            .bind(enabled)
+            .bind(id)
+            .execute(&self.mysql_pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn set_address(&self, id: u32, address: Ipv4Addr) -> UserStoreResult<bool> {
+        let result = sqlx::query("UPDATE users SET local_tunnel_address = ? WHERE id = ?")
+            .bind(address.to_bits())
+            .bind(id)
+            .execute(&self.mysql_pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn delete(&self, id: u32) -> UserStoreResult<bool> {
+        let result = sqlx::query("DELETE FROM users WHERE id = ?")
+            .bind(id)
+            .execute(&self.mysql_pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn disabled_users(&self) -> UserStoreResult<Vec<u32>> {
+        Ok(sqlx::query_scalar::<_, u32>("SELECT id FROM users WHERE enabled = 0")
+            .fetch_all(&self.mysql_pool)
+            .await?)
+    }
+
+    async fn revoked_tokens(&self) -> UserStoreResult<Vec<RevokedToken>> {
+        let revoked_tokens = sqlx::query_as::<_, (String, Option<i64>)>(
+            "SELECT jti, CAST(UNIX_TIMESTAMP(expires_at) AS SIGNED) FROM revoked_tokens"
+        )
+            .fetch_all(&self.mysql_pool)
+            .await?;
+
+        Ok(revoked_tokens.into_iter()
+            .map(|(jti, exp)| RevokedToken { jti, exp })
+            .collect())
+    }
+}
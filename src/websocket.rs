@@ -0,0 +1,173 @@
+use std::env;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use futures::{Sink, Stream};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::tungstenite::http::Uri;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::WebSocketStream;
+
+/// Byte stream over a WebSocket: every write goes out as one binary message
+/// and reads yield the payloads of received binary messages in order.
+pub struct WebSocketByteStream<S> {
+    inner: WebSocketStream<S>,
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    written: Option<usize>,
+}
+
+impl<S> WebSocketByteStream<S> {
+    pub fn new(inner: WebSocketStream<S>) -> Self {
+        Self {
+            inner,
+            read_buf: Vec::new(),
+            read_pos: 0,
+            written: None,
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for WebSocketByteStream<S> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+
+        loop {
+            if this.read_pos < this.read_buf.len() {
+                let n = (this.read_buf.len() - this.read_pos).min(buf.remaining());
+                buf.put_slice(&this.read_buf[this.read_pos..this.read_pos + n]);
+                this.read_pos += n;
+
+                return Poll::Ready(Ok(()));
+            }
+
+            match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => {
+                    this.read_buf = data;
+                    this.read_pos = 0;
+                }
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                // pings are answered by tungstenite itself.
+                Some(Ok(_)) => {}
+                Some(Err(err)) => return Poll::Ready(Err(ws_error(err))),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for WebSocketByteStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        // the message is queued once, a pending flush is retried with the same buf.
+        if this.written.is_none() {
+            ready!(Pin::new(&mut this.inner).poll_ready(cx)).map_err(ws_error)?;
+            Pin::new(&mut this.inner).start_send(Message::Binary(buf.to_vec())).map_err(ws_error)?;
+            this.written = Some(buf.len());
+        }
+
+        let flushed = ready!(Pin::new(&mut this.inner).poll_flush(cx));
+        let written = this.written.take().unwrap_or_default();
+
+        Poll::Ready(flushed.map(|_| written).map_err(ws_error))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx).map_err(ws_error)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_close(cx).map_err(ws_error)
+    }
+}
+
+/// Client side of the server's TLS listener, a `wss://` url.
+pub struct WebSocketConnector {
+    url: String,
+    host: String,
+    port: u16,
+    connector: TlsConnector,
+}
+
+impl WebSocketConnector {
+    pub fn new(url: &str, roots: RootCertStore) -> io::Result<Self> {
+        let uri: Uri = url.parse().map_err(io::Error::other)?;
+
+        if uri.scheme_str() != Some("wss") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Not a wss url {url}")));
+        }
+
+        let host = uri.host()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Missing host in {url}")))?
+            .to_string();
+
+        let config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(io::Error::other)?
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        Ok(Self {
+            url: url.to_string(),
+            port: uri.port_u16().unwrap_or(443),
+            host,
+            connector: TlsConnector::from(Arc::new(config)),
+        })
+    }
+
+    /// Reads SMO_WEBSOCKET_URL (none when unset) and SMO_TLS_CA, a PEM file of
+    /// trusted roots that defaults to the bundled web roots.
+    pub fn from_env() -> Option<Self> {
+        let url = env::var("SMO_WEBSOCKET_URL").ok().filter(|url| !url.is_empty())?;
+
+        let roots = match env::var("SMO_TLS_CA") {
+            Ok(path) => load_roots(&path).expect("Failed parse SMO_TLS_CA."),
+            Err(_) => RootCertStore {
+                roots: webpki_roots::TLS_SERVER_ROOTS.to_vec(),
+            },
+        };
+
+        Some(Self::new(&url, roots).expect("Failed parse SMO_WEBSOCKET_URL."))
+    }
+
+    pub async fn connect(&self) -> io::Result<WebSocketByteStream<TlsStream<TcpStream>>> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port)).await?;
+        stream.set_nodelay(true)?;
+
+        let server_name = ServerName::try_from(self.host.clone()).map_err(io::Error::other)?;
+        let stream = self.connector.connect(server_name, stream).await?;
+
+        let (stream, _) = tokio_tungstenite::client_async(self.url.as_str(), stream)
+            .await
+            .map_err(ws_error)?;
+
+        Ok(WebSocketByteStream::new(stream))
+    }
+}
+
+/// Root store of the PEM certificates in `path`.
+pub fn load_roots(path: &str) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+
+    for cert in rustls_pemfile::certs(&mut BufReader::new(File::open(path)?)) {
+        roots.add(cert?).map_err(io::Error::other)?;
+    }
+
+    Ok(roots)
+}
+
+fn ws_error(err: tungstenite::Error) -> io::Error {
+    match err {
+        tungstenite::Error::Io(err) => err,
+        err => io::Error::other(err),
+    }
+}
@@ -0,0 +1,70 @@
+//! Fixtures shared by the integration tests; each test crate uses a subset.
+#![allow(dead_code)]
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use smo::dns_message;
+use tokio::net::UdpSocket;
+
+/// A query for example.com with the given transaction id.
+pub fn query(id: u16) -> Vec<u8> {
+    let mut query = question("example.com", dns_message::TYPE_A);
+    dns_message::set_id(&mut query, id);
+    query
+}
+
+/// A recursive query for `name` and `qtype`.
+pub fn question(name: &str, qtype: u16) -> Vec<u8> {
+    let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+    query.extend_from_slice(&dns_message::encode_name(name));
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&[0, 1]);
+    query
+}
+
+/// Fresh directory for the files of one test.
+pub fn scratch(suite: &str, name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("smo-{suite}-{name}-{}", std::process::id()));
+    std::fs::remove_dir_all(&dir).ok();
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Minimal IPv4 header around `payload`.
+pub fn ipv4_packet(source: Ipv4Addr, destination: Ipv4Addr, protocol: u8, payload: &[u8]) -> Vec<u8> {
+    let mut packet = vec![0u8; 20];
+    packet[0] = 0x45;
+    packet[2..4].copy_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+    packet[8] = 64;
+    packet[9] = protocol;
+    packet[12..16].copy_from_slice(&source.octets());
+    packet[16..20].copy_from_slice(&destination.octets());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Upstream answering every query with one A record, counting the queries.
+pub async fn spawn_upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let queries = Arc::new(AtomicUsize::new(0));
+    let counter = queries.clone();
+
+    tokio::spawn(async move {
+        let mut buf = [0u8; 2048];
+
+        while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+            counter.fetch_add(1, Ordering::Relaxed);
+
+            let mut answer = dns_message::response(&buf[..n], dns_message::RCODE_NOERROR).unwrap();
+            answer[7] = 1;
+            answer.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 93, 184, 216, 34]);
+
+            socket.send_to(&answer, peer).await.unwrap();
+        }
+    });
+
+    (addr, queries)
+}
@@ -0,0 +1,100 @@
+mod common;
+
+use std::time::Duration;
+use smo::dns_cache::DnsCache;
+use smo::dns_message;
+use common::query;
+
+/// Answer to `query` with one A record of the given TTL.
+fn a_answer(query: &[u8], ttl: u32) -> Vec<u8> {
+    let mut answer = dns_message::response(query, dns_message::RCODE_NOERROR).unwrap();
+    answer[7] = 1;
+    answer.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1]);
+    answer.extend_from_slice(&ttl.to_be_bytes());
+    answer.extend_from_slice(&[0, 4, 93, 184, 216, 34]);
+    answer
+}
+
+/// NXDOMAIN with a SOA in the authority section.
+fn nxdomain(query: &[u8], soa_ttl: u32, minimum: u32) -> Vec<u8> {
+    let mut answer = dns_message::response(query, dns_message::RCODE_NXDOMAIN).unwrap();
+    answer[9] = 1;
+    answer.extend_from_slice(&[0xc0, 0x14, 0, 6, 0, 1]);
+    answer.extend_from_slice(&soa_ttl.to_be_bytes());
+
+    let mut rdata = b"\x02ns\xc0\x14\x04root\xc0\x14".to_vec();
+    for value in [1u32, 7200, 3600, 1209600, minimum] {
+        rdata.extend_from_slice(&value.to_be_bytes());
+    }
+
+    answer.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+    answer.extend_from_slice(&rdata);
+    answer
+}
+
+fn answer_ttls(answer: &[u8]) -> Vec<u32> {
+    dns_message::records(answer).unwrap().iter().map(|record| record.ttl).collect()
+}
+
+#[test]
+fn hit_rewrites_transaction_id() {
+    let cache = DnsCache::new(16, 0, 86400, 900);
+
+    assert_eq!(cache.get(&query(1)), None);
+    cache.insert(&query(1), &a_answer(&query(1), 300));
+
+    let answer = cache.get(&query(2)).unwrap();
+
+    assert_eq!(dns_message::id(&answer), Some(2));
+    assert_eq!(answer_ttls(&answer), vec![300]);
+
+    let stats = cache.stats();
+    assert_eq!((stats.hits, stats.misses, stats.entries), (1, 1, 1));
+}
+
+#[test]
+fn ttl_is_clamped() {
+    let cache = DnsCache::new(16, 0, 1, 900);
+    cache.insert(&query(1), &a_answer(&query(1), 300));
+
+    assert!(cache.get(&query(1)).is_some());
+    std::thread::sleep(Duration::from_millis(1100));
+    assert_eq!(cache.get(&query(1)), None);
+}
+
+#[test]
+fn zero_ttl_answers_are_not_cached() {
+    let cache = DnsCache::new(16, 0, 86400, 900);
+    cache.insert(&query(1), &a_answer(&query(1), 0));
+
+    assert_eq!(cache.get(&query(1)), None);
+}
+
+#[test]
+fn negative_answers_use_soa_minimum() {
+    let cache = DnsCache::new(16, 0, 86400, 900);
+    cache.insert(&query(1), &nxdomain(&query(1), 3600, 60));
+
+    let answer = cache.get(&query(3)).unwrap();
+
+    assert_eq!(dns_message::rcode(&answer), Some(dns_message::RCODE_NXDOMAIN));
+    assert_eq!(dns_message::id(&answer), Some(3));
+}
+
+#[test]
+fn negative_answers_without_soa_are_not_cached() {
+    let cache = DnsCache::new(16, 0, 86400, 900);
+    let answer = dns_message::response(&query(1), dns_message::RCODE_NXDOMAIN).unwrap();
+
+    cache.insert(&query(1), &answer);
+    assert_eq!(cache.get(&query(1)), None);
+}
+
+#[test]
+fn servfail_is_not_cached() {
+    let cache = DnsCache::new(16, 0, 86400, 900);
+    let answer = dns_message::response(&query(1), dns_message::RCODE_SERVFAIL).unwrap();
+
+    cache.insert(&query(1), &answer);
+    assert_eq!(cache.get(&query(1)), None);
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use std::net::Ipv4Addr;
+use smo::dns_filter::{DnsBlocklist, DnsFilter};
+use smo::dns_message;
+use smo::user::User;
+use common::scratch;
+
+fn user(username: &str) -> User {
+    User::new(1, username, Ipv4Addr::new(10, 8, 0, 2), true)
+}
+
+#[test]
+fn parses_hosts_and_domain_lists() {
+    let blocklist = DnsBlocklist::parse("\
+        # hosts\n\
+        127.0.0.1 localhost\n\
+        0.0.0.0 ads.example.com tracker.example.net # inline\n\
+        malware.test.\n\
+        *.doubleclick.net\n");
+
+    assert_eq!(blocklist.len(), 4);
+    assert!(blocklist.is_blocked("ads.example.com"));
+    assert!(blocklist.is_blocked("tracker.example.net"));
+    assert!(blocklist.is_blocked("malware.test"));
+    assert!(!blocklist.is_blocked("localhost"));
+    assert!(!blocklist.is_blocked("www.ads.example.com"));
+
+    assert!(blocklist.is_blocked("ad.doubleclick.net"));
+    assert!(blocklist.is_blocked("a.b.doubleclick.net"));
+    assert!(!blocklist.is_blocked("doubleclick.net"));
+    assert!(!blocklist.is_blocked("notdoubleclick.net"));
+}
+
+#[test]
+fn applies_default_group_and_user_lists() {
+    let dir = scratch("dns-filter", "policy");
+
+    std::fs::write(dir.join("malware.hosts"), "0.0.0.0 malware.test\n").unwrap();
+    std::fs::write(dir.join("tracking.txt"), "*.tracker.test\n").unwrap();
+    std::fs::write(dir.join("social.txt"), "social.test\n").unwrap();
+    std::fs::write(dir.join("policy.json"), serde_json::json!({
+        "blocklists": {
+            "malware": dir.join("malware.hosts"),
+            "tracking": dir.join("tracking.txt"),
+            "social": dir.join("social.txt"),
+        },
+        "default": ["malware"],
+        "groups": { "managed": { "users": ["alice"], "blocklists": ["tracking"] } },
+        "users": { "bob": ["social"] },
+    }).to_string()).unwrap();
+
+    let filter = DnsFilter::load(dir.join("policy.json").to_str().unwrap()).unwrap();
+
+    assert_eq!(filter.blocked_by(&user("carol"), "malware.test").as_deref(), Some("malware"));
+    assert_eq!(filter.blocked_by(&user("carol"), "ads.tracker.test"), None);
+    assert_eq!(filter.blocked_by(&user("alice"), "ads.tracker.test").as_deref(), Some("tracking"));
+    assert_eq!(filter.blocked_by(&user("alice"), "social.test"), None);
+    assert_eq!(filter.blocked_by(&user("bob"), "social.test").as_deref(), Some("social"));
+
+    let query = common::question("malware.test", dns_message::TYPE_A);
+    let (question, _) = dns_message::question(&query).unwrap();
+    let answer = filter.blocked_answer(&query, &question).unwrap();
+
+    assert_eq!(dns_message::rcode(&answer), Some(dns_message::RCODE_NXDOMAIN));
+    assert_eq!(dns_message::id(&answer), Some(0x1234));
+
+    // reload picks up changed lists.
+    std::fs::write(dir.join("malware.hosts"), "0.0.0.0 other.test\n").unwrap();
+    filter.reload().unwrap();
+
+    assert_eq!(filter.blocked_by(&user("carol"), "malware.test"), None);
+    assert_eq!(filter.blocked_by(&user("carol"), "other.test").as_deref(), Some("malware"));
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn sinkhole_answers_address_queries() {
+    let dir = scratch("dns-filter", "sinkhole");
+
+    std::fs::write(dir.join("ads.txt"), "ads.test\n").unwrap();
+    std::fs::write(dir.join("policy.json"), serde_json::json!({
+        "blocklists": { "ads": dir.join("ads.txt") },
+        "default": ["ads"],
+        "action": "sinkhole",
+        "sinkhole_v4": "10.8.0.1",
+    }).to_string()).unwrap();
+
+    let filter = DnsFilter::load(dir.join("policy.json").to_str().unwrap()).unwrap();
+
+    let query_a = common::question("ads.test", dns_message::TYPE_A);
+    let (question, _) = dns_message::question(&query_a).unwrap();
+    let answer = filter.blocked_answer(&query_a, &question).unwrap();
+
+    let records = dns_message::records(&answer).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(&answer[records[0].rdata.clone()], &[10, 8, 0, 1]);
+
+    let query_aaaa = common::question("ads.test", dns_message::TYPE_AAAA);
+    let (question, _) = dns_message::question(&query_aaaa).unwrap();
+    let answer = filter.blocked_answer(&query_aaaa, &question).unwrap();
+
+    let records = dns_message::records(&answer).unwrap();
+    assert_eq!(&answer[records[0].rdata.clone()], &[0u8; 16]);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
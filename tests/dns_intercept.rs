@@ -0,0 +1,220 @@
+mod common;
+
+use std::collections::HashMap;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
+use smo::dns::Dns;
+use smo::dns_cache::DnsCache;
+use smo::dns_intercept::DnsInterceptor;
+use smo::dns_message;
+use smo::dns_upstream::DnsUpstreams;
+use smo::session::SessionsPool;
+use smo::user::User;
+use tokio::sync::RwLock;
+use common::{ipv4_packet, query, spawn_upstream};
+
+const ALICE: Ipv4Addr = Ipv4Addr::new(10, 8, 0, 2);
+const RESOLVER: Ipv4Addr = Ipv4Addr::new(8, 8, 8, 8);
+
+fn alice() -> User {
+    User::new(1, "alice", ALICE, true)
+}
+
+fn udp_packet(source: Ipv4Addr, destination: Ipv4Addr, destination_port: u16, payload: &[u8]) -> Vec<u8> {
+    let mut datagram = 40000u16.to_be_bytes().to_vec();
+    datagram.extend_from_slice(&destination_port.to_be_bytes());
+    datagram.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+
+    ipv4_packet(source, destination, 17, &datagram)
+}
+
+fn tcp_packet(seq: u32, ack: u32, flags: u8, data: &[u8]) -> Vec<u8> {
+    let mut segment = 40001u16.to_be_bytes().to_vec();
+    segment.extend_from_slice(&53u16.to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.extend_from_slice(&[5 << 4, flags, 0xff, 0xff, 0, 0, 0, 0]);
+    segment.extend_from_slice(data);
+
+    ipv4_packet(ALICE, RESOLVER, 6, &segment)
+}
+
+/// Internet checksum of the data, zero when the data carries a valid checksum.
+fn checksum(data: &[u8]) -> u16 {
+    let mut sum = data.chunks(2)
+        .map(|word| u16::from_be_bytes([word[0], word.get(1).copied().unwrap_or(0)]) as u32)
+        .sum::<u32>();
+
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+
+    !(sum as u16)
+}
+
+/// Checks the IPv4 and transport checksums of a synthesized packet, returns its transport segment.
+fn verify(packet: &[u8]) -> &[u8] {
+    assert_eq!(checksum(&packet[..20]), 0);
+
+    let segment = &packet[20..];
+    let mut pseudo = packet[12..20].to_vec();
+    pseudo.extend_from_slice(&[0, packet[9]]);
+    pseudo.extend_from_slice(&(segment.len() as u16).to_be_bytes());
+    pseudo.extend_from_slice(segment);
+
+    assert_eq!(checksum(&pseudo), 0);
+    segment
+}
+
+async fn interceptor() -> Arc<DnsInterceptor> {
+    let sessions_pool: SessionsPool = Arc::new(RwLock::new(HashMap::new()));
+
+    let dns = Dns::new("127.0.0.1:0", sessions_pool)
+        .unwrap()
+        .with_upstreams(DnsUpstreams::new(vec![spawn_upstream().await.0], Duration::from_millis(500)))
+        .with_cache(DnsCache::new(16, 0, 86400, 900));
+
+    Arc::new(DnsInterceptor::new(Arc::new(dns), 1400))
+}
+
+async fn next_reply(interceptor: &DnsInterceptor) -> Vec<u8> {
+    tokio::time::timeout(Duration::from_secs(5), interceptor.next_reply())
+        .await
+        .expect("intercepted reply timed out")
+        .unwrap()
+}
+
+#[tokio::test]
+async fn answers_udp_queries_from_the_session() {
+    let interceptor = interceptor().await;
+
+    assert!(interceptor.intercept(&alice(), ALICE, &udp_packet(ALICE, RESOLVER, 53, &query(9))));
+
+    let reply = next_reply(&interceptor).await;
+    assert_eq!(&reply[12..16], &RESOLVER.octets());
+    assert_eq!(&reply[16..20], &ALICE.octets());
+
+    let datagram = verify(&reply);
+    assert_eq!(u16::from_be_bytes([datagram[0], datagram[1]]), 53);
+    assert_eq!(u16::from_be_bytes([datagram[2], datagram[3]]), 40000);
+
+    let answer = &datagram[8..];
+    assert_eq!(dns_message::id(answer), Some(9));
+    assert_eq!(dns_message::records(answer).unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn leaves_other_traffic_to_the_tunnel() {
+    let interceptor = interceptor().await;
+
+    assert!(!interceptor.intercept(&alice(), ALICE, &udp_packet(ALICE, RESOLVER, 443, &query(1))));
+
+    // only packets from the session's own address are answered.
+    assert!(!interceptor.intercept(&alice(), ALICE, &udp_packet(Ipv4Addr::new(10, 8, 0, 3), RESOLVER, 53, &query(1))));
+}
+
+#[tokio::test]
+async fn answers_tcp_queries_from_the_session() {
+    let interceptor = interceptor().await;
+
+    assert!(interceptor.intercept(&alice(), ALICE, &tcp_packet(1000, 0, 0x02, &[])));
+
+    let syn_ack = next_reply(&interceptor).await;
+    let segment = verify(&syn_ack);
+    assert_eq!(segment[13], 0x12);
+    assert_eq!(u32::from_be_bytes(segment[8..12].try_into().unwrap()), 1001);
+    let server_seq = u32::from_be_bytes(segment[4..8].try_into().unwrap()).wrapping_add(1);
+
+    let mut frame = (query(5).len() as u16).to_be_bytes().to_vec();
+    frame.extend_from_slice(&query(5));
+    let client_seq = 1001 + frame.len() as u32;
+
+    interceptor.intercept(&alice(), ALICE, &tcp_packet(1001, server_seq, 0x18, &frame));
+    interceptor.intercept(&alice(), ALICE, &tcp_packet(client_seq, server_seq, 0x11, &[]));
+
+    let ack = next_reply(&interceptor).await;
+    assert_eq!(u32::from_be_bytes(verify(&ack)[8..12].try_into().unwrap()), client_seq);
+
+    let ack = next_reply(&interceptor).await;
+    assert_eq!(u32::from_be_bytes(verify(&ack)[8..12].try_into().unwrap()), client_seq + 1);
+
+    // the answer follows the client's FIN, then the server closes too.
+    let data = next_reply(&interceptor).await;
+    let segment = verify(&data);
+    assert_eq!(u32::from_be_bytes(segment[4..8].try_into().unwrap()), server_seq);
+
+    let answer = &segment[22..];
+    assert_eq!(u16::from_be_bytes([segment[20], segment[21]]) as usize, answer.len());
+    assert_eq!(dns_message::id(answer), Some(5));
+
+    let fin = next_reply(&interceptor).await;
+    let segment = verify(&fin);
+    assert_eq!(segment[13], 0x11);
+    assert_eq!(u32::from_be_bytes(segment[4..8].try_into().unwrap()), server_seq + 2 + answer.len() as u32);
+}
+
+#[tokio::test]
+async fn answers_of_a_reset_flow_stay_out_of_its_successor() {
+    let interceptor = interceptor().await;
+
+    let framed = |id: u16| {
+        let mut frame = (query(id).len() as u16).to_be_bytes().to_vec();
+        frame.extend_from_slice(&query(id));
+        frame
+    };
+
+    interceptor.intercept(&alice(), ALICE, &tcp_packet(1000, 0, 0x02, &[]));
+    let segment = verify(&next_reply(&interceptor).await).to_vec();
+    let server_seq = u32::from_be_bytes(segment[4..8].try_into().unwrap()).wrapping_add(1);
+
+    // the query is still resolving when the client resets and reconnects on the same port.
+    interceptor.intercept(&alice(), ALICE, &tcp_packet(1001, server_seq, 0x18, &framed(5)));
+    interceptor.intercept(&alice(), ALICE, &tcp_packet(1001 + framed(5).len() as u32, server_seq, 0x04, &[]));
+    interceptor.intercept(&alice(), ALICE, &tcp_packet(5000, 0, 0x02, &[]));
+
+    let ack = next_reply(&interceptor).await;
+    assert_eq!(verify(&ack)[13], 0x10);
+
+    let syn_ack = next_reply(&interceptor).await;
+    let segment = verify(&syn_ack).to_vec();
+    assert_eq!(segment[13], 0x12);
+    let server_seq = u32::from_be_bytes(segment[4..8].try_into().unwrap()).wrapping_add(1);
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let client_seq = 5001 + framed(6).len() as u32;
+    interceptor.intercept(&alice(), ALICE, &tcp_packet(5001, server_seq, 0x18, &framed(6)));
+    interceptor.intercept(&alice(), ALICE, &tcp_packet(client_seq, server_seq, 0x11, &[]));
+
+    assert_eq!(u32::from_be_bytes(verify(&next_reply(&interceptor).await)[8..12].try_into().unwrap()), client_seq);
+    assert_eq!(u32::from_be_bytes(verify(&next_reply(&interceptor).await)[8..12].try_into().unwrap()), client_seq + 1);
+
+    // only the new flow's answer is sent, at the new flow's sequence number.
+    let data = next_reply(&interceptor).await;
+    let segment = verify(&data);
+    assert_eq!(u32::from_be_bytes(segment[4..8].try_into().unwrap()), server_seq);
+    assert_eq!(dns_message::id(&segment[22..]), Some(6));
+
+    let fin = next_reply(&interceptor).await;
+    assert_eq!(verify(&fin)[13], 0x11);
+}
+
+#[tokio::test]
+async fn tiny_mtu_does_not_underflow() {
+    let sessions_pool: SessionsPool = Arc::new(RwLock::new(HashMap::new()));
+
+    let dns = Dns::new("127.0.0.1:0", sessions_pool)
+        .unwrap()
+        .with_upstreams(DnsUpstreams::new(vec![spawn_upstream().await.0], Duration::from_millis(500)));
+
+    let interceptor = Arc::new(DnsInterceptor::new(Arc::new(dns), 20));
+
+    assert!(interceptor.intercept(&alice(), ALICE, &udp_packet(ALICE, RESOLVER, 53, &query(9))));
+
+    // nothing fits, so the answer is cut down to its question.
+    let answer = &verify(&next_reply(&interceptor).await)[8..].to_vec();
+    assert!(dns_message::is_truncated(answer));
+}
@@ -0,0 +1,11 @@
+use smo::dns_message;
+
+#[test]
+fn inflated_section_counts_are_refused() {
+    // a bare header claiming 65535 records in every section.
+    let mut query = vec![0x12, 0x34, 0x01, 0x00, 0, 0];
+    query.extend_from_slice(&[0xff; 6]);
+
+    assert!(dns_message::records(&query).is_none());
+    assert_eq!(dns_message::udp_payload_size(&query), dns_message::UDP_PAYLOAD_MIN);
+}
@@ -0,0 +1,346 @@
+mod common;
+
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+use ring::aead::LessSafeKey;
+use smo::cipher_suite::CipherSuite;
+use smo::data_header::DataHeader;
+use smo::dns::{open, read_frame, seal, write_frame, Dns};
+use smo::dns_cache::DnsCache;
+use smo::dns_message;
+use smo::dns_upstream::DnsUpstreams;
+use smo::message_type::MessageType;
+use smo::obfuscation::Obfuscation;
+use smo::session::SessionsPool;
+use smo::session_context::SessionContext;
+use smo::session_payload::SessionPayload;
+use smo::user::User;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::RwLock;
+use common::{query, spawn_upstream};
+
+const SESSION_ID: u32 = 7;
+
+fn session_key() -> LessSafeKey {
+    CipherSuite::ChaCha20Poly1305.less_safe_key(&[7u8; 32]).unwrap()
+}
+
+/// The same query advertising a 4096 byte EDNS0 payload size.
+fn edns_query(id: u16) -> Vec<u8> {
+    let mut query = query(id);
+    query[11] = 1;
+    query.extend_from_slice(&[0, 0, 41, 0x10, 0, 0, 0, 0, 0, 0, 0]);
+    query
+}
+
+/// Answer to `query` with 64 A records, too large for a plain UDP answer.
+fn large_answer(query: &[u8]) -> Vec<u8> {
+    let mut answer = dns_message::response(query, dns_message::RCODE_NOERROR).unwrap();
+    answer[7] = 64;
+
+    for host in 0..64u8 {
+        answer.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 1, 44, 0, 4, 10, 0, 0, host]);
+    }
+
+    answer
+}
+
+/// Upstream answering UDP queries truncated and TCP queries with `large_answer`.
+async fn spawn_large_upstream() -> SocketAddr {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let listener = TcpListener::bind(addr).await.unwrap();
+
+    tokio::spawn(async move {
+        let mut buf = [0u8; 2048];
+
+        while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+            let answer = dns_message::truncated(&large_answer(&buf[..n])).unwrap();
+            socket.send_to(&answer, peer).await.unwrap();
+        }
+    });
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let query = read_frame(&mut stream).await.unwrap();
+            write_frame(&mut stream, &large_answer(&query)).await.unwrap();
+        }
+    });
+
+    addr
+}
+
+/// Address nobody answers on, kept bound so the port is not reused.
+async fn dead_upstream() -> (UdpSocket, SocketAddr) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    (socket, addr)
+}
+
+/// Proxy serving alice's session `SESSION_ID`.
+async fn spawn_proxy(upstreams: Vec<SocketAddr>) -> (SocketAddr, Arc<Dns>, SessionsPool) {
+    spawn_proxy_with(upstreams, |dns| dns).await
+}
+
+async fn spawn_proxy_with(upstreams: Vec<SocketAddr>, configure: impl FnOnce(Dns) -> Dns) -> (SocketAddr, Arc<Dns>, SessionsPool) {
+    let mut context = SessionContext::new();
+    context.set_pk(session_key());
+
+    let sessions_pool: SessionsPool = Arc::new(RwLock::new(HashMap::new()));
+    sessions_pool.write().await.insert(SESSION_ID, SessionPayload::new(
+        User::new(1, "alice", Ipv4Addr::new(10, 8, 0, 2), true),
+        &context,
+        "127.0.0.1:1".parse().unwrap(),
+        "127.0.0.1:2".parse().unwrap(),
+    ));
+
+    let dns = Dns::new("127.0.0.1:0", sessions_pool.clone())
+        .unwrap()
+        .with_upstreams(DnsUpstreams::new(upstreams, Duration::from_millis(200)))
+        .with_cache(DnsCache::new(16, 0, 86400, 900));
+    let dns = configure(dns);
+
+    let addr = dns.async_socket.local_addr().unwrap();
+    let dns = Arc::new(dns);
+    let proxy = dns.clone();
+
+    tokio::spawn(async move { proxy.expose().await });
+
+    (addr, dns, sessions_pool)
+}
+
+/// Sends a sealed query as `SESSION_ID`, `None` when the proxy does not answer.
+async fn try_ask(proxy: SocketAddr, id: u16, timeout: Duration) -> Option<Vec<u8>> {
+    try_ask_query(proxy, id, &query(id), timeout).await
+}
+
+async fn try_ask_query(proxy: SocketAddr, id: u16, query: &[u8], timeout: Duration) -> Option<Vec<u8>> {
+    let key = session_key();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+    let header = DataHeader::new(MessageType::DnsQuery, SESSION_ID, id as u64);
+    socket.send_to(&seal(header, query, &key), proxy).await.unwrap();
+
+    let mut buf = vec![0u8; 65535];
+    let n = tokio::time::timeout(timeout, socket.recv(&mut buf))
+        .await
+        .ok()?
+        .unwrap();
+
+    let answer_header = DataHeader::parse(&buf[..n]).unwrap();
+    assert_eq!(answer_header.message_type, MessageType::DnsAnswer);
+    assert_eq!(answer_header.counter, id as u64);
+
+    open(&buf[..n], &key)
+}
+
+async fn ask(proxy: SocketAddr, id: u16) -> Vec<u8> {
+    try_ask(proxy, id, Duration::from_secs(5))
+        .await
+        .expect("DNS answer timed out")
+}
+
+/// Sends a sealed query as `SESSION_ID` over TCP.
+async fn ask_tcp(proxy: SocketAddr, id: u16, query: &[u8]) -> Vec<u8> {
+    let key = session_key();
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+
+    let header = DataHeader::new(MessageType::DnsQuery, SESSION_ID, id as u64);
+    write_frame(&mut stream, &seal(header, query, &key)).await.unwrap();
+
+    let packet = tokio::time::timeout(Duration::from_secs(5), read_frame(&mut stream))
+        .await
+        .expect("DNS answer timed out")
+        .unwrap();
+
+    let answer_header = DataHeader::parse(&packet).unwrap();
+    assert_eq!(answer_header.message_type, MessageType::DnsAnswer);
+    assert_eq!(answer_header.counter, id as u64);
+
+    open(&packet, &key).unwrap()
+}
+
+fn answer_count(answer: &[u8]) -> u16 {
+    u16::from_be_bytes([answer[6], answer[7]])
+}
+
+#[tokio::test]
+async fn fails_over_to_next_upstream() {
+    let (_dead, dead_addr) = dead_upstream().await;
+    let (upstream_addr, _) = spawn_upstream().await;
+    let (proxy, _, _) = spawn_proxy(vec![dead_addr, upstream_addr]).await;
+
+    let answer = ask(proxy, 0x1234).await;
+
+    assert_eq!(dns_message::id(&answer), Some(0x1234));
+    assert_eq!(answer[3] & 0x0f, 0);
+}
+
+#[tokio::test]
+async fn answers_servfail_when_all_upstreams_fail() {
+    let (_dead, dead_addr) = dead_upstream().await;
+    let (proxy, _, _) = spawn_proxy(vec![dead_addr]).await;
+
+    let answer = ask(proxy, 0x4321).await;
+
+    assert_eq!(dns_message::id(&answer), Some(0x4321));
+    assert_eq!(answer[3] & 0x0f, dns_message::RCODE_SERVFAIL);
+    assert_eq!(dns_message::question(&answer).unwrap().0.name, "example.com");
+}
+
+#[tokio::test]
+async fn repeated_queries_are_answered_from_cache() {
+    let (upstream_addr, queries) = spawn_upstream().await;
+    let (proxy, dns, _) = spawn_proxy(vec![upstream_addr]).await;
+
+    assert_eq!(dns_message::id(&ask(proxy, 1).await), Some(1));
+    assert_eq!(dns_message::id(&ask(proxy, 2).await), Some(2));
+
+    assert_eq!(queries.load(Ordering::Relaxed), 1);
+    assert_eq!(dns.cache_stats().hits, 1);
+}
+
+#[tokio::test]
+async fn queries_without_an_active_session_are_dropped() {
+    let (upstream_addr, queries) = spawn_upstream().await;
+    let (proxy, _, sessions_pool) = spawn_proxy(vec![upstream_addr]).await;
+
+    sessions_pool.write().await.remove(&SESSION_ID);
+
+    assert_eq!(try_ask(proxy, 1, Duration::from_millis(300)).await, None);
+    assert_eq!(queries.load(Ordering::Relaxed), 0);
+}
+
+#[tokio::test]
+async fn truncated_upstream_answers_are_retried_over_tcp() {
+    let upstream_addr = spawn_large_upstream().await;
+    let (proxy, _, _) = spawn_proxy(vec![upstream_addr]).await;
+
+    let answer = try_ask_query(proxy, 1, &edns_query(1), Duration::from_secs(5)).await.unwrap();
+
+    assert!(!dns_message::is_truncated(&answer));
+    assert_eq!(answer_count(&answer), 64);
+    assert_eq!(dns_message::id(&answer), Some(1));
+}
+
+#[tokio::test]
+async fn udp_answers_beyond_the_payload_size_are_truncated() {
+    let upstream_addr = spawn_large_upstream().await;
+    let (proxy, _, _) = spawn_proxy(vec![upstream_addr]).await;
+
+    // without EDNS0 the client only accepts 512 bytes.
+    let answer = ask(proxy, 2).await;
+
+    assert!(dns_message::is_truncated(&answer));
+    assert_eq!(answer_count(&answer), 0);
+    assert_eq!(dns_message::question(&answer).unwrap().0.name, "example.com");
+}
+
+#[tokio::test]
+async fn answers_queries_over_tcp() {
+    let upstream_addr = spawn_large_upstream().await;
+    let (proxy, _, _) = spawn_proxy(vec![upstream_addr]).await;
+
+    let answer = ask_tcp(proxy, 3, &query(3)).await;
+
+    assert!(!dns_message::is_truncated(&answer));
+    assert_eq!(answer_count(&answer), 64);
+    assert_eq!(dns_message::id(&answer), Some(3));
+}
+
+#[tokio::test]
+async fn replayed_queries_are_dropped() {
+    let (upstream_addr, queries) = spawn_upstream().await;
+    let (proxy, _, _) = spawn_proxy(vec![upstream_addr]).await;
+
+    assert_eq!(dns_message::id(&ask(proxy, 5).await), Some(5));
+
+    // the same sealed query again, as an observer on the path could resend it.
+    assert_eq!(try_ask(proxy, 5, Duration::from_millis(300)).await, None);
+
+    // nor is it answered on the TCP port.
+    let mut stream = TcpStream::connect(proxy).await.unwrap();
+    let header = DataHeader::new(MessageType::DnsQuery, SESSION_ID, 5);
+    write_frame(&mut stream, &seal(header, &query(5), &session_key())).await.unwrap();
+    assert!(read_frame(&mut stream).await.is_err());
+
+    assert_eq!(dns_message::id(&ask(proxy, 6).await), Some(6));
+    assert_eq!(queries.load(Ordering::Relaxed), 1);
+}
+
+/// Whether the proxy closes `stream` within `timeout`.
+async fn closed_within(stream: &mut TcpStream, timeout: Duration) -> bool {
+    let mut buf = [0u8; 16];
+
+    matches!(tokio::time::timeout(timeout, stream.read(&mut buf)).await, Ok(Ok(0) | Err(_)))
+}
+
+#[tokio::test]
+async fn unauthenticated_tcp_connections_are_closed() {
+    let (upstream_addr, queries) = spawn_upstream().await;
+    let (proxy, _, _) = spawn_proxy(vec![upstream_addr]).await;
+
+    let mut forged = TcpStream::connect(proxy).await.unwrap();
+    let header = DataHeader::new(MessageType::DnsQuery, SESSION_ID, 1);
+    let wrong_key = CipherSuite::ChaCha20Poly1305.less_safe_key(&[8u8; 32]).unwrap();
+    write_frame(&mut forged, &seal(header, &query(1), &wrong_key)).await.unwrap();
+    assert!(closed_within(&mut forged, Duration::from_millis(300)).await);
+
+    // a client that never sends a query is closed long before the idle timeout.
+    let mut silent = TcpStream::connect(proxy).await.unwrap();
+    assert!(closed_within(&mut silent, Duration::from_secs(5)).await);
+
+    assert_eq!(queries.load(Ordering::Relaxed), 0);
+}
+
+#[tokio::test]
+async fn tcp_connections_beyond_the_limit_are_closed() {
+    let upstream_addr = spawn_large_upstream().await;
+    let (proxy, _, _) = spawn_proxy_with(vec![upstream_addr], |dns| dns.with_max_tcp_connections(1)).await;
+
+    let mut held = TcpStream::connect(proxy).await.unwrap();
+    held.write_all(&[0]).await.unwrap();
+
+    let mut refused = TcpStream::connect(proxy).await.unwrap();
+    assert!(closed_within(&mut refused, Duration::from_millis(300)).await);
+
+    // the slot is free again once the held connection is gone.
+    drop(held);
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let answer = ask_tcp(proxy, 4, &query(4)).await;
+    assert_eq!(dns_message::id(&answer), Some(4));
+}
+
+#[tokio::test]
+async fn answers_obfuscated_queries() {
+    let obfuscation = Obfuscation::new(b"dns-proxy-obfuscation");
+    let (upstream_addr, _) = spawn_upstream().await;
+    let (proxy, _, _) = spawn_proxy_with(vec![upstream_addr], |dns| dns.with_obfuscation(Some(obfuscation.clone()))).await;
+    let key = session_key();
+
+    // plain queries go unanswered once the proxy is obfuscated.
+    assert_eq!(try_ask(proxy, 1, Duration::from_millis(300)).await, None);
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let header = DataHeader::new(MessageType::DnsQuery, SESSION_ID, 2);
+    socket.send_to(&obfuscation.seal_datagram(&seal(header, &query(2), &key)), proxy).await.unwrap();
+
+    let mut buf = vec![0u8; 65535];
+    let n = tokio::time::timeout(Duration::from_secs(5), socket.recv(&mut buf)).await.unwrap().unwrap();
+
+    let packet = obfuscation.open_datagram(&buf[..n]).unwrap();
+    assert_eq!(DataHeader::parse(&packet).unwrap().message_type, MessageType::DnsAnswer);
+    assert_eq!(dns_message::id(&open(&packet, &key).unwrap()), Some(2));
+
+    let mut stream = obfuscation.stream(TcpStream::connect(proxy).await.unwrap());
+    let header = DataHeader::new(MessageType::DnsQuery, SESSION_ID, 3);
+    write_frame(&mut stream, &seal(header, &query(3), &key)).await.unwrap();
+
+    let packet = tokio::time::timeout(Duration::from_secs(5), read_frame(&mut stream)).await.unwrap().unwrap();
+    assert_eq!(dns_message::id(&open(&packet, &key).unwrap()), Some(3));
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::time::Duration;
+use serde_json::Value;
+use smo::dns_message;
+use smo::dns_query_log::{DnsQueryLog, DnsQueryLogNames};
+use smo::user::User;
+use common::{question, scratch};
+
+fn source() -> SocketAddr {
+    "192.0.2.1:5353".parse().unwrap()
+}
+
+fn read_lines(path: &Path) -> Vec<Value> {
+    std::fs::read_to_string(path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn record(query_log: &DnsQueryLog, name: &str) {
+    let alice = User::new(1, "alice", Ipv4Addr::new(10, 8, 0, 2), true);
+    let query = question(name, dns_message::TYPE_A);
+    let answer = dns_message::response(&query, dns_message::RCODE_NXDOMAIN).unwrap();
+
+    query_log.record(&alice, source(), &query, &answer, Some(Duration::from_millis(12)));
+    query_log.flush();
+}
+
+#[test]
+fn records_queries_as_json_lines() {
+    let dir = scratch("dns-query-log", "plain");
+    let path = dir.join("queries.jsonl");
+    let query_log = DnsQueryLog::open(path.to_str().unwrap(), 1 << 20, 2, DnsQueryLogNames::Plain).unwrap();
+
+    record(&query_log, "example.com");
+
+    let lines = read_lines(&path);
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["user"], "alice");
+    assert_eq!(lines[0]["source"], "192.0.2.1:5353");
+    assert_eq!(lines[0]["name"], "example.com");
+    assert_eq!(lines[0]["type"], dns_message::TYPE_A);
+    assert_eq!(lines[0]["rcode"], dns_message::RCODE_NXDOMAIN);
+    assert_eq!(lines[0]["upstream_ms"], 12);
+    assert!(lines[0]["timestamp"].is_string());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn hides_names_when_asked() {
+    let dir = scratch("dns-query-log", "private");
+
+    let hashed = dir.join("hashed.jsonl");
+    let query_log = DnsQueryLog::open(hashed.to_str().unwrap(), 1 << 20, 2, DnsQueryLogNames::Hash)
+        .unwrap()
+        .with_hash_key(b"key");
+
+    record(&query_log, "example.com");
+    record(&query_log, "example.com");
+    record(&query_log, "example.net");
+
+    let lines = read_lines(&hashed);
+    assert_eq!(lines[0]["name"], lines[1]["name"]);
+    assert_ne!(lines[0]["name"], lines[2]["name"]);
+    assert_eq!(lines[0]["name"].as_str().unwrap().len(), 64);
+    assert!(!lines[0]["name"].as_str().unwrap().contains("example"));
+
+    let off = dir.join("off.jsonl");
+    let query_log = DnsQueryLog::open(off.to_str().unwrap(), 1 << 20, 2, DnsQueryLogNames::Off).unwrap();
+
+    record(&query_log, "example.com");
+
+    let lines = read_lines(&off);
+    assert!(lines[0].get("name").is_none());
+    assert_eq!(lines[0]["user"], "alice");
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn rotates_by_size() {
+    let dir = scratch("dns-query-log", "rotate");
+    let path = dir.join("queries.jsonl");
+
+    // every line is well over 100 bytes, so each one starts a new file.
+    let query_log = DnsQueryLog::open(path.to_str().unwrap(), 100, 2, DnsQueryLogNames::Plain).unwrap();
+
+    for name in ["one.test", "two.test", "three.test", "four.test"] {
+        record(&query_log, name);
+    }
+
+    assert_eq!(read_lines(&path)[0]["name"], "four.test");
+    assert_eq!(read_lines(&dir.join("queries.jsonl.1"))[0]["name"], "three.test");
+    assert_eq!(read_lines(&dir.join("queries.jsonl.2"))[0]["name"], "two.test");
+    assert!(!dir.join("queries.jsonl.3").exists());
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn bursts_within_the_queue_are_written_in_order() {
+    let dir = scratch("dns-query-log", "burst");
+    let path = dir.join("queries.jsonl");
+    let query_log = DnsQueryLog::open(path.to_str().unwrap(), 1 << 20, 2, DnsQueryLogNames::Plain).unwrap();
+    let alice = User::new(1, "alice", Ipv4Addr::new(10, 8, 0, 2), true);
+
+    for index in 0..500 {
+        let query = question(&format!("host{index}.test"), dns_message::TYPE_A);
+        let answer = dns_message::response(&query, dns_message::RCODE_NOERROR).unwrap();
+
+        query_log.record(&alice, source(), &query, &answer, None);
+    }
+
+    query_log.flush();
+
+    let lines = read_lines(&path);
+    assert_eq!(lines.len(), 500);
+    assert_eq!(lines[0]["name"], "host0.test");
+    assert_eq!(lines[499]["name"], "host499.test");
+    assert_eq!(query_log.dropped(), 0);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
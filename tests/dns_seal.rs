@@ -0,0 +1,41 @@
+use ring::aead::LessSafeKey;
+use smo::cipher_suite::CipherSuite;
+use smo::data_header::DataHeader;
+use smo::dns::{open, seal};
+use smo::message_type::MessageType;
+
+fn session_key(material: u8) -> LessSafeKey {
+    CipherSuite::Aes256Gcm.less_safe_key(&[material; 32]).unwrap()
+}
+
+#[test]
+fn sealed_query_round_trip() {
+    let key = session_key(1);
+    let header = DataHeader::new(MessageType::DnsQuery, 7, 7);
+
+    let packet = seal(header, b"query", &key);
+
+    assert_eq!(DataHeader::parse(&packet), Some(header));
+    assert_eq!(open(&packet, &key).as_deref(), Some(&b"query"[..]));
+}
+
+#[test]
+fn tampered_or_foreign_packets_are_rejected() {
+    let key = session_key(1);
+    let header = DataHeader::new(MessageType::DnsQuery, 7, 7);
+
+    let packet = seal(header, b"query", &key);
+    assert_eq!(open(&packet, &session_key(2)), None);
+
+    // the header is authenticated as well as the body.
+    let mut tampered = packet.clone();
+    tampered[12] ^= 1;
+    assert_eq!(open(&tampered, &key), None);
+
+    let mut tampered = packet;
+    let last = tampered.len() - 13;
+    tampered[last] ^= 1;
+    assert_eq!(open(&tampered, &key), None);
+
+    assert_eq!(open(b"query", &key), None);
+}
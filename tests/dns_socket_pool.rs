@@ -0,0 +1,78 @@
+mod common;
+
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use smo::dns_message;
+use smo::dns_socket_pool::DnsSocketPool;
+use tokio::net::UdpSocket;
+use common::query;
+
+/// Upstream echoing queries as answers, recording source ports and ids it saw.
+async fn spawn_upstream() -> (SocketAddr, Arc<Mutex<Vec<(u16, u16)>>>) {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let record = seen.clone();
+
+    tokio::spawn(async move {
+        let mut buf = [0u8; 2048];
+
+        while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+            record.lock().unwrap().push((peer.port(), dns_message::id(&buf[..n]).unwrap()));
+
+            let answer = dns_message::response(&buf[..n], dns_message::RCODE_NOERROR).unwrap();
+            socket.send_to(&answer, peer).await.unwrap();
+        }
+    });
+
+    (addr, seen)
+}
+
+#[tokio::test]
+async fn concurrent_queries_share_the_pool() {
+    let (upstream, seen) = spawn_upstream().await;
+    let pool = Arc::new(DnsSocketPool::new("127.0.0.1:0".parse().unwrap(), 4));
+
+    let exchanges = (0..200u16).map(|id| {
+        let pool = pool.clone();
+
+        tokio::spawn(async move {
+            let answer = tokio::time::timeout(Duration::from_secs(5), pool.exchange(upstream, &query(id)))
+                .await
+                .unwrap()
+                .unwrap();
+
+            assert_eq!(dns_message::id(&answer), Some(id));
+        })
+    }).collect::<Vec<_>>();
+
+    for exchange in exchanges {
+        exchange.await.unwrap();
+    }
+
+    let seen = seen.lock().unwrap();
+    let ports = seen.iter().map(|(port, _)| *port).collect::<HashSet<_>>();
+    let ids = seen.iter().filter(|(_, id)| *id < 200).count();
+
+    assert_eq!(seen.len(), 200);
+    assert!(ports.len() <= 4);
+
+    // outgoing ids are random, not the requesters' 0..200.
+    assert!(ids < 20);
+}
+
+#[tokio::test]
+async fn unanswered_queries_are_forgotten() {
+    let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let pool = DnsSocketPool::new("127.0.0.1:0".parse().unwrap(), 1);
+
+    let query_1 = query(1);
+    let exchange = tokio::time::timeout(Duration::from_millis(100), pool.exchange(silent.local_addr().unwrap(), &query_1));
+    assert!(exchange.await.is_err());
+
+    let (upstream, _) = spawn_upstream().await;
+    let answer = pool.exchange(upstream, &query(2)).await.unwrap();
+    assert_eq!(dns_message::id(&answer), Some(2));
+}
@@ -0,0 +1,76 @@
+mod common;
+
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use smo::dns_message;
+use smo::dns_zone::DnsZone;
+use smo::tunnel_config::TunnelConfig;
+use smo::user::User;
+use smo::user_store_memory::MemoryUserStore;
+use common::question;
+
+async fn zone() -> DnsZone {
+    let user_store = Arc::new(MemoryUserStore::new(vec![
+        User::new(1, "Alice", Ipv4Addr::new(10, 8, 0, 2), true),
+        User::new(2, "bob", Ipv4Addr::new(10, 8, 0, 3), false),
+    ]));
+
+    let tunnel_config = TunnelConfig::new(Ipv4Addr::new(10, 8, 0, 1), Ipv4Addr::new(255, 255, 0, 0), 1450);
+    let zone = DnsZone::new("vpn.internal.", user_store, tunnel_config);
+
+    zone.refresh().await.unwrap();
+    zone
+}
+
+fn ask(zone: &DnsZone, name: &str, qtype: u16) -> Option<Vec<u8>> {
+    let query = question(name, qtype);
+    let (question, _) = dns_message::question(&query).unwrap();
+
+    zone.answer(&query, &question)
+}
+
+#[tokio::test]
+async fn resolves_users_by_name() {
+    let zone = zone().await;
+    let answer = ask(&zone, "alice.vpn.internal", dns_message::TYPE_A).unwrap();
+
+    assert_eq!(dns_message::rcode(&answer), Some(dns_message::RCODE_NOERROR));
+    assert_eq!(answer[2] & 0x04, 0x04);
+
+    let records = dns_message::records(&answer).unwrap();
+    assert_eq!(records.len(), 1);
+    assert_eq!(&answer[records[0].rdata.clone()], &[10, 8, 0, 2]);
+
+    let answer = ask(&zone, "alice.vpn.internal", dns_message::TYPE_AAAA).unwrap();
+    assert_eq!(dns_message::rcode(&answer), Some(dns_message::RCODE_NOERROR));
+    assert!(dns_message::records(&answer).unwrap().is_empty());
+
+    // disabled users and unknown names do not exist.
+    let answer = ask(&zone, "bob.vpn.internal", dns_message::TYPE_A).unwrap();
+    assert_eq!(dns_message::rcode(&answer), Some(dns_message::RCODE_NXDOMAIN));
+
+    let answer = ask(&zone, "carol.vpn.internal", dns_message::TYPE_A).unwrap();
+    assert_eq!(dns_message::rcode(&answer), Some(dns_message::RCODE_NXDOMAIN));
+}
+
+#[tokio::test]
+async fn resolves_tunnel_addresses_in_reverse() {
+    let zone = zone().await;
+    let answer = ask(&zone, "2.0.8.10.in-addr.arpa", dns_message::TYPE_PTR).unwrap();
+
+    let records = dns_message::records(&answer).unwrap();
+    let (name, _) = dns_message::read_name(&answer, records[0].rdata.start).unwrap();
+    assert_eq!(name, "alice.vpn.internal");
+
+    let answer = ask(&zone, "9.0.8.10.in-addr.arpa", dns_message::TYPE_PTR).unwrap();
+    assert_eq!(dns_message::rcode(&answer), Some(dns_message::RCODE_NXDOMAIN));
+}
+
+#[tokio::test]
+async fn forwards_names_outside_the_zone() {
+    let zone = zone().await;
+
+    assert_eq!(ask(&zone, "example.com", dns_message::TYPE_A), None);
+    assert_eq!(ask(&zone, "evilvpn.internal", dns_message::TYPE_A), None);
+    assert_eq!(ask(&zone, "1.1.1.1.in-addr.arpa", dns_message::TYPE_PTR), None);
+}
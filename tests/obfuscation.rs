@@ -0,0 +1,126 @@
+use smo::data_header::{DataHeader, DATA_HEADER_LEN};
+use smo::message_type::MessageType;
+use smo::obfuscation::Obfuscation;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Header followed by 40 bytes standing in for a sealed body.
+fn datagram() -> Vec<u8> {
+    let mut datagram = DataHeader::new(MessageType::Data, 7, 42).to_bytes().to_vec();
+    datagram.extend((0..40u8).map(|byte| byte.wrapping_mul(37)));
+    datagram
+}
+
+#[tokio::test]
+async fn stream_round_trip_hides_plaintext() {
+    let obfuscation = Obfuscation::new(b"obfuscation-test");
+    let (client, mut wire) = tokio::io::duplex(4096);
+    let (mut server_wire, server) = tokio::io::duplex(4096);
+
+    let mut client = obfuscation.stream(client);
+    let mut server = obfuscation.stream(server);
+
+    let sign = [&[0x22u8, 0, 0, 0, 32][..], &[9u8; 32]].concat();
+    client.write_all(&sign).await.unwrap();
+    client.write_all(b"second").await.unwrap();
+
+    let mut captured = vec![0u8; 4096];
+    let n = wire.read(&mut captured).await.unwrap();
+    captured.truncate(n);
+
+    // a nonce, then one record header per write.
+    assert!(captured.len() >= 24 + 4 + sign.len() + 4 + 6);
+    assert!(!captured.windows(sign.len()).any(|window| window == sign));
+
+    // the captured bytes are handed over one at a time, nonce and padding
+    // split across reads.
+    for byte in &captured {
+        server_wire.write_all(&[*byte]).await.unwrap();
+    }
+
+    let mut received = vec![0u8; sign.len() + 6];
+    server.read_exact(&mut received).await.unwrap();
+
+    assert_eq!(received, [&sign[..], b"second"].concat());
+}
+
+#[tokio::test]
+async fn stream_pads_every_write() {
+    let obfuscation = Obfuscation::new(b"obfuscation-test");
+    let (client, mut wire) = tokio::io::duplex(4096);
+    let mut client = obfuscation.stream(client);
+
+    client.write_all(b"first").await.unwrap();
+    let mut captured = vec![0u8; 4096];
+    assert!(wire.read(&mut captured).await.unwrap() >= 24 + 4 + 5);
+
+    // the same message, written again and again, leaves the stream at varying lengths.
+    let mut lengths = std::collections::HashSet::new();
+
+    for _ in 0..16 {
+        client.write_all(b"again").await.unwrap();
+
+        let n = wire.read(&mut captured).await.unwrap();
+        assert!(n >= 4 + 5);
+        lengths.insert(n);
+    }
+
+    assert!(lengths.len() > 1);
+}
+
+#[tokio::test]
+async fn stream_splits_large_writes_into_records() {
+    let obfuscation = Obfuscation::new(b"obfuscation-test");
+    let (client, server) = tokio::io::duplex(1 << 16);
+    let mut client = obfuscation.stream(client);
+    let mut server = obfuscation.stream(server);
+
+    let message = (0..100_000u32).map(|index| index as u8).collect::<Vec<_>>();
+    let expected = message.clone();
+
+    tokio::spawn(async move {
+        client.write_all(&message).await.unwrap();
+        client.shutdown().await.unwrap();
+    });
+
+    let mut received = Vec::new();
+    server.read_to_end(&mut received).await.unwrap();
+
+    assert_eq!(received, expected);
+}
+
+#[tokio::test]
+async fn stream_with_other_key_yields_garbage() {
+    let (client, server) = tokio::io::duplex(4096);
+    let mut client = Obfuscation::new(b"obfuscation-test").stream(client);
+    let mut server = Obfuscation::new(b"other key").stream(server);
+
+    client.write_all(&[0u8; 512]).await.unwrap();
+    client.shutdown().await.unwrap();
+
+    let mut received = Vec::new();
+    server.read_to_end(&mut received).await.unwrap();
+
+    assert_ne!(received, vec![0u8; 512]);
+}
+
+#[test]
+fn datagram_round_trip_masks_header() {
+    let obfuscation = Obfuscation::new(b"obfuscation-test");
+    let datagram = datagram();
+
+    let sealed = obfuscation.seal_datagram(&datagram);
+
+    assert!(sealed.len() > datagram.len());
+    assert_ne!(sealed[..DATA_HEADER_LEN], datagram[..DATA_HEADER_LEN]);
+    assert_eq!(obfuscation.open_datagram(&sealed), Some(datagram));
+}
+
+#[test]
+fn datagram_with_other_key_does_not_open_to_header() {
+    let datagram = datagram();
+    let sealed = Obfuscation::new(b"obfuscation-test").seal_datagram(&datagram);
+
+    let opened = Obfuscation::new(b"other key").open_datagram(&sealed);
+
+    assert_ne!(opened, Some(datagram));
+}
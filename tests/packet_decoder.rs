@@ -0,0 +1,47 @@
+use ring::aead::{LessSafeKey, UnboundKey, AES_256_GCM};
+use smo::message_type::MessageType;
+use smo::packet_decoder::PacketDecoder;
+use smo::packet_encoder::PacketEncoder;
+
+fn key(material: u8) -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &[material; 32]).unwrap())
+}
+
+#[test]
+fn truncated_fields_are_errors() {
+    let mut packet = PacketEncoder::new();
+    packet.write_opcode(MessageType::Sign);
+    packet.write_string(&[9u8; 32]);
+    packet.write_u16(3);
+
+    let bytes = packet.to_bytes(None);
+    let mut packet = PacketDecoder::new(&bytes[..bytes.len() - 1]);
+
+    assert_eq!(packet.read_opcode(), MessageType::Sign);
+    assert_eq!(packet.try_read_string().unwrap(), [9u8; 32]);
+    assert!(packet.try_read_uint16().is_err());
+    assert!(packet.try_read_uint32().is_err());
+    assert_eq!(PacketDecoder::new(&[]).read_opcode(), MessageType::Undefined);
+}
+
+#[test]
+fn string_lengths_beyond_the_message_are_refused() {
+    let mut packet = PacketEncoder::new();
+    packet.write_u32(u32::MAX);
+    packet.write_u32(0);
+
+    assert!(PacketDecoder::new(&packet.to_bytes(None)).try_read_string().is_err());
+}
+
+#[test]
+fn sealed_packets_do_not_fall_back_to_plaintext() {
+    let mut packet = PacketEncoder::new();
+    packet.write_opcode(MessageType::Trace);
+
+    let plain = packet.to_bytes(None);
+    let sealed = packet.to_bytes(Some(key(1)));
+
+    assert!(PacketDecoder::open(&plain, &key(1), &[]).is_none());
+    assert!(PacketDecoder::open(&sealed, &key(2), &[]).is_none());
+    assert_eq!(PacketDecoder::open(&sealed, &key(1), &[]).unwrap().read_opcode(), MessageType::Trace);
+}
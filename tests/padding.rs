@@ -0,0 +1,97 @@
+use ring::aead::{LessSafeKey, UnboundKey, AES_256_GCM};
+use smo::message_type::MessageType;
+use smo::packet_decoder::PacketDecoder;
+use smo::packet_encoder::PacketEncoder;
+use smo::padding::{Padding, PADDED_LEN_MAX};
+
+fn key() -> LessSafeKey {
+    LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &[7u8; 32]).unwrap())
+}
+
+#[test]
+fn parses_policies() {
+    assert_eq!("buckets:256, 512,1024".parse(), Ok(Padding::Buckets(vec![256, 512, 1024])));
+    assert_eq!("mtu:1400".parse(), Ok(Padding::Mtu(1400)));
+    assert_eq!("random:64".parse(), Ok(Padding::Random(64)));
+
+    assert!("buckets".parse::<Padding>().is_err());
+    assert!("fixed:10".parse::<Padding>().is_err());
+    assert!("mtu:large".parse::<Padding>().is_err());
+
+    // padded datagrams have to fit the receive buffers.
+    assert_eq!(format!("mtu:{PADDED_LEN_MAX}").parse(), Ok(Padding::Mtu(PADDED_LEN_MAX)));
+    assert!(format!("mtu:{}", PADDED_LEN_MAX + 1).parse::<Padding>().is_err());
+    assert!("buckets:512,4096".parse::<Padding>().is_err());
+    assert!("random:65535".parse::<Padding>().is_err());
+}
+
+#[test]
+fn pads_to_policy_lengths() {
+    let buckets = Padding::Buckets(vec![512, 256]);
+    assert_eq!(buckets.pad_len(100), 156);
+    assert_eq!(buckets.pad_len(256), 0);
+    assert_eq!(buckets.pad_len(300), 212);
+    assert_eq!(buckets.pad_len(600), 424);
+
+    assert_eq!(Padding::Mtu(1400).pad_len(100), 1300);
+    assert_eq!(Padding::Mtu(1400).pad_len(1500), 0);
+
+    assert!((0..100).all(|_| Padding::Random(16).pad_len(100) <= 16));
+
+    // beyond the largest bucket, multiples of it stop at the receive buffers.
+    assert_eq!(Padding::Buckets(vec![1024]).pad_len(1100), PADDED_LEN_MAX - 1100);
+    assert_eq!(Padding::Mtu(1400).pad_len(PADDED_LEN_MAX + 10), 0);
+    assert!((0..100).all(|_| Padding::Random(1000).pad_len(1500) <= PADDED_LEN_MAX - 1500));
+}
+
+#[test]
+fn sealed_messages_are_padded_and_stripped() {
+    let mut packet = PacketEncoder::new().with_padding(Some(Padding::Buckets(vec![256])));
+    packet.write_opcode(MessageType::Trace);
+    packet.write_string(b"hello");
+
+    let sealed = packet.to_bytes_with_aad(Some(key()), b"header");
+    assert_eq!(sealed.len(), 256);
+
+    let mut packet = PacketDecoder::open_padded(&sealed, &key(), b"header", true).unwrap();
+    assert_eq!(packet.read_opcode(), MessageType::Trace);
+    assert_eq!(packet.try_read_string().unwrap(), b"hello");
+    assert_eq!(packet.remaining(), 0);
+
+    let packet = PacketDecoder::open_padded(&PacketEncoder::new()
+        .with_padding(Some(Padding::Mtu(300)))
+        .to_bytes(Some(key())), &key(), &[], true).unwrap();
+    assert_eq!(packet.remaining(), 0);
+}
+
+#[test]
+fn padding_is_only_stripped_when_negotiated() {
+    // an unpadded message that happens to start like a padding header stays intact.
+    let mut packet = PacketEncoder::new();
+    packet.write_u32(u32::MAX);
+    packet.write_string(b"hello");
+    let sealed = packet.to_bytes(Some(key()));
+
+    let mut packet = PacketDecoder::open(&sealed, &key(), &[]).unwrap();
+    assert_eq!(packet.try_read_uint32().unwrap(), u32::MAX);
+    assert_eq!(packet.try_read_string().unwrap(), b"hello");
+
+    // a padded message whose pad length runs past its end does not open.
+    let mut packet = PacketEncoder::new();
+    packet.write_u16(64);
+    assert!(PacketDecoder::open_padded(&packet.to_bytes(Some(key())), &key(), &[], true).is_none());
+}
+
+#[test]
+fn unpadded_and_unsealed_messages_are_unchanged() {
+    let mut packet = PacketEncoder::new();
+    packet.write_string(b"hello");
+    let sealed = packet.to_bytes(Some(key()));
+
+    assert_eq!(PacketDecoder::open(&sealed, &key(), &[]).unwrap().try_read_string().unwrap(), b"hello");
+
+    let mut packet = PacketEncoder::new().with_padding(Some(Padding::Mtu(1400)));
+    packet.write_opcode(MessageType::Sign);
+
+    assert_eq!(packet.to_bytes(None), vec![u8::from(MessageType::Sign)]);
+}
@@ -0,0 +1,57 @@
+use smo::replay_window::ReplayWindow;
+
+#[test]
+fn accepts_increasing_counters() {
+    let mut replay_window = ReplayWindow::new();
+
+    for counter in 0..200 {
+        assert!(replay_window.accept(counter));
+    }
+}
+
+#[test]
+fn rejects_duplicates() {
+    let mut replay_window = ReplayWindow::new();
+
+    assert!(replay_window.accept(0));
+    assert!(!replay_window.accept(0));
+
+    assert!(replay_window.accept(5));
+    assert!(!replay_window.accept(5));
+
+    // late but unseen counters still pass, once.
+    assert!(replay_window.accept(3));
+    assert!(!replay_window.accept(3));
+}
+
+#[test]
+fn slides_with_the_highest_counter() {
+    let mut replay_window = ReplayWindow::new();
+
+    assert!(replay_window.accept(10));
+    assert!(replay_window.accept(100));
+
+    // 10 fell out of the window when it slid to 100.
+    assert!(!replay_window.accept(10));
+    assert!(replay_window.accept(37));
+    assert!(!replay_window.accept(37));
+    assert!(!replay_window.accept(100));
+
+    // a jump past the window width forgets every earlier counter.
+    assert!(replay_window.accept(1000));
+    assert!(replay_window.accept(999));
+    assert!(!replay_window.accept(100));
+}
+
+#[test]
+fn rejects_too_old_counters() {
+    let mut replay_window = ReplayWindow::new();
+
+    assert!(replay_window.accept(64));
+    assert!(replay_window.accept(1));
+    assert!(!replay_window.accept(0));
+
+    assert!(replay_window.accept(65));
+    assert!(!replay_window.accept(1));
+    assert!(replay_window.accept(2));
+}
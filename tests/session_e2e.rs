@@ -0,0 +1,905 @@
+mod common;
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use jsonwebtoken::Algorithm;
+use smo::cipher_suite::CipherSuite;
+use smo::client::{Client, TunnelFactory};
+use smo::data_frame::{frame, next_frame};
+use smo::message_type::MessageType;
+use smo::packet_decoder::PacketDecoder;
+use smo::obfuscation::Obfuscation;
+use smo::packet_encoder::PacketEncoder;
+use smo::padding::Padding;
+use smo::protocol::{Capabilities, PROTOCOL_VERSION_MAX, PROTOCOL_VERSION_MIN};
+use smo::server::Server;
+use smo::session::Session;
+use smo::session_claims::SessionClaims;
+use smo::session_signer::SessionSigner;
+use smo::session_ticket::SessionTickets;
+use smo::session_verifier::SessionVerifier;
+use smo::tls_listener::TlsListener;
+use smo::tunnel_config::TunnelConfig;
+use smo::tunnel_device::TunnelDevice;
+use smo::tunnel_memory::{MemoryTunnel, MemoryTunnelPeer};
+use smo::user::User;
+use smo::user_store::UserStore;
+use smo::user_store_memory::MemoryUserStore;
+use smo::websocket::{load_roots, WebSocketConnector};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use common::ipv4_packet;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+const COVER_TRAFFIC: Duration = Duration::from_millis(20);
+const OBFUSCATION_KEY: &[u8] = b"session-e2e-obfuscation";
+const JWT_SHARED_SECRET: &[u8] = b"session-e2e-secret";
+const REVOCATION_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const RESUMPTION_TICKET_KEY: &[u8] = b"session-e2e-tickets";
+
+/// Signs tokens the test servers accept, configured in code rather than
+/// through the environment the tests running in parallel share.
+fn signer() -> SessionSigner {
+    SessionSigner::from_secret(Algorithm::HS512, JWT_SHARED_SECRET)
+}
+
+async fn next(peer: &mut MemoryTunnelPeer) -> Vec<u8> {
+    tokio::time::timeout(TIMEOUT, peer.next())
+        .await
+        .expect("tunnel packet timed out")
+        .expect("tunnel closed")
+}
+
+/// Relays a client on one port: control connections unchanged, client
+/// datagrams to the server `copies` times and server datagrams back. The
+/// first client message of each control connection is recorded.
+async fn spawn_relay(
+    server_addr: SocketAddr,
+    copies: usize,
+    rewrite: fn(Vec<u8>) -> Vec<u8>,
+) -> (SocketAddr, Arc<Mutex<Vec<Vec<u8>>>>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let relay_addr = listener.local_addr().unwrap();
+    let udp_socket = tokio::net::UdpSocket::bind(relay_addr).await.unwrap();
+    let first_messages = Arc::new(Mutex::new(Vec::new()));
+    let recorded = first_messages.clone();
+
+    tokio::spawn(async move {
+        while let Ok((mut client, _)) = listener.accept().await {
+            let mut server = TcpStream::connect(server_addr).await.unwrap();
+            let recorded = recorded.clone();
+
+            tokio::spawn(async move {
+                // the client waits for the reply to its first message.
+                let mut buf = [0u8; 2048];
+                let n = client.read(&mut buf).await.unwrap();
+                recorded.lock().unwrap().push(buf[..n].to_vec());
+
+                server.write_all(&rewrite(buf[..n].to_vec())).await.unwrap();
+                tokio::io::copy_bidirectional(&mut client, &mut server).await
+            });
+        }
+    });
+
+    tokio::spawn(async move {
+        let mut client_addr = None;
+        let mut buf = [0u8; 2048];
+
+        while let Ok((n, sock_addr)) = udp_socket.recv_from(&mut buf).await {
+            if sock_addr != server_addr {
+                client_addr = Some(sock_addr);
+
+                for _ in 0..copies {
+                    udp_socket.send_to(&buf[..n], server_addr).await.unwrap();
+                }
+            } else if let Some(client_addr) = client_addr {
+                udp_socket.send_to(&buf[..n], client_addr).await.unwrap();
+            }
+        }
+    });
+
+    (relay_addr, first_messages)
+}
+
+/// Datagram side of a client that roams: its datagrams reach the server from
+/// `source`, which a test swaps for a new socket, and come back from whichever
+/// socket the server answers.
+struct RoamingRelay {
+    source: Mutex<Arc<tokio::net::UdpSocket>>,
+    sent: Mutex<Vec<Vec<u8>>>,
+    answered_at: Mutex<Vec<SocketAddr>>,
+    client_side: tokio::net::UdpSocket,
+    client_addr: Mutex<Option<SocketAddr>>,
+}
+
+impl RoamingRelay {
+    /// Client datagrams to the server from the current source socket.
+    async fn forward(self: Arc<Self>, server_addr: SocketAddr) {
+        let mut buf = [0u8; 2048];
+
+        while let Ok((n, sock_addr)) = self.client_side.recv_from(&mut buf).await {
+            *self.client_addr.lock().unwrap() = Some(sock_addr);
+            self.sent.lock().unwrap().push(buf[..n].to_vec());
+
+            let source = self.source.lock().unwrap().clone();
+            source.send_to(&buf[..n], server_addr).await.unwrap();
+        }
+    }
+
+    /// Moves the client to a fresh source address, returned.
+    async fn roam(self: &Arc<Self>) -> SocketAddr {
+        let source = Arc::new(tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        let source_addr = source.local_addr().unwrap();
+        let relay = self.clone();
+        let answers = source.clone();
+
+        tokio::spawn(async move {
+            let mut buf = [0u8; 2048];
+
+            while let Ok((n, _)) = answers.recv_from(&mut buf).await {
+                relay.answered_at.lock().unwrap().push(answers.local_addr().unwrap());
+
+                let client_addr = *relay.client_addr.lock().unwrap();
+                if let Some(client_addr) = client_addr {
+                    relay.client_side.send_to(&buf[..n], client_addr).await.unwrap();
+                }
+            }
+        });
+
+        *self.source.lock().unwrap() = source;
+        source_addr
+    }
+
+    fn last_sent(&self) -> Vec<u8> {
+        self.sent.lock().unwrap().last().unwrap().clone()
+    }
+
+    fn last_answered_at(&self) -> SocketAddr {
+        *self.answered_at.lock().unwrap().last().unwrap()
+    }
+}
+
+/// Relays a client whose datagrams can be moved to a new source address,
+/// control connections go through unchanged.
+async fn spawn_roaming_relay(server_addr: SocketAddr) -> (SocketAddr, Arc<RoamingRelay>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let relay_addr = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((mut client, _)) = listener.accept().await {
+            let mut server = TcpStream::connect(server_addr).await.unwrap();
+            tokio::spawn(async move { tokio::io::copy_bidirectional(&mut client, &mut server).await });
+        }
+    });
+
+    let relay = Arc::new(RoamingRelay {
+        source: Mutex::new(Arc::new(tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap())),
+        sent: Mutex::new(Vec::new()),
+        answered_at: Mutex::new(Vec::new()),
+        client_side: tokio::net::UdpSocket::bind(relay_addr).await.unwrap(),
+        client_addr: Mutex::new(None),
+    });
+
+    relay.roam().await;
+    tokio::spawn(relay.clone().forward(server_addr));
+
+    (relay_addr, relay)
+}
+
+/// How the client carries its data packets.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Transport {
+    Udp,
+    Tcp,
+    WebSocket,
+}
+
+/// Fake user store with alice (10.8.0.2).
+fn alice_store() -> Arc<MemoryUserStore> {
+    Arc::new(MemoryUserStore::new(vec![
+        User::new(1, "alice", Ipv4Addr::new(10, 8, 0, 2), true),
+    ]))
+}
+
+/// Server with alice in a fake user store and a memory tunnel.
+async fn spawn_server() -> (SocketAddr, MemoryTunnelPeer) {
+    spawn_server_with(None, None, None).await
+}
+
+async fn spawn_server_with(tls: Option<TlsListener>, obfuscation: Option<Obfuscation>, padding: Option<Padding>) -> (SocketAddr, MemoryTunnelPeer) {
+    spawn_server_for(alice_store(), tls, obfuscation, padding).await
+}
+
+async fn spawn_server_for(
+    user_store: Arc<MemoryUserStore>,
+    tls: Option<TlsListener>,
+    obfuscation: Option<Obfuscation>,
+    padding: Option<Padding>,
+) -> (SocketAddr, MemoryTunnelPeer) {
+    spawn_sessions(sessions(user_store, padding), tls, obfuscation).await
+}
+
+/// Sessions of the test servers, signing in with `signer` tokens.
+fn sessions(user_store: Arc<MemoryUserStore>, padding: Option<Padding>) -> Session {
+    let tunnel_config = TunnelConfig::new(Ipv4Addr::new(10, 8, 0, 1), Ipv4Addr::new(255, 255, 255, 0), 1400);
+    let verifier = SessionVerifier::from_secret(Algorithm::HS512, JWT_SHARED_SECRET);
+
+    Session::with_verifier(user_store, tunnel_config, verifier)
+        .with_revocation_interval(REVOCATION_REFRESH_INTERVAL)
+        .with_cover_traffic(padding.as_ref().map(|_| COVER_TRAFFIC))
+        .with_padding(padding)
+}
+
+async fn spawn_sessions(sessions: Session, tls: Option<TlsListener>, obfuscation: Option<Obfuscation>) -> (SocketAddr, MemoryTunnelPeer) {
+    let (server_tunnel, server_peer) = MemoryTunnel::pair();
+    let server = Server::bind("127.0.0.1:0", Arc::new(sessions), Arc::new(server_tunnel))
+        .await
+        .unwrap();
+
+    let server = match tls {
+        Some(tls) => server.with_tls(tls),
+        None => server,
+    };
+
+    let server = match obfuscation {
+        Some(obfuscation) => server.with_obfuscation(obfuscation),
+        None => server,
+    };
+
+    let server_addr = server.local_addr().unwrap();
+    tokio::spawn(async move { server.serve().await });
+
+    (server_addr, server_peer)
+}
+
+/// Tunnel factory handing the peer of the client's memory tunnel to `tunnel_peer`.
+fn memory_tunnel_factory() -> (TunnelFactory, Arc<Mutex<Option<MemoryTunnelPeer>>>) {
+    let client_peer = Arc::new(Mutex::new(None));
+    let factory_peer = client_peer.clone();
+
+    let tunnel_factory: TunnelFactory = Box::new(move |_| {
+        let (tunnel, peer) = MemoryTunnel::pair();
+        *factory_peer.lock().unwrap() = Some(peer);
+
+        Arc::new(tunnel) as Arc<dyn TunnelDevice>
+    });
+
+    (tunnel_factory, client_peer)
+}
+
+/// Waits for the client to create its tunnel.
+async fn tunnel_peer(client_peer: &Mutex<Option<MemoryTunnelPeer>>) -> MemoryTunnelPeer {
+    loop {
+        if let Some(peer) = client_peer.lock().unwrap().take() {
+            return peer;
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+/// TLS listener with the test certificate for `localhost`.
+async fn tls_listener() -> TlsListener {
+    TlsListener::bind(
+        "127.0.0.1:0",
+        "tests/fixtures/tls/cert.pem",
+        "tests/fixtures/tls/key.pem",
+        "/smo",
+    ).await.unwrap()
+}
+
+/// Signs alice in with the given cipher suite and moves a packet each way,
+/// over UDP, framed on the control connection or through the TLS listener,
+/// optionally obfuscated with a shared key and padded with cover traffic.
+async fn round_trip(cipher: CipherSuite, transport: Transport, obfuscated: bool, padding: Option<Padding>) {
+    let alice_address = Ipv4Addr::new(10, 8, 0, 2);
+
+    let (tls, websocket) = match transport {
+        Transport::WebSocket => {
+            let tls = tls_listener().await;
+            let url = format!("wss://localhost:{}/smo", tls.local_addr().unwrap().port());
+            let roots = load_roots("tests/fixtures/tls/ca.pem").unwrap();
+
+            (Some(tls), Some(WebSocketConnector::new(&url, roots).unwrap()))
+        }
+        _ => (None, None),
+    };
+
+    let obfuscation = obfuscated.then(|| Obfuscation::new(OBFUSCATION_KEY));
+    let (server_addr, mut server_peer) = spawn_server_with(tls, obfuscation.clone(), padding.clone()).await;
+
+    let access_token = signer()
+        .sign(&mut SessionClaims::new(1, "alice"))
+        .unwrap();
+
+    let (tunnel_factory, client_peer) = memory_tunnel_factory();
+
+    let udp_socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut client = Client::new(&server_addr.to_string(), &access_token, udp_socket)
+        .with_cipher(cipher)
+        .with_tcp_data(transport == Transport::Tcp)
+        .with_websocket(websocket)
+        .with_obfuscation(obfuscation)
+        .with_cover_traffic(padding.as_ref().map(|_| COVER_TRAFFIC))
+        .with_padding(padding.clone())
+        .with_tunnel_factory(tunnel_factory);
+
+    let session = tokio::time::timeout(TIMEOUT, client.connect())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(session.config.address, alice_address);
+    assert_eq!(session.protocol_version, PROTOCOL_VERSION_MAX);
+    let mut capabilities = Capabilities::RESUMPTION | cipher.capability();
+
+    if padding.is_some() {
+        capabilities = capabilities | Capabilities::PADDING;
+    }
+
+    if transport != Transport::Udp {
+        capabilities = capabilities | Capabilities::TCP_DATA;
+    }
+
+    assert_eq!(session.capabilities, capabilities);
+    assert_eq!(session.cipher, cipher);
+
+    tokio::spawn(async move { client.transmit(session).await });
+
+    let mut client_peer = tunnel_peer(&client_peer).await;
+
+    let outbound = ipv4_packet(alice_address, Ipv4Addr::new(1, 1, 1, 1), 17, b"ping");
+    client_peer.inject(&outbound).await.unwrap();
+    assert_eq!(next(&mut server_peer).await, outbound);
+
+    let inbound = ipv4_packet(Ipv4Addr::new(1, 1, 1, 1), alice_address, 17, b"pong");
+    server_peer.inject(&inbound).await.unwrap();
+    assert_eq!(next(&mut client_peer).await, inbound);
+
+    if padding.is_some() {
+        // idle intervals pass with cover packets only, none of them reach a tunnel.
+        tokio::time::sleep(COVER_TRAFFIC * 5).await;
+
+        client_peer.inject(&outbound).await.unwrap();
+        assert_eq!(next(&mut server_peer).await, outbound);
+
+        server_peer.inject(&inbound).await.unwrap();
+        assert_eq!(next(&mut client_peer).await, inbound);
+    }
+}
+
+#[tokio::test]
+async fn data_plane_round_trip_aes_256_gcm() {
+    round_trip(CipherSuite::Aes256Gcm, Transport::Udp, false, None).await;
+}
+
+#[tokio::test]
+async fn data_plane_round_trip_chacha20_poly1305() {
+    round_trip(CipherSuite::ChaCha20Poly1305, Transport::Udp, false, None).await;
+}
+
+#[tokio::test]
+async fn data_plane_round_trip_over_tcp() {
+    round_trip(CipherSuite::ChaCha20Poly1305, Transport::Tcp, false, None).await;
+}
+
+#[tokio::test]
+async fn data_plane_round_trip_over_websocket() {
+    round_trip(CipherSuite::ChaCha20Poly1305, Transport::WebSocket, false, None).await;
+}
+
+#[tokio::test]
+async fn data_plane_round_trip_obfuscated() {
+    round_trip(CipherSuite::Aes256Gcm, Transport::Udp, true, None).await;
+}
+
+#[tokio::test]
+async fn data_plane_round_trip_obfuscated_over_tcp() {
+    round_trip(CipherSuite::ChaCha20Poly1305, Transport::Tcp, true, None).await;
+}
+
+#[tokio::test]
+async fn data_plane_round_trip_padded() {
+    round_trip(CipherSuite::Aes256Gcm, Transport::Udp, false, Some(Padding::Buckets(vec![256, 512, 1500]))).await;
+}
+
+#[tokio::test]
+async fn data_plane_round_trip_padded_over_tcp() {
+    round_trip(CipherSuite::ChaCha20Poly1305, Transport::Tcp, true, Some(Padding::Random(64))).await;
+}
+
+#[tokio::test]
+async fn obfuscated_server_ignores_plain_sign() {
+    let (server_addr, _server_peer) = spawn_server_with(None, Some(Obfuscation::new(OBFUSCATION_KEY)), None).await;
+    let mut control = TcpStream::connect(server_addr).await.unwrap();
+
+    let mut packet = PacketEncoder::new();
+    packet.write_opcode(MessageType::Sign);
+    packet.write_string(&[0u8; 32]);
+    packet.write_u16(PROTOCOL_VERSION_MAX);
+    packet.write_u32(Capabilities::supported().bits());
+
+    control.write_all(&packet.to_bytes(None)).await.unwrap();
+
+    let mut buf = [0u8; 2048];
+    let answered = tokio::time::timeout(Duration::from_millis(500), control.read(&mut buf)).await;
+
+    assert!(!matches!(answered, Ok(Ok(n)) if n > 0));
+}
+
+#[tokio::test]
+async fn websocket_upgrade_on_other_path_is_refused() {
+    let tls = tls_listener().await;
+    let url = format!("wss://localhost:{}/other", tls.local_addr().unwrap().port());
+    let roots = load_roots("tests/fixtures/tls/ca.pem").unwrap();
+    spawn_server_with(Some(tls), None, None).await;
+
+    let connector = WebSocketConnector::new(&url, roots).unwrap();
+    let refused = tokio::time::timeout(TIMEOUT, connector.connect()).await.unwrap();
+
+    assert!(refused.is_err());
+}
+
+#[tokio::test]
+async fn sign_rejects_unsupported_version() {
+    let (server_addr, _server_peer) = spawn_server().await;
+    let mut control = TcpStream::connect(server_addr).await.unwrap();
+
+    let mut packet = PacketEncoder::new();
+    packet.write_opcode(MessageType::Sign);
+    packet.write_string(&[0u8; 32]);
+    packet.write_u16(PROTOCOL_VERSION_MIN - 1);
+    packet.write_u32(Capabilities::supported().bits());
+
+    control.write_all(&packet.to_bytes(None)).await.unwrap();
+
+    let mut buf = [0u8; 2048];
+    let n = tokio::time::timeout(TIMEOUT, control.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut packet = PacketDecoder::new(&buf[..n]);
+    assert_eq!(packet.read_opcode(), MessageType::SignReject);
+
+    let _reason = packet.try_read_string().unwrap();
+    assert_eq!(packet.try_read_uint16().unwrap(), PROTOCOL_VERSION_MIN);
+    assert_eq!(packet.try_read_uint16().unwrap(), PROTOCOL_VERSION_MAX);
+}
+
+#[tokio::test]
+async fn replayed_datagram_is_dropped() {
+    let alice_address = Ipv4Addr::new(10, 8, 0, 2);
+    let (server_addr, mut server_peer) = spawn_server().await;
+
+    // every client datagram reaches the server twice.
+    let (relay_addr, _) = spawn_relay(server_addr, 2, |first| first).await;
+
+    let access_token = signer()
+        .sign(&mut SessionClaims::new(1, "alice"))
+        .unwrap();
+
+    let (tunnel_factory, client_peer) = memory_tunnel_factory();
+
+    let udp_socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut client = Client::new(&relay_addr.to_string(), &access_token, udp_socket)
+        .with_tunnel_factory(tunnel_factory);
+
+    let session = tokio::time::timeout(TIMEOUT, client.connect())
+        .await
+        .unwrap()
+        .unwrap();
+
+    tokio::spawn(async move { client.transmit(session).await });
+
+    let client_peer = tunnel_peer(&client_peer).await;
+
+    let first = ipv4_packet(alice_address, Ipv4Addr::new(1, 1, 1, 1), 17, b"first");
+    client_peer.inject(&first).await.unwrap();
+    assert_eq!(next(&mut server_peer).await, first);
+
+    // the copy of the first packet arrived in between and was dropped.
+    let second = ipv4_packet(alice_address, Ipv4Addr::new(1, 1, 1, 1), 17, b"second");
+    client_peer.inject(&second).await.unwrap();
+    assert_eq!(next(&mut server_peer).await, second);
+}
+
+#[tokio::test]
+async fn authenticated_datagrams_move_the_session_endpoint() {
+    let alice_address = Ipv4Addr::new(10, 8, 0, 2);
+    let (server_addr, mut server_peer) = spawn_server().await;
+    let (relay_addr, relay) = spawn_roaming_relay(server_addr).await;
+
+    let access_token = signer()
+        .sign(&mut SessionClaims::new(1, "alice"))
+        .unwrap();
+
+    let udp_socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (tunnel_factory, client_peer) = memory_tunnel_factory();
+    let mut client = Client::new(&relay_addr.to_string(), &access_token, udp_socket)
+        .with_tunnel_factory(tunnel_factory);
+
+    let session = tokio::time::timeout(TIMEOUT, client.connect())
+        .await
+        .unwrap()
+        .unwrap();
+
+    tokio::spawn(async move { client.transmit(session).await });
+    let mut client_peer = tunnel_peer(&client_peer).await;
+
+    let outbound = ipv4_packet(alice_address, Ipv4Addr::new(1, 1, 1, 1), 17, b"out");
+    let inbound = ipv4_packet(Ipv4Addr::new(1, 1, 1, 1), alice_address, 17, b"back");
+
+    client_peer.inject(&outbound).await.unwrap();
+    assert_eq!(next(&mut server_peer).await, outbound);
+
+    server_peer.inject(&inbound).await.unwrap();
+    assert_eq!(next(&mut client_peer).await, inbound);
+    let first_addr = relay.last_answered_at();
+
+    // the client switches networks, its next datagram arrives from a new address.
+    let roamed_addr = relay.roam().await;
+    assert_ne!(roamed_addr, first_addr);
+
+    client_peer.inject(&outbound).await.unwrap();
+    assert_eq!(next(&mut server_peer).await, outbound);
+
+    server_peer.inject(&inbound).await.unwrap();
+    assert_eq!(next(&mut client_peer).await, inbound);
+    assert_eq!(relay.last_answered_at(), roamed_addr);
+
+    // a replayed and a forged datagram from a third address leave the endpoint alone.
+    let attacker = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let captured = relay.last_sent();
+    let mut forged = captured.clone();
+    *forged.last_mut().unwrap() ^= 1;
+
+    attacker.send_to(&captured, server_addr).await.unwrap();
+    attacker.send_to(&forged, server_addr).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    server_peer.inject(&inbound).await.unwrap();
+    assert_eq!(next(&mut client_peer).await, inbound);
+    assert_eq!(relay.last_answered_at(), roamed_addr);
+
+    let mut buf = [0u8; 2048];
+    let stolen = tokio::time::timeout(Duration::from_millis(200), attacker.recv_from(&mut buf)).await;
+    assert!(stolen.is_err(), "tunnel traffic went to the attacker");
+}
+
+#[tokio::test]
+async fn revoked_token_terminates_live_session() {
+    let user_store = alice_store();
+    let (server_addr, _server_peer) = spawn_server_for(user_store.clone(), None, None, None).await;
+
+    let mut claims = SessionClaims::new(1, "alice");
+    let access_token = signer().sign(&mut claims).unwrap();
+
+    let udp_socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (tunnel_factory, _client_peer) = memory_tunnel_factory();
+    let mut client = Client::new(&server_addr.to_string(), &access_token, udp_socket)
+        .with_tunnel_factory(tunnel_factory);
+
+    let session = tokio::time::timeout(TIMEOUT, client.connect())
+        .await
+        .unwrap()
+        .unwrap();
+
+    user_store.revoke(claims.jti().unwrap(), None).await;
+
+    // the server closes the control connection at its next deny list refresh.
+    let transmitted = tokio::time::timeout(TIMEOUT, client.transmit(session))
+        .await
+        .expect("revoked session kept running");
+
+    assert!(transmitted.is_err());
+
+    // neither the resumption ticket nor the token get a new session.
+    let reconnected = tokio::time::timeout(TIMEOUT, client.connect()).await.unwrap();
+    assert!(reconnected.is_err());
+}
+
+#[tokio::test]
+async fn resumption_ticket_is_single_use() {
+    let alice_address = Ipv4Addr::new(10, 8, 0, 2);
+    let (server_addr, mut server_peer) = spawn_server().await;
+    let (relay_addr, first_messages) = spawn_relay(server_addr, 1, |first| first).await;
+
+    let access_token = signer()
+        .sign(&mut SessionClaims::new(1, "alice"))
+        .unwrap();
+
+    let udp_socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (tunnel_factory, client_peer) = memory_tunnel_factory();
+    let mut client = Client::new(&relay_addr.to_string(), &access_token, udp_socket)
+        .with_tunnel_factory(tunnel_factory);
+
+    let signed = tokio::time::timeout(TIMEOUT, client.connect())
+        .await
+        .unwrap()
+        .unwrap();
+
+    // the control connection drops, the client comes back with its ticket.
+    drop(signed);
+
+    let resumed = tokio::time::timeout(TIMEOUT, client.connect())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let resume = first_messages.lock().unwrap()[1].clone();
+    let resume_message = next_frame(&mut resume.clone()).unwrap();
+    assert_eq!(PacketDecoder::new(&resume_message).read_opcode(), MessageType::Resume);
+    assert_eq!(resumed.config.address, alice_address);
+
+    tokio::spawn(async move { client.transmit(resumed).await });
+    let client_peer = tunnel_peer(&client_peer).await;
+
+    let outbound = ipv4_packet(alice_address, Ipv4Addr::new(1, 1, 1, 1), 17, b"resumed");
+    client_peer.inject(&outbound).await.unwrap();
+    assert_eq!(next(&mut server_peer).await, outbound);
+
+    // the captured Resume, replayed, is refused.
+    let mut control = TcpStream::connect(server_addr).await.unwrap();
+    control.write_all(&resume).await.unwrap();
+
+    let mut buf = [0u8; 2048];
+    let n = tokio::time::timeout(TIMEOUT, control.read(&mut buf))
+        .await
+        .unwrap()
+        .unwrap_or(0);
+
+    assert_eq!(n, 0);
+}
+
+#[tokio::test]
+async fn disabled_user_cannot_resume() {
+    let user_store = alice_store();
+    let (server_addr, _server_peer) = spawn_server_for(user_store.clone(), None, None, None).await;
+    let (relay_addr, first_messages) = spawn_relay(server_addr, 1, |first| first).await;
+
+    let access_token = signer()
+        .sign(&mut SessionClaims::new(1, "alice"))
+        .unwrap();
+
+    let udp_socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (tunnel_factory, _client_peer) = memory_tunnel_factory();
+    let mut client = Client::new(&relay_addr.to_string(), &access_token, udp_socket)
+        .with_tunnel_factory(tunnel_factory);
+
+    let signed = tokio::time::timeout(TIMEOUT, client.connect())
+        .await
+        .unwrap()
+        .unwrap();
+
+    // resumption reads the user from the ticket, the disabled flag from the next refresh.
+    user_store.set_enabled(1, false).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    drop(signed);
+
+    let reconnected = tokio::time::timeout(TIMEOUT, client.connect()).await.unwrap();
+    assert!(reconnected.is_err());
+
+    // the Resume was refused, the client fell back to a Sign that was refused as well.
+    let first_messages = first_messages.lock().unwrap();
+    let opcodes = first_messages.iter()
+        .map(|first| PacketDecoder::new(&next_frame(&mut first.clone()).unwrap()).read_opcode())
+        .collect::<Vec<_>>();
+
+    assert_eq!(opcodes, vec![MessageType::Sign, MessageType::Resume, MessageType::Sign]);
+}
+
+#[tokio::test]
+async fn framed_sign_split_across_writes_is_reassembled() {
+    let (server_addr, _server_peer) = spawn_server().await;
+    let mut control = TcpStream::connect(server_addr).await.unwrap();
+
+    let mut packet = PacketEncoder::new();
+    packet.write_opcode(MessageType::Sign);
+    packet.write_string(&[9u8; 32]);
+    packet.write_u16(PROTOCOL_VERSION_MAX);
+    packet.write_u32(Capabilities::supported().bits());
+
+    let sign = frame(&packet.to_bytes(None));
+    let (head, tail) = sign.split_at(10);
+
+    control.write_all(head).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    control.write_all(tail).await.unwrap();
+
+    let mut received = Vec::new();
+    let reply = loop {
+        if let Some(reply) = next_frame(&mut received) {
+            break reply;
+        }
+
+        let mut buf = [0u8; 2048];
+        let n = tokio::time::timeout(TIMEOUT, control.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(n > 0, "server closed the connection");
+        received.extend_from_slice(&buf[..n]);
+    };
+
+    let mut packet = PacketDecoder::new(&reply);
+    assert_eq!(packet.read_opcode(), MessageType::SignWaitApprove);
+}
+
+/// Rewrites a framed `Sign` so it no longer offers ChaCha20-Poly1305.
+fn strip_chacha20(mut sign: Vec<u8>) -> Vec<u8> {
+    let message = next_frame(&mut sign).unwrap();
+    let mut packet = PacketDecoder::new(&message);
+    assert_eq!(packet.read_opcode(), MessageType::Sign);
+
+    let public_key = packet.try_read_string().unwrap();
+    let version = packet.try_read_uint16().unwrap();
+    let capabilities = packet.try_read_uint32().unwrap() & !Capabilities::CHACHA20_POLY1305.bits();
+
+    let mut packet = PacketEncoder::new();
+    packet.write_opcode(MessageType::Sign);
+    packet.write_string(&public_key);
+    packet.write_u16(version);
+    packet.write_u32(capabilities);
+
+    frame(&packet.to_bytes(None))
+}
+
+#[tokio::test]
+async fn tampered_sign_negotiation_is_refused() {
+    let (server_addr, _server_peer) = spawn_server().await;
+    let (relay_addr, _) = spawn_relay(server_addr, 1, strip_chacha20).await;
+
+    let access_token = signer()
+        .sign(&mut SessionClaims::new(1, "alice"))
+        .unwrap();
+
+    let udp_socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (tunnel_factory, _client_peer) = memory_tunnel_factory();
+    let mut client = Client::new(&relay_addr.to_string(), &access_token, udp_socket)
+        .with_tunnel_factory(tunnel_factory)
+        .with_cipher(CipherSuite::ChaCha20Poly1305);
+
+    // both sides would agree on AES-256-GCM, but the sealed echo gives the downgrade away.
+    let signed = tokio::time::timeout(TIMEOUT, client.connect()).await.unwrap();
+    assert!(signed.is_err());
+}
+
+/// Client signed in as alice, with `padding`, and its tunnel peer.
+async fn padded_client(server_addr: SocketAddr, obfuscation: Option<Obfuscation>, padding: Option<Padding>) -> (Capabilities, MemoryTunnelPeer) {
+    let access_token = signer()
+        .sign(&mut SessionClaims::new(1, "alice"))
+        .unwrap();
+
+    let udp_socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (tunnel_factory, client_peer) = memory_tunnel_factory();
+    let mut client = Client::new(&server_addr.to_string(), &access_token, udp_socket)
+        .with_obfuscation(obfuscation)
+        .with_padding(padding)
+        .with_tunnel_factory(tunnel_factory);
+
+    let session = tokio::time::timeout(TIMEOUT, client.connect())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let capabilities = session.capabilities;
+    tokio::spawn(async move { client.transmit(session).await });
+
+    (capabilities, tunnel_peer(&client_peer).await)
+}
+
+#[tokio::test]
+async fn padding_needs_a_policy_on_both_ends() {
+    let alice_address = Ipv4Addr::new(10, 8, 0, 2);
+    let (server_addr, mut server_peer) = spawn_server().await;
+
+    let (capabilities, client_peer) = padded_client(server_addr, None, Some(Padding::Random(64))).await;
+    assert!(!capabilities.contains(Capabilities::PADDING));
+
+    let outbound = ipv4_packet(alice_address, Ipv4Addr::new(1, 1, 1, 1), 17, b"unpadded");
+    client_peer.inject(&outbound).await.unwrap();
+    assert_eq!(next(&mut server_peer).await, outbound);
+}
+
+#[tokio::test]
+async fn padded_packets_near_the_mtu_fit_the_receive_buffers() {
+    let alice_address = Ipv4Addr::new(10, 8, 0, 2);
+    let padding = Some(Padding::Buckets(vec![1024]));
+    let obfuscation = Some(Obfuscation::new(OBFUSCATION_KEY));
+    let (server_addr, mut server_peer) = spawn_server_with(None, obfuscation.clone(), padding.clone()).await;
+
+    let (capabilities, mut client_peer) = padded_client(server_addr, obfuscation, padding).await;
+    assert!(capabilities.contains(Capabilities::PADDING));
+
+    // past the largest bucket, so padding alone would double it.
+    let outbound = ipv4_packet(alice_address, Ipv4Addr::new(1, 1, 1, 1), 17, &[7u8; 1380]);
+    client_peer.inject(&outbound).await.unwrap();
+    assert_eq!(next(&mut server_peer).await, outbound);
+
+    let inbound = ipv4_packet(Ipv4Addr::new(1, 1, 1, 1), alice_address, 17, &[9u8; 1380]);
+    server_peer.inject(&inbound).await.unwrap();
+    assert_eq!(next(&mut client_peer).await, inbound);
+}
+
+/// Control connections forwarded to whichever server `target` names when they
+/// are accepted, so a client can come back to a reconfigured server.
+async fn spawn_switch(server_addr: SocketAddr) -> (SocketAddr, Arc<Mutex<SocketAddr>>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let switch_addr = listener.local_addr().unwrap();
+    let target = Arc::new(Mutex::new(server_addr));
+    let current = target.clone();
+
+    tokio::spawn(async move {
+        while let Ok((mut client, _)) = listener.accept().await {
+            let server_addr = *current.lock().unwrap();
+
+            tokio::spawn(async move {
+                let mut server = TcpStream::connect(server_addr).await.unwrap();
+                tokio::io::copy_bidirectional(&mut client, &mut server).await
+            });
+        }
+    });
+
+    (switch_addr, target)
+}
+
+#[tokio::test]
+async fn resumption_drops_capabilities_the_server_no_longer_supports() {
+    let alice_address = Ipv4Addr::new(10, 8, 0, 2);
+    let padding = Some(Padding::Buckets(vec![256, 512, 1500]));
+
+    let padded = sessions(alice_store(), padding.clone())
+        .with_tickets(SessionTickets::from_secret(RESUMPTION_TICKET_KEY));
+    let (padded_addr, _padded_peer) = spawn_sessions(padded, None, None).await;
+
+    // the same server without its padding policy, and with nobody to sign in:
+    // only the ticket brings alice back.
+    let unpadded = sessions(Arc::new(MemoryUserStore::new(Vec::new())), None)
+        .with_tickets(SessionTickets::from_secret(RESUMPTION_TICKET_KEY));
+    let (unpadded_addr, mut server_peer) = spawn_sessions(unpadded, None, None).await;
+
+    let (switch_addr, target) = spawn_switch(padded_addr).await;
+
+    let access_token = signer()
+        .sign(&mut SessionClaims::new(1, "alice"))
+        .unwrap();
+
+    let udp_socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let (tunnel_factory, client_peer) = memory_tunnel_factory();
+    let mut client = Client::new(&switch_addr.to_string(), &access_token, udp_socket)
+        .with_tcp_data(true)
+        .with_padding(padding)
+        .with_tunnel_factory(tunnel_factory);
+
+    let signed = tokio::time::timeout(TIMEOUT, client.connect())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(signed.capabilities.contains(Capabilities::PADDING));
+
+    *target.lock().unwrap() = unpadded_addr;
+    drop(signed);
+
+    let resumed = tokio::time::timeout(TIMEOUT, client.connect())
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert!(!resumed.capabilities.contains(Capabilities::PADDING));
+    assert!(resumed.capabilities.contains(Capabilities::TCP_DATA));
+
+    tokio::spawn(async move { client.transmit(resumed).await });
+    let mut client_peer = tunnel_peer(&client_peer).await;
+
+    let outbound = ipv4_packet(alice_address, Ipv4Addr::new(1, 1, 1, 1), 17, b"unpadded");
+    client_peer.inject(&outbound).await.unwrap();
+    assert_eq!(next(&mut server_peer).await, outbound);
+
+    let inbound = ipv4_packet(Ipv4Addr::new(1, 1, 1, 1), alice_address, 17, b"unpadded");
+    server_peer.inject(&inbound).await.unwrap();
+    assert_eq!(next(&mut client_peer).await, inbound);
+}
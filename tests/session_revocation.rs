@@ -0,0 +1,49 @@
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use smo::session_revocation::SessionRevocation;
+use smo::user::User;
+use smo::user_store::UserStore;
+use smo::user_store_memory::MemoryUserStore;
+use time::OffsetDateTime;
+
+#[tokio::test]
+async fn expired_entries_are_dropped_on_refresh() {
+    let user_store = Arc::new(MemoryUserStore::new(Vec::new()));
+    let revocation = SessionRevocation::new(user_store.clone());
+    let now = OffsetDateTime::now_utc().unix_timestamp();
+
+    user_store.revoke("expired", Some(now - 1)).await;
+    user_store.revoke("live", Some(now + 3600)).await;
+    user_store.revoke("forever", None).await;
+
+    assert!(!revocation.is_revoked(Some("live")).await);
+    assert!(revocation.refresh().await.unwrap());
+
+    assert!(!revocation.is_revoked(Some("expired")).await);
+    assert!(revocation.is_revoked(Some("live")).await);
+    assert!(revocation.is_revoked(Some("forever")).await);
+    assert!(!revocation.is_revoked(None).await);
+
+    // nothing changed since.
+    assert!(!revocation.refresh().await.unwrap());
+}
+
+#[tokio::test]
+async fn disabled_users_are_cached_on_refresh() {
+    let user_store = Arc::new(MemoryUserStore::new(vec![
+        User::new(1, "alice", Ipv4Addr::new(10, 8, 0, 2), true),
+        User::new(2, "bob", Ipv4Addr::new(10, 8, 0, 3), false),
+    ]));
+    let revocation = SessionRevocation::new(user_store.clone());
+
+    revocation.refresh().await.unwrap();
+    assert!(!revocation.is_disabled(1).await);
+    assert!(revocation.is_disabled(2).await);
+
+    user_store.set_enabled(1, false).await.unwrap();
+    user_store.set_enabled(2, true).await.unwrap();
+
+    revocation.refresh().await.unwrap();
+    assert!(revocation.is_disabled(1).await);
+    assert!(!revocation.is_disabled(2).await);
+}
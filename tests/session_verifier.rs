@@ -0,0 +1,151 @@
+use std::sync::Mutex;
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use serde_json::{json, Value};
+use smo::session_claims::SessionClaims;
+use smo::session_signer::SessionSigner;
+use smo::session_verifier::SessionVerifier;
+
+const SHARED_SECRET: &str = "session-verifier-secret";
+const JWT_VARIABLES: &[&str] = &[
+    "JWT_ALGORITHMS",
+    "JWT_SHARED_SECRET",
+    "JWT_PUBLIC_KEYS",
+    "JWT_JWKS_FILE",
+    "JWT_ISSUER",
+    "JWT_AUDIENCE",
+    "JWT_LEEWAY",
+];
+
+/// `SessionVerifier` reads the environment, tests configure it one at a time.
+static ENV: Mutex<()> = Mutex::new(());
+
+fn verifier(variables: &[(&str, &str)]) -> SessionVerifier {
+    let _env = ENV.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+    for variable in JWT_VARIABLES {
+        std::env::remove_var(variable);
+    }
+
+    for (variable, value) in variables {
+        std::env::set_var(variable, value);
+    }
+
+    SessionVerifier::from_env()
+}
+
+fn claims() -> Value {
+    let now = time::OffsetDateTime::now_utc().unix_timestamp();
+
+    json!({
+        "iat": now,
+        "exp": now + 3600,
+        "identifier": 1,
+        "username": "alice",
+    })
+}
+
+fn with_claim(mut claims: Value, claim: &str, value: &str) -> Value {
+    claims[claim] = json!(value);
+    claims
+}
+
+fn hmac_token(algorithm: Algorithm, claims: &Value) -> String {
+    jsonwebtoken::encode(&Header::new(algorithm), claims, &EncodingKey::from_secret(SHARED_SECRET.as_ref())).unwrap()
+}
+
+fn es256_token(key: &str, kid: Option<&str>) -> String {
+    let pem = std::fs::read(format!("tests/fixtures/jwt/{key}.key")).unwrap();
+
+    let mut header = Header::new(Algorithm::ES256);
+    header.kid = kid.map(String::from);
+
+    jsonwebtoken::encode(&header, &claims(), &EncodingKey::from_ec_pem(&pem).unwrap()).unwrap()
+}
+
+fn error_kind(result: jsonwebtoken::errors::Result<impl Sized>) -> ErrorKind {
+    match result {
+        Ok(_) => panic!("token accepted"),
+        Err(err) => err.into_kind(),
+    }
+}
+
+#[test]
+fn selects_public_key_by_kid() {
+    let verifier = verifier(&[
+        ("JWT_ALGORITHMS", "ES256"),
+        ("JWT_PUBLIC_KEYS", "a=tests/fixtures/jwt/identity-a.pem,b=tests/fixtures/jwt/identity-b.pem"),
+    ]);
+
+    assert!(verifier.verify(&es256_token("identity-b", Some("b"))).is_ok());
+
+    // without a kid every key of the algorithm is tried.
+    assert!(verifier.verify(&es256_token("identity-a", None)).is_ok());
+
+    // a kid naming another key is not tried against the rest.
+    assert_eq!(error_kind(verifier.verify(&es256_token("identity-b", Some("a")))), ErrorKind::InvalidSignature);
+    assert!(verifier.verify(&es256_token("identity-b", Some("c"))).is_err());
+}
+
+#[test]
+fn refuses_algorithms_outside_the_allow_list() {
+    let verifier = verifier(&[
+        ("JWT_ALGORITHMS", "HS512"),
+        ("JWT_SHARED_SECRET", SHARED_SECRET),
+    ]);
+
+    assert!(verifier.verify(&hmac_token(Algorithm::HS512, &claims())).is_ok());
+    assert_eq!(error_kind(verifier.verify(&hmac_token(Algorithm::HS256, &claims()))), ErrorKind::InvalidAlgorithm);
+}
+
+#[test]
+fn refuses_wrong_or_missing_issuer() {
+    let verifier = verifier(&[
+        ("JWT_SHARED_SECRET", SHARED_SECRET),
+        ("JWT_ISSUER", "https://identity.example"),
+    ]);
+
+    assert!(verifier.verify(&hmac_token(Algorithm::HS512, &with_claim(claims(), "iss", "https://identity.example"))).is_ok());
+    assert_eq!(
+        error_kind(verifier.verify(&hmac_token(Algorithm::HS512, &with_claim(claims(), "iss", "https://other.example")))),
+        ErrorKind::InvalidIssuer
+    );
+    assert_eq!(
+        error_kind(verifier.verify(&hmac_token(Algorithm::HS512, &claims()))),
+        ErrorKind::MissingRequiredClaim(String::from("iss"))
+    );
+}
+
+#[test]
+fn refuses_wrong_or_missing_audience() {
+    let verifier = verifier(&[
+        ("JWT_SHARED_SECRET", SHARED_SECRET),
+        ("JWT_AUDIENCE", "smo, smo-staging"),
+    ]);
+
+    assert!(verifier.verify(&hmac_token(Algorithm::HS512, &with_claim(claims(), "aud", "smo-staging"))).is_ok());
+    assert_eq!(
+        error_kind(verifier.verify(&hmac_token(Algorithm::HS512, &with_claim(claims(), "aud", "other")))),
+        ErrorKind::InvalidAudience
+    );
+    assert_eq!(
+        error_kind(verifier.verify(&hmac_token(Algorithm::HS512, &claims()))),
+        ErrorKind::MissingRequiredClaim(String::from("aud"))
+    );
+}
+
+#[test]
+fn shared_secret_verifier_accepts_tokens_of_its_signer() {
+    let mut claims = SessionClaims::new(1, "alice");
+    let token = SessionSigner::from_secret(Algorithm::HS512, SHARED_SECRET.as_ref())
+        .sign(&mut claims)
+        .unwrap();
+
+    let verified = SessionVerifier::from_secret(Algorithm::HS512, SHARED_SECRET.as_ref())
+        .verify(&token)
+        .unwrap();
+    assert_eq!(verified.claims.jti(), claims.jti());
+
+    let other = SessionVerifier::from_secret(Algorithm::HS512, b"other-secret");
+    assert!(other.verify(&token).is_err());
+}
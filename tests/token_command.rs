@@ -0,0 +1,29 @@
+use time::Duration;
+use smo::token_command::parse_ttl;
+
+#[test]
+fn parses_ttls_with_units() {
+    assert_eq!(parse_ttl("3600"), Ok(Duration::seconds(3600)));
+    assert_eq!(parse_ttl("90s"), Ok(Duration::seconds(90)));
+    assert_eq!(parse_ttl("30m"), Ok(Duration::minutes(30)));
+    assert_eq!(parse_ttl("12h"), Ok(Duration::hours(12)));
+    assert_eq!(parse_ttl("365d"), Ok(Duration::days(365)));
+    assert_eq!(parse_ttl("2w"), Ok(Duration::weeks(2)));
+}
+
+#[test]
+fn refuses_malformed_ttls() {
+    assert!(parse_ttl("").is_err());
+    assert!(parse_ttl("d").is_err());
+    assert!(parse_ttl("-5s").is_err());
+    assert!(parse_ttl("5y").is_err());
+    assert!(parse_ttl("5 d").is_err());
+}
+
+#[test]
+fn refuses_ttls_out_of_range() {
+    // the value alone overflows, or the expiry falls past the last representable date.
+    assert!(parse_ttl("99999999999999999999").is_err());
+    assert!(parse_ttl("99999999999999w").is_err());
+    assert!(parse_ttl("999999w").is_err());
+}
@@ -0,0 +1,37 @@
+use std::net::Ipv4Addr;
+use smo::tunnel_config::TunnelConfig;
+
+fn subnet(netmask: Ipv4Addr) -> TunnelConfig {
+    TunnelConfig::new(Ipv4Addr::new(10, 8, 0, 1), netmask, 1400)
+}
+
+#[test]
+fn assignable_addresses_lie_inside_the_subnet() {
+    let tunnel_config = subnet(Ipv4Addr::new(255, 255, 255, 0));
+
+    assert!(tunnel_config.is_assignable(Ipv4Addr::new(10, 8, 0, 2)));
+    assert!(tunnel_config.is_assignable(Ipv4Addr::new(10, 8, 0, 254)));
+
+    // network, broadcast and the server's own address.
+    assert!(!tunnel_config.is_assignable(Ipv4Addr::new(10, 8, 0, 0)));
+    assert!(!tunnel_config.is_assignable(Ipv4Addr::new(10, 8, 0, 255)));
+    assert!(!tunnel_config.is_assignable(Ipv4Addr::new(10, 8, 0, 1)));
+
+    assert!(!tunnel_config.is_assignable(Ipv4Addr::new(10, 8, 1, 2)));
+    assert!(!tunnel_config.is_assignable(Ipv4Addr::new(192, 168, 0, 2)));
+}
+
+#[test]
+fn free_address_skips_used_ones() {
+    let tunnel_config = subnet(Ipv4Addr::new(255, 255, 255, 0));
+
+    assert_eq!(tunnel_config.free_address(&[]), Some(Ipv4Addr::new(10, 8, 0, 2)));
+
+    let used = [Ipv4Addr::new(10, 8, 0, 2).to_bits(), Ipv4Addr::new(10, 8, 0, 4).to_bits()];
+    assert_eq!(tunnel_config.free_address(&used), Some(Ipv4Addr::new(10, 8, 0, 3)));
+
+    // a /30 holds the server and a single client.
+    let tunnel_config = subnet(Ipv4Addr::new(255, 255, 255, 252));
+    assert_eq!(tunnel_config.free_address(&[]), Some(Ipv4Addr::new(10, 8, 0, 2)));
+    assert_eq!(tunnel_config.free_address(&[Ipv4Addr::new(10, 8, 0, 2).to_bits()]), None);
+}
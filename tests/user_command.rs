@@ -0,0 +1,77 @@
+use std::net::Ipv4Addr;
+use smo::command::UserCommand;
+use smo::tunnel_config::TunnelConfig;
+use smo::user::User;
+use smo::user_command::execute;
+use smo::user_store::UserStore;
+use smo::user_store_memory::MemoryUserStore;
+
+fn tunnel_config() -> TunnelConfig {
+    TunnelConfig::new(Ipv4Addr::new(10, 8, 0, 1), Ipv4Addr::new(255, 255, 255, 0), 1400)
+}
+
+fn add(username: &str, address: Option<Ipv4Addr>) -> UserCommand {
+    UserCommand::Add {
+        username: String::from(username),
+        address,
+    }
+}
+
+fn alice(address: Ipv4Addr) -> User {
+    User::new(1, "alice", address, true)
+}
+
+fn bob(address: Ipv4Addr) -> User {
+    User::new(2, "bob", address, true)
+}
+
+fn alice_store() -> MemoryUserStore {
+    MemoryUserStore::new(vec![alice(Ipv4Addr::new(10, 8, 0, 2))])
+}
+
+#[tokio::test]
+async fn add_picks_the_next_free_address() {
+    let user_store = alice_store();
+
+    execute(&user_store, &tunnel_config(), add("bob", None)).await.unwrap();
+
+    assert_eq!(user_store.list().await.unwrap(), vec![alice(Ipv4Addr::new(10, 8, 0, 2)), bob(Ipv4Addr::new(10, 8, 0, 3))]);
+}
+
+#[tokio::test]
+async fn add_refuses_unassignable_and_used_addresses() {
+    let user_store = alice_store();
+
+    for address in [
+        Ipv4Addr::new(10, 8, 0, 0),
+        Ipv4Addr::new(10, 8, 0, 1),
+        Ipv4Addr::new(10, 8, 0, 255),
+        Ipv4Addr::new(10, 9, 0, 2),
+        Ipv4Addr::new(10, 8, 0, 2),
+    ] {
+        assert!(execute(&user_store, &tunnel_config(), add("bob", Some(address))).await.is_err(), "{address} accepted");
+    }
+
+    assert_eq!(user_store.list().await.unwrap(), vec![alice(Ipv4Addr::new(10, 8, 0, 2))]);
+
+    execute(&user_store, &tunnel_config(), add("bob", Some(Ipv4Addr::new(10, 8, 0, 9)))).await.unwrap();
+    assert_eq!(user_store.list().await.unwrap(), vec![alice(Ipv4Addr::new(10, 8, 0, 2)), bob(Ipv4Addr::new(10, 8, 0, 9))]);
+}
+
+#[tokio::test]
+async fn set_address_refuses_addresses_of_other_users() {
+    let user_store = alice_store();
+    execute(&user_store, &tunnel_config(), add("bob", None)).await.unwrap();
+
+    let set_address = |id, address| UserCommand::SetAddress { id, address };
+
+    // keeping its own address is no conflict.
+    execute(&user_store, &tunnel_config(), set_address(1, Ipv4Addr::new(10, 8, 0, 2))).await.unwrap();
+
+    assert!(execute(&user_store, &tunnel_config(), set_address(1, Ipv4Addr::new(10, 8, 0, 3))).await.is_err());
+    assert!(execute(&user_store, &tunnel_config(), set_address(1, Ipv4Addr::new(10, 8, 0, 255))).await.is_err());
+    assert_eq!(user_store.list().await.unwrap(), vec![alice(Ipv4Addr::new(10, 8, 0, 2)), bob(Ipv4Addr::new(10, 8, 0, 3))]);
+
+    execute(&user_store, &tunnel_config(), set_address(1, Ipv4Addr::new(10, 8, 0, 7))).await.unwrap();
+    assert_eq!(user_store.list().await.unwrap(), vec![alice(Ipv4Addr::new(10, 8, 0, 7)), bob(Ipv4Addr::new(10, 8, 0, 3))]);
+}
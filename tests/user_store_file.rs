@@ -0,0 +1,38 @@
+mod common;
+
+use std::net::Ipv4Addr;
+use smo::user_store::{RevokedToken, UserStore};
+use smo::user_store_file::FileUserStore;
+use common::scratch;
+
+#[tokio::test]
+async fn revoked_tokens_are_read_from_the_deny_list() {
+    let dir = scratch("user-store-file", "revoked");
+    let path = dir.join("users.json");
+    let user_store = FileUserStore::new(path.to_str().unwrap());
+
+    assert!(user_store.revoked_tokens().await.unwrap().is_empty());
+
+    user_store.revoke("token-a", None).await.unwrap();
+    user_store.revoke("token-b", Some(1_900_000_000)).await.unwrap();
+    user_store.revoke("token-a", Some(1)).await.unwrap();
+
+    assert_eq!(user_store.revoked_tokens().await.unwrap(), vec![
+        RevokedToken::new("token-a", None),
+        RevokedToken::new("token-b", Some(1_900_000_000)),
+    ]);
+
+    // entries added by hand count as well, blank lines are skipped.
+    std::fs::write(dir.join("users.json.revoked"), "token-a\n\n  token-c  1700000000\ntoken-d soon\n").unwrap();
+    let revoked = vec![
+        RevokedToken::new("token-a", None),
+        RevokedToken::new("token-c", Some(1_700_000_000)),
+        RevokedToken::new("token-d", None),
+    ];
+    assert_eq!(user_store.revoked_tokens().await.unwrap(), revoked);
+
+    // the user document is left untouched.
+    user_store.add("alice", Ipv4Addr::new(10, 8, 0, 2)).await.unwrap();
+    assert_eq!(user_store.list().await.unwrap().len(), 1);
+    assert_eq!(user_store.revoked_tokens().await.unwrap(), revoked);
+}